@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_emulator::cartridge::{Cartridge, Rom};
+
+// Any input must either be rejected with a RomError or load into a
+// cartridge whose whole CPU and PPU address ranges can be read.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cartridge) = Rom::from_bytes(data).and_then(Cartridge::from_rom) {
+        for addr in 0x4020..=0xffff {
+            cartridge.cpu_peek(addr);
+        }
+        for addr in 0x0000..0x2000 {
+            cartridge.ppu_peek(addr);
+        }
+        for addr in 0x2000..0x3000 {
+            cartridge.nametable_peek(addr);
+        }
+    }
+});
@@ -0,0 +1,189 @@
+use crate::cartridge::Cartridge;
+use crate::ppu::Ppu;
+
+#[cfg(test)]
+mod tests;
+
+/// Everything the CPU is wired to. The CPU makes exactly one `read` or
+/// `write` per cycle, so a bus can use them to keep other chips in step.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, val: u8, addr: u16);
+
+    /// Reads without any of the side effects `read` may have, for debuggers
+    /// and traces.
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Called once per CPU cycle, before that cycle's bus access.
+    fn tick(&mut self) {}
+
+    /// Whether a device on the bus, such as a cartridge, is holding the IRQ
+    /// line. The CPU checks it along with `Cpu::set_irq_line`.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether a device on the bus, such as the PPU, is asserting NMI. The
+    /// CPU runs an NMI each time this goes from `false` to `true`.
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// Scanline and dot of the PPU, for buses that have one. Traces use it
+    /// to show where the PPU is.
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Takes the page a write asked the CPU to copy to OAM, such as one to
+    /// $4014 on the NES. The CPU makes the copy once the instruction doing
+    /// the write is done.
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// 64K of RAM and nothing else, for running the 6502 core on its own.
+pub struct FlatBus {
+    ram: Vec<u8>,
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus { ram: vec![0; 0x10000] }
+    }
+
+    /// Copies `data` into memory starting at `addr`, wrapping around $FFFF.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, &val) in data.iter().enumerate() {
+            self.ram[addr.wrapping_add(i as u16) as usize] = val;
+        }
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> FlatBus {
+        FlatBus::new()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, val: u8, addr: u16) {
+        self.ram[addr as usize] = val;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+/// The NES CPU's address space with a cartridge inserted.
+///
+/// The 2KB of internal RAM repeats four times up to $1FFF and the eight PPU
+/// registers repeat every eight bytes up to $3FFF. Reads from addresses
+/// nothing drives, including the write-only APU registers, return the open
+/// bus: the last value that went over the data bus. PRG ROM ignores writes.
+/// The PPU runs three dots per CPU cycle, and writes to $4014 have the CPU
+/// copy a page of memory to its OAM.
+pub struct NesBus {
+    ram: Vec<u8>,
+    ppu: Ppu,
+    cartridge: Cartridge,
+    open_bus: u8,
+    oam_dma: Option<u8>,
+}
+
+impl NesBus {
+    pub fn new(cartridge: Cartridge) -> NesBus {
+        NesBus {
+            ram: vec![0; 0x800],
+            ppu: Ppu::new(),
+            cartridge,
+            open_bus: 0,
+            oam_dma: None,
+        }
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.open_bus = match addr {
+            0x2000..=0x3fff => self.ppu.read_register(addr, &mut self.cartridge),
+            0x4020..=0xffff => self.cartridge.cpu_read(addr).unwrap_or(self.open_bus),
+            _ => self.peek(addr),
+        };
+        self.open_bus
+    }
+
+    fn write(&mut self, val: u8, addr: u16) {
+        self.open_bus = val;
+        match addr {
+            0..=0x1fff => self.ram[(addr & 0x07ff) as usize] = val,
+            0x2000..=0x3fff => {
+                self.ppu.write_register(val, addr, &mut self.cartridge);
+                self.cartridge.ppu_register_write(val, addr);
+            }
+            0x4014 => self.oam_dma = Some(val),
+            0x4020..=0xffff => self.cartridge.cpu_write(val, addr),
+            _ => {}
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0..=0x1fff => self.ram[(addr & 0x07ff) as usize],
+            0x2000..=0x3fff => self.ppu.peek_register(addr),
+            // There is no APU or controller yet, so the bits they drive read
+            // as 0: the APU status leaves bit 5 undriven and the controller
+            // ports the top three bits.
+            0x4015 => self.open_bus & 0b00100000,
+            0x4016..=0x4017 => self.open_bus & 0b11100000,
+            0x4020..=0xffff => self.cartridge.cpu_peek(addr).unwrap_or(self.open_bus),
+            _ => self.open_bus,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cartridge.cpu_tick();
+        for _ in 0..3 {
+            self.ppu.tick(&mut self.cartridge);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.cartridge.irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.ppu.nmi()
+    }
+
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        Some((self.ppu.scanline(), self.ppu.dot()))
+    }
+
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+}
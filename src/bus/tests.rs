@@ -0,0 +1,112 @@
+use super::*;
+use crate::cartridge::Rom;
+
+/// A bus with a 16KB NROM cartridge whose PRG ROM holds the low byte of
+/// each address.
+fn nes_bus() -> NesBus {
+    let mut image = vec![0; 16 + 0x4000];
+    image[..4].copy_from_slice(b"NES\x1a");
+    image[4] = 1;
+    for (i, byte) in image[16..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    NesBus::new(Cartridge::from_rom(Rom::from_bytes(&image).unwrap()).unwrap())
+}
+
+#[test]
+fn ram_is_mirrored_four_times() {
+    let mut bus = nes_bus();
+    bus.write(0x42, 0x0123);
+    for mirror in [0x0123, 0x0923, 0x1123, 0x1923] {
+        assert_eq!(bus.read(mirror), 0x42, "{mirror:04x}");
+    }
+
+    bus.write(0x99, 0x1fff);
+    assert_eq!(bus.read(0x07ff), 0x99);
+}
+
+#[test]
+fn ppu_registers_are_mirrored_every_eight_bytes() {
+    let mut bus = nes_bus();
+    // PPUADDR and PPUDATA through mirrors near the top of the range.
+    bus.write(0x3f, 0x3ffe);
+    bus.write(0x01, 0x2ffe);
+    bus.write(0x2a, 0x3fff);
+    bus.write(0x3f, 0x200e);
+    bus.write(0x01, 0x3006);
+    assert_eq!(bus.read(0x2007), 0x2a);
+
+    // PPUSTATUS reads back the same from every mirror.
+    for base in (0x2000..0x4000).step_by(8) {
+        assert_eq!(bus.peek(base + 2), bus.peek(0x2002), "{:04x}", base + 2);
+    }
+}
+
+#[test]
+fn ppu_runs_three_dots_per_cpu_cycle() {
+    let mut bus = nes_bus();
+    for _ in 0..10 {
+        bus.tick();
+    }
+    assert_eq!((bus.ppu().scanline(), bus.ppu().dot()), (0, 30));
+}
+
+#[test]
+fn prg_rom_ignores_writes() {
+    let mut bus = nes_bus();
+    bus.write(0xff, 0x8010);
+    assert_eq!(bus.read(0x8010), 0x10);
+
+    // NROM-128 repeats its 16KB at $C000.
+    bus.write(0xff, 0xc010);
+    assert_eq!(bus.read(0xc010), 0x10);
+}
+
+#[test]
+fn prg_ram_is_not_mirrored() {
+    let mut bus = nes_bus();
+    bus.write(0x12, 0x6000);
+    bus.write(0x34, 0x7fff);
+    assert_eq!(bus.read(0x6000), 0x12);
+    assert_eq!(bus.read(0x7fff), 0x34);
+}
+
+#[test]
+fn unmapped_reads_return_the_open_bus() {
+    let mut bus = nes_bus();
+    bus.read(0x8042);
+    assert_eq!(bus.read(0x5000), 0x42);
+    assert_eq!(bus.peek(0x4020), 0x42);
+
+    // Writes drive the data bus too, and write-only APU registers read back
+    // whatever was last on it.
+    bus.write(0xa5, 0x4000);
+    assert_eq!(bus.read(0x4000), 0xa5);
+    bus.write(0x3c, 0x0000);
+    assert_eq!(bus.read(0x4013), 0x3c);
+    assert_eq!(bus.read(0x401f), 0x3c);
+}
+
+#[test]
+fn apu_status_and_controller_reads_mix_in_the_open_bus() {
+    let mut bus = nes_bus();
+    bus.write(0xff, 0x4015);
+    bus.write(0xff, 0x4016);
+    assert_eq!(bus.read(0x4015), 0x20);
+    assert_eq!(bus.read(0x4016), 0x20);
+    assert_eq!(bus.peek(0x4017), 0x20);
+
+    bus.read(0x80ff);
+    assert_eq!(bus.peek(0x4015), 0x20);
+    assert_eq!(bus.peek(0x4016), 0xe0);
+    assert_eq!(bus.read(0x4017), 0xe0);
+}
+
+#[test]
+fn writes_to_4014_request_oam_dma() {
+    let mut bus = nes_bus();
+    assert_eq!(bus.take_oam_dma(), None);
+    bus.write(0x02, 0x4014);
+    assert_eq!(bus.take_oam_dma(), Some(0x02));
+    assert_eq!(bus.take_oam_dma(), None);
+}
@@ -0,0 +1,480 @@
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use crate::mapper::{self, Mapper};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderFormat {
+    INes,
+    Nes2,
+}
+
+/// How the cartridge wires the PPU's two nametables.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mirroring {
+    /// $2000 and $2400 share a table, as do $2800 and $2C00, for vertical
+    /// scrolling.
+    Horizontal,
+    /// $2000 and $2800 share a table, as do $2400 and $2C00, for horizontal
+    /// scrolling.
+    Vertical,
+    /// The cartridge has its own VRAM for all four nametables.
+    FourScreen,
+    /// All four nametables show the first table.
+    SingleScreenLower,
+    /// All four nametables show the second table.
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    /// The 1KB page of nametable memory that `addr` in $2000-$2FFF selects.
+    /// The console has pages 0 and 1; four-screen boards add pages 2 and 3.
+    pub fn nametable_page(self, addr: u16) -> usize {
+        let table = ((addr >> 10) & 0b11) as usize;
+        match self {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 1,
+            Mirroring::FourScreen => table,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+    /// Runs on both NTSC and PAL consoles.
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// One of the NES 2.0 extended console types, e.g. 3 for a Famiclone
+    /// with decimal mode.
+    Extended(u8),
+}
+
+/// Everything the 16-byte iNES or NES 2.0 header says about a cartridge.
+/// Sizes are in bytes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    /// Whether the cartridge keeps its PRG-RAM alive with a battery.
+    pub battery: bool,
+    /// Whether a 512-byte trainer sits between the header and PRG ROM.
+    pub trainer: bool,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    /// Battery-backed PRG-RAM.
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    /// Battery-backed CHR-RAM.
+    pub chr_nvram_size: usize,
+    pub tv_system: TvSystem,
+    pub console_type: ConsoleType,
+}
+
+pub const HEADER_LEN: usize = 16;
+const MAGIC: &[u8; 4] = b"NES\x1a";
+
+const TRAINER_LEN: usize = 512;
+const PRG_ROM_UNIT: usize = 0x4000;
+const CHR_ROM_UNIT: usize = 0x2000;
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The file is shorter than the 16-byte header.
+    TruncatedHeader,
+    /// The file does not start with "NES\x1A".
+    BadMagic,
+    /// A NES 2.0 header gives a ROM size that cannot be addressed.
+    OversizedRom,
+    NoPrgRom,
+    TruncatedPrg { expected: usize, actual: usize },
+    TruncatedChr { expected: usize, actual: usize },
+    UnsupportedMapper { mapper: u16 },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(e) => write!(f, "cannot read ROM: {e}"),
+            RomError::TruncatedHeader => write!(f, "file is too short for an iNES header"),
+            RomError::BadMagic => write!(f, "not an iNES file"),
+            RomError::OversizedRom => write!(f, "NES 2.0 header gives an impossibly large ROM size"),
+            RomError::NoPrgRom => write!(f, "ROM has no PRG ROM"),
+            RomError::TruncatedPrg { expected, actual } => {
+                write!(f, "PRG ROM truncated: expected {expected} bytes, found {actual}")
+            }
+            RomError::TruncatedChr { expected, actual } => {
+                write!(f, "CHR ROM truncated: expected {expected} bytes, found {actual}")
+            }
+            RomError::UnsupportedMapper { mapper } => write!(f, "mapper {mapper} is not supported"),
+        }
+    }
+}
+
+impl std::error::Error for RomError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RomError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> RomError {
+        RomError::Io(e)
+    }
+}
+
+impl RomHeader {
+    /// Parses the header at the start of `data`.
+    pub fn parse(data: &[u8]) -> Result<RomHeader, RomError> {
+        let header = data.get(..HEADER_LEN).ok_or(RomError::TruncatedHeader)?;
+        if &header[..4] != MAGIC {
+            return Err(RomError::BadMagic);
+        }
+
+        let flags6 = header[6];
+        let flags7 = header[7];
+        let mirroring = if flags6 & 0b00001000 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0b00000001 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0b00000010 != 0;
+        let trainer = flags6 & 0b00000100 != 0;
+
+        if flags7 & 0b00001100 == 0b00001000 {
+            return RomHeader::parse_nes2(header, mirroring, battery, trainer).ok_or(RomError::OversizedRom);
+        }
+
+        // Old dumping tools left text like "DiskDude!" in bytes 7-15, so
+        // byte 7 is only trusted when the unused bytes are clear.
+        let flags7 = if header[12..].iter().all(|&b| b == 0) { flags7 } else { 0 };
+        let console_type = if flags7 & 0b00000001 != 0 {
+            ConsoleType::VsSystem
+        } else if flags7 & 0b00000010 != 0 {
+            ConsoleType::Playchoice10
+        } else {
+            ConsoleType::Nes
+        };
+
+        let chr_rom_size = header[5] as usize * CHR_ROM_UNIT;
+        // A PRG-RAM size of 0 means 8KB for compatibility.
+        let prg_ram_size = header[8].max(1) as usize * 0x2000;
+
+        Ok(RomHeader {
+            format: HeaderFormat::INes,
+            mapper: ((flags7 & 0xf0) | (flags6 >> 4)) as u16,
+            submapper: 0,
+            mirroring,
+            battery,
+            trainer,
+            prg_rom_size: header[4] as usize * PRG_ROM_UNIT,
+            chr_rom_size,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+            chr_nvram_size: 0,
+            tv_system: if header[9] & 0b00000001 != 0 { TvSystem::Pal } else { TvSystem::Ntsc },
+            console_type,
+        })
+    }
+
+    fn parse_nes2(header: &[u8], mirroring: Mirroring, battery: bool, trainer: bool) -> Option<RomHeader> {
+        let console_type = match header[7] & 0b00000011 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(header[13] & 0x0f),
+        };
+        let tv_system = match header[12] & 0b00000011 {
+            0 => TvSystem::Ntsc,
+            1 => TvSystem::Pal,
+            2 => TvSystem::MultiRegion,
+            _ => TvSystem::Dendy,
+        };
+
+        Some(RomHeader {
+            format: HeaderFormat::Nes2,
+            mapper: ((header[8] & 0x0f) as u16) << 8 | (header[7] & 0xf0) as u16 | (header[6] >> 4) as u16,
+            submapper: header[8] >> 4,
+            mirroring,
+            battery,
+            trainer,
+            prg_rom_size: nes2_rom_size(header[4], header[9] & 0x0f, PRG_ROM_UNIT)?,
+            chr_rom_size: nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_UNIT)?,
+            prg_ram_size: nes2_ram_size(header[10] & 0x0f),
+            prg_nvram_size: nes2_ram_size(header[10] >> 4),
+            chr_ram_size: nes2_ram_size(header[11] & 0x0f),
+            chr_nvram_size: nes2_ram_size(header[11] >> 4),
+            tv_system,
+            console_type,
+        })
+    }
+}
+
+/// A NES 2.0 ROM size: either a 12-bit count of `unit`s, or, when the high
+/// nibble is $F, 2^E * (MM * 2 + 1) bytes with the low byte laid out as
+/// EEEEEEMM.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0f {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b00000011) as usize * 2 + 1;
+        1usize.checked_shl(exponent)?.checked_mul(multiplier)
+    } else {
+        Some(((msb as usize) << 8 | lsb as usize) * unit)
+    }
+}
+
+/// A NES 2.0 RAM size, stored as a shift count of 64 bytes.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// The contents of a .nes file.
+pub struct Rom {
+    pub(crate) header: RomHeader,
+    /// Empty without a trainer.
+    pub(crate) trainer: Vec<u8>,
+    pub(crate) prg_rom: Vec<u8>,
+    pub(crate) chr_rom: Vec<u8>,
+}
+
+impl Rom {
+    /// Parses an iNES or NES 2.0 image. Never panics, whatever `data` is.
+    pub fn from_bytes(data: &[u8]) -> Result<Rom, RomError> {
+        let header = RomHeader::parse(data)?;
+        if header.prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        // The layout is header, trainer, PRG ROM, CHR ROM.
+        let prg_start = HEADER_LEN + if header.trainer { TRAINER_LEN } else { 0 };
+        let prg_end = prg_start.checked_add(header.prg_rom_size).ok_or(RomError::OversizedRom)?;
+        let prg_rom = data.get(prg_start..prg_end).ok_or(RomError::TruncatedPrg {
+            expected: header.prg_rom_size,
+            actual: data.len().saturating_sub(prg_start),
+        })?;
+
+        let chr_end = prg_end.checked_add(header.chr_rom_size).ok_or(RomError::OversizedRom)?;
+        let chr_rom = data.get(prg_end..chr_end).ok_or(RomError::TruncatedChr {
+            expected: header.chr_rom_size,
+            actual: data.len() - prg_end,
+        })?;
+
+        Ok(Rom {
+            header,
+            trainer: data[HEADER_LEN..prg_start].to_vec(),
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+        })
+    }
+
+    pub fn header(&self) -> &RomHeader {
+        &self.header
+    }
+
+    /// The 512 bytes copier hardware loaded into $7000-$71FF, if the image
+    /// has them.
+    pub fn trainer(&self) -> Option<&[u8]> {
+        (!self.trainer.is_empty()).then_some(&*self.trainer)
+    }
+
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    /// The CHR ROM, empty for boards with CHR-RAM instead.
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+}
+
+/// A cartridge: the header it was loaded with and the mapper that decodes
+/// its CPU side ($4020-$FFFF) and pattern tables and nametables ($0000-$2FFF
+/// on the PPU side).
+pub struct Cartridge {
+    header: RomHeader,
+    mapper: Box<dyn Mapper>,
+    /// Where battery-backed PRG-RAM is kept between sessions.
+    save_path: Option<PathBuf>,
+}
+
+impl Cartridge {
+    /// Reads and parses the .nes file at `path`. If the cartridge has a
+    /// battery, its PRG-RAM is restored from the .sav file next to it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cartridge, RomError> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let mut cartridge = Cartridge::from_rom(Rom::from_bytes(&data)?)?;
+        if cartridge.header.battery {
+            cartridge.attach_save(path.with_extension("sav"))?;
+        }
+        Ok(cartridge)
+    }
+
+    /// Builds the board `rom` was dumped from, if it is one we support.
+    pub fn from_rom(rom: Rom) -> Result<Cartridge, RomError> {
+        let header = rom.header.clone();
+        Ok(Cartridge {
+            header,
+            mapper: mapper::from_rom(rom)?,
+            save_path: None,
+        })
+    }
+
+    /// Keeps PRG-RAM in the file at `path` from now on, first loading it
+    /// from there if the file exists.
+    pub fn attach_save<P: Into<PathBuf>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.into();
+        match fs::read(&path) {
+            Ok(data) => self.mapper.memory_mut().load_prg_ram(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.save_path = Some(path);
+        Ok(())
+    }
+
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    /// Writes PRG-RAM to the save file if it changed since the last save.
+    ///
+    /// The data goes to a temporary file that is then renamed over the
+    /// save, so a crash part way through leaves the previous save intact.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.save_path else {
+            return Ok(());
+        };
+        if !self.mapper.memory().prg_ram_dirty() {
+            return Ok(());
+        }
+
+        let mut temp_path = OsString::from(path);
+        temp_path.push(".tmp");
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(self.mapper.memory().prg_ram())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        self.mapper.memory_mut().mark_prg_ram_saved();
+        Ok(())
+    }
+
+    pub fn header(&self) -> &RomHeader {
+        &self.header
+    }
+
+    /// Reads from $4020-$FFFF, or `None` if nothing drives the bus.
+    pub fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4020..=0x5fff => self.mapper.expansion_read(addr),
+            _ => self.mapper.cpu_read(addr),
+        }
+    }
+
+    pub fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4020..=0x5fff => self.mapper.expansion_peek(addr),
+            _ => self.mapper.cpu_peek(addr),
+        }
+    }
+
+    pub fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x4020..=0x5fff => self.mapper.expansion_write(val, addr),
+            _ => self.mapper.cpu_write(val, addr),
+        }
+    }
+
+    /// Shows the board a CPU write to the PPU registers.
+    pub fn ppu_register_write(&mut self, val: u8, addr: u16) {
+        self.mapper.ppu_register_write(val, 0x2000 | (addr & 0x0007));
+    }
+
+    /// Reads the pattern tables at $0000-$1FFF of the PPU address space.
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.ppu_read(addr & 0x1fff)
+    }
+
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.mapper.ppu_peek(addr & 0x1fff)
+    }
+
+    /// Writes the pattern tables, if they are CHR-RAM.
+    pub fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.mapper.ppu_write(val, addr & 0x1fff);
+    }
+
+    /// Reads a nametable byte the board provides itself, or `None` if it
+    /// comes from nametable RAM at `nametable_page`.
+    pub fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+        self.mapper.nametable_read(0x2000 | (addr & 0x0fff))
+    }
+
+    pub fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        self.mapper.nametable_peek(0x2000 | (addr & 0x0fff))
+    }
+
+    /// Returns whether the board took the write.
+    pub fn nametable_write(&mut self, val: u8, addr: u16) -> bool {
+        self.mapper.nametable_write(val, 0x2000 | (addr & 0x0fff))
+    }
+
+    /// The 1KB page of nametable RAM that `addr` selects.
+    pub fn nametable_page(&self, addr: u16) -> usize {
+        self.mapper.nametable_page(0x2000 | (addr & 0x0fff))
+    }
+
+    /// Tells the board about an address the PPU put on its bus.
+    pub fn ppu_address(&mut self, addr: u16) {
+        self.mapper.ppu_address(addr);
+    }
+
+    /// The nametable mirroring the board currently selects.
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    /// Whether the board is holding the CPU's IRQ line.
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    /// Called once per CPU cycle.
+    pub fn cpu_tick(&mut self) {
+        self.mapper.cpu_tick();
+    }
+
+    /// The board's expansion audio, from 0.0 to 1.0.
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+}
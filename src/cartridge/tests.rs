@@ -0,0 +1,293 @@
+use super::*;
+
+fn header(bytes: [u8; 12]) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[..4].copy_from_slice(MAGIC);
+    header[4..].copy_from_slice(&bytes);
+    header
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut data = header([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data[3] = 0x1b;
+    assert!(matches!(RomHeader::parse(&data), Err(RomError::BadMagic)));
+    assert!(matches!(RomHeader::parse(&data[..15]), Err(RomError::TruncatedHeader)));
+}
+
+#[test]
+fn parses_nestest_header() {
+    let data = std::fs::read("nestest.nes").unwrap();
+    let header = RomHeader::parse(&data).unwrap();
+    assert_eq!(header.format, HeaderFormat::INes);
+    assert_eq!(header.mapper, 0);
+    assert_eq!(header.prg_rom_size, 0x4000);
+    assert_eq!(header.chr_rom_size, 0x2000);
+    assert_eq!(header.chr_ram_size, 0);
+    assert!(!header.trainer);
+}
+
+#[test]
+fn parses_ines_flags() {
+    // MMC1 with battery-backed RAM, vertical mirroring and a trainer.
+    let h = RomHeader::parse(&header([8, 0, 0x17, 0x00, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert_eq!(h.format, HeaderFormat::INes);
+    assert_eq!(h.mapper, 1);
+    assert_eq!(h.mirroring, Mirroring::Vertical);
+    assert!(h.battery);
+    assert!(h.trainer);
+    assert_eq!(h.prg_rom_size, 8 * 0x4000);
+    assert_eq!(h.chr_rom_size, 0);
+    assert_eq!(h.chr_ram_size, 0x2000);
+    assert_eq!(h.prg_ram_size, 0);
+    assert_eq!(h.prg_nvram_size, 0x2000);
+    assert_eq!(h.tv_system, TvSystem::Pal);
+
+    // MMC3 (both nibbles), four-screen, VS System, 16KB of PRG-RAM.
+    let h = RomHeader::parse(&header([2, 2, 0x48, 0x01, 2, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert_eq!(h.mapper, 4);
+    assert_eq!(h.mirroring, Mirroring::FourScreen);
+    assert_eq!(h.console_type, ConsoleType::VsSystem);
+    assert_eq!(h.prg_ram_size, 0x4000);
+
+    let h = RomHeader::parse(&header([2, 2, 0x10, 0x40, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert_eq!(h.mapper, 0x41);
+    assert_eq!(h.mirroring, Mirroring::Horizontal);
+}
+
+#[test]
+fn ignores_byte_7_of_dirty_ines_headers() {
+    let mut data = header([2, 1, 0x10, b'D', b'i', b's', b'k', b'D', b'u', b'd', b'e', b'!']);
+    assert_eq!(RomHeader::parse(&data).unwrap().mapper, 1);
+    data[7] = 0x42;
+    assert_eq!(RomHeader::parse(&data).unwrap().console_type, ConsoleType::Nes);
+}
+
+#[test]
+fn parses_nes2_extensions() {
+    let h = RomHeader::parse(&header([0x02, 0x01, 0x51, 0xfb, 0x21, 0x12, 0x97, 0x09, 0x03, 0x0c, 0, 0])).unwrap();
+    assert_eq!(h.format, HeaderFormat::Nes2);
+    assert_eq!(h.mapper, 0x1f5);
+    assert_eq!(h.submapper, 2);
+    assert_eq!(h.prg_rom_size, 0x202 * 0x4000);
+    assert_eq!(h.chr_rom_size, 0x101 * 0x2000);
+    assert_eq!(h.prg_ram_size, 64 << 7);
+    assert_eq!(h.prg_nvram_size, 64 << 9);
+    assert_eq!(h.chr_ram_size, 64 << 9);
+    assert_eq!(h.chr_nvram_size, 0);
+    assert_eq!(h.tv_system, TvSystem::Dendy);
+    assert_eq!(h.console_type, ConsoleType::Extended(0x0c));
+}
+
+#[test]
+fn parses_nes2_exponent_multiplier_sizes() {
+    // 2^10 * 3 bytes of PRG ROM and 2^4 * 1 bytes of CHR ROM.
+    let h = RomHeader::parse(&header([0b00101001, 0b00010000, 0, 0x08, 0, 0xff, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert_eq!(h.prg_rom_size, 3 << 10);
+    assert_eq!(h.chr_rom_size, 16);
+
+    // 2^63 * 7 does not fit.
+    let data = header([0xff, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0]);
+    assert!(matches!(RomHeader::parse(&data), Err(RomError::OversizedRom)));
+}
+
+#[test]
+fn reports_truncated_and_unsupported_roms() {
+    let mut data = header([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).to_vec();
+    data.resize(16 + 0x4000, 0);
+    assert!(matches!(
+        Rom::from_bytes(&data),
+        Err(RomError::TruncatedPrg { expected: 0x8000, actual: 0x4000 })
+    ));
+
+    data.resize(16 + 0x8000 + 0x1000, 0);
+    assert!(matches!(
+        Rom::from_bytes(&data),
+        Err(RomError::TruncatedChr { expected: 0x2000, actual: 0x1000 })
+    ));
+
+    data.resize(16 + 0x8000 + 0x2000, 0);
+    assert!(Cartridge::from_rom(Rom::from_bytes(&data).unwrap()).is_ok());
+
+    data[6] = 0xf0;
+    let rom = Rom::from_bytes(&data).unwrap();
+    assert!(matches!(Cartridge::from_rom(rom), Err(RomError::UnsupportedMapper { mapper: 15 })));
+
+    data[4] = 0;
+    assert!(matches!(Rom::from_bytes(&data), Err(RomError::NoPrgRom)));
+
+    assert!(matches!(Cartridge::load("no-such-rom.nes"), Err(RomError::Io(_))));
+}
+
+/// A cheap stand-in for the fuzz target in fuzz/: loading mangled copies of
+/// nestest.nes must fail cleanly or produce a cartridge the CPU can read
+/// from, never panic.
+#[test]
+fn loading_garbage_never_panics() {
+    let original = std::fs::read("nestest.nes").unwrap();
+    let mut seed = 0x2545f491u32;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+
+    for len in (0..64).chain((64..original.len()).step_by(997)) {
+        let _ = Rom::from_bytes(&original[..len]);
+    }
+    for _ in 0..2000 {
+        let mut data = original[..(random() as usize % (original.len() + 1))].to_vec();
+        for _ in 0..random() % 8 {
+            if !data.is_empty() {
+                let i = random() as usize % data.len().min(16);
+                data[i] = random() as u8;
+            }
+        }
+        if let Ok(cartridge) = Rom::from_bytes(&data).and_then(Cartridge::from_rom) {
+            for addr in 0x4020..=0xffff {
+                cartridge.cpu_peek(addr);
+            }
+            for addr in 0x0000..0x2000 {
+                cartridge.ppu_peek(addr);
+            }
+            for addr in 0x2000..0x3000 {
+                cartridge.nametable_peek(addr);
+            }
+        }
+    }
+}
+
+/// An NROM image with a 16KB PRG ROM filled with $EA, `chr_banks` of CHR
+/// ROM filled with $C0 and, if `trainer` is set, a trainer of $7E.
+fn nrom_image(chr_banks: u8, trainer: bool) -> Vec<u8> {
+    let mut data = header([1, chr_banks, if trainer { 0x04 } else { 0 }, 0, 0, 0, 0, 0, 0, 0, 0, 0]).to_vec();
+    if trainer {
+        data.extend([0x7e; 512]);
+    }
+    data.extend([0xea; 0x4000]);
+    data.extend(vec![0xc0; chr_banks as usize * 0x2000]);
+    data
+}
+
+#[test]
+fn loads_chr_rom_after_prg_and_trainer() {
+    for trainer in [false, true] {
+        let rom = Rom::from_bytes(&nrom_image(1, trainer)).unwrap();
+        assert!(rom.prg_rom().iter().all(|&b| b == 0xea));
+        assert_eq!(rom.chr_rom().len(), 0x2000);
+        assert!(rom.chr_rom().iter().all(|&b| b == 0xc0));
+        assert_eq!(rom.trainer().map(<[u8]>::len), trainer.then_some(512));
+    }
+
+    let mut data = nrom_image(1, true);
+    data.pop();
+    assert!(matches!(
+        Rom::from_bytes(&data),
+        Err(RomError::TruncatedChr { expected: 0x2000, actual: 0x1fff })
+    ));
+}
+
+#[test]
+fn chr_rom_is_read_only() {
+    let mut cartridge = Cartridge::from_rom(Rom::from_bytes(&nrom_image(1, false)).unwrap()).unwrap();
+    cartridge.ppu_write(0x12, 0x0000);
+    cartridge.ppu_write(0x12, 0x1fff);
+    assert_eq!(cartridge.ppu_read(0x0000), 0xc0);
+    assert_eq!(cartridge.ppu_read(0x1fff), 0xc0);
+}
+
+#[test]
+fn boards_without_chr_rom_get_chr_ram() {
+    let mut cartridge = Cartridge::from_rom(Rom::from_bytes(&nrom_image(0, false)).unwrap()).unwrap();
+    assert_eq!(cartridge.ppu_read(0x1234), 0);
+    cartridge.ppu_write(0x12, 0x0000);
+    cartridge.ppu_write(0x34, 0x1fff);
+    assert_eq!(cartridge.ppu_read(0x0000), 0x12);
+    assert_eq!(cartridge.ppu_read(0x1fff), 0x34);
+
+    // A NES 2.0 header that declares no CHR-RAM still gets 8KB.
+    let mut data = nrom_image(0, false);
+    data[7] = 0x08;
+    let mut cartridge = Cartridge::from_rom(Rom::from_bytes(&data).unwrap()).unwrap();
+    cartridge.ppu_write(0x56, 0x1000);
+    assert_eq!(cartridge.ppu_read(0x1000), 0x56);
+}
+
+#[test]
+fn mirroring_picks_nametable_pages() {
+    let pages = |mirroring: Mirroring| [0x2000, 0x2400, 0x2800, 0x2fff].map(|addr| mirroring.nametable_page(addr));
+    assert_eq!(pages(Mirroring::Horizontal), [0, 0, 1, 1]);
+    assert_eq!(pages(Mirroring::Vertical), [0, 1, 0, 1]);
+    assert_eq!(pages(Mirroring::FourScreen), [0, 1, 2, 3]);
+    assert_eq!(pages(Mirroring::SingleScreenLower), [0, 0, 0, 0]);
+    assert_eq!(pages(Mirroring::SingleScreenUpper), [1, 1, 1, 1]);
+}
+
+#[test]
+fn expansion_area_is_open_bus_without_a_board_device() {
+    let mut cartridge = Cartridge::from_rom(Rom::from_bytes(&nrom_image(1, false)).unwrap()).unwrap();
+    assert_eq!(cartridge.cpu_read(0x4020), None);
+    assert_eq!(cartridge.cpu_read(0x5fff), None);
+    cartridge.cpu_write(0x12, 0x5000);
+    assert_eq!(cartridge.cpu_read(0x6000), Some(0));
+    assert_eq!(cartridge.nametable_read(0x2000), None);
+}
+
+/// A directory of its own under the system's temporary directory.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nes-emulator-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn battery_ram_survives_reloading() {
+    let dir = temp_dir("battery");
+    let rom_path = dir.join("game.nes");
+    let mut data = nrom_image(1, false);
+    data[6] |= 0b00000010;
+    fs::write(&rom_path, data).unwrap();
+
+    let mut cartridge = Cartridge::load(&rom_path).unwrap();
+    assert_eq!(cartridge.save_path(), Some(&*dir.join("game.sav")));
+    // Nothing is written until the RAM is.
+    cartridge.save().unwrap();
+    assert!(!dir.join("game.sav").exists());
+
+    cartridge.cpu_write(0x42, 0x6000);
+    cartridge.cpu_write(0x43, 0x7fff);
+    cartridge.save().unwrap();
+    assert_eq!(fs::read(dir.join("game.sav")).unwrap().len(), 0x2000);
+    assert!(!dir.join("game.sav.tmp").exists());
+
+    let mut cartridge = Cartridge::load(&rom_path).unwrap();
+    assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+    assert_eq!(cartridge.cpu_read(0x7fff), Some(0x43));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cartridges_without_a_battery_are_not_saved() {
+    let dir = temp_dir("no-battery");
+    let rom_path = dir.join("game.nes");
+    fs::write(&rom_path, nrom_image(1, false)).unwrap();
+
+    let mut cartridge = Cartridge::load(&rom_path).unwrap();
+    assert_eq!(cartridge.save_path(), None);
+    cartridge.cpu_write(0x42, 0x6000);
+    cartridge.save().unwrap();
+    assert!(!dir.join("game.sav").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn trainer_is_copied_to_7000() {
+    let mut cartridge = Cartridge::from_rom(Rom::from_bytes(&nrom_image(1, true)).unwrap()).unwrap();
+    assert_eq!(cartridge.cpu_read(0x6fff), Some(0));
+    assert_eq!(cartridge.cpu_read(0x7000), Some(0x7e));
+    assert_eq!(cartridge.cpu_read(0x71ff), Some(0x7e));
+    assert_eq!(cartridge.cpu_read(0x7200), Some(0));
+    assert_eq!(cartridge.cpu_read(0x8000), Some(0xea));
+}
@@ -1,1225 +1,843 @@
-use std::{num::Wrapping};
+use std::{fmt, num::Wrapping};
 
-pub struct Rom {
-    prg_rom_size: u16,
-    chr_rom_size: u16,
-    rom_data: Vec<u8>,
+use crate::bus::{Bus, NesBus};
+use crate::cartridge::Cartridge;
+
+mod opcodes;
+mod status;
+#[cfg(test)]
+mod tests;
+
+pub use opcodes::{AddressingMode, Opcode, OPCODES};
+pub use status::{Flag, Status};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
 }
 
-impl Rom {
-    pub fn new(data: Vec<u8>) -> Rom {
-        let header = &data[..16];
-        let prg_rom_size = (header[4] as u16) * 16384;
-        let chr_rom_size = (header[5] as u16) * 8192;
-        
-        //println!("Length: {:x?}", data.len());
-        let mut prg_rom: Vec<u8> = Vec::new();
-        if prg_rom_size <= 0x4000 {
-            let mut prg_rom_lower = Vec::from(&data[16..(prg_rom_size as usize + 16)]);
-            let mut prg_rom_upper = Vec::from(&data[16..(prg_rom_size as usize + 16)]);
-
-            prg_rom.append(&mut prg_rom_lower);
-            prg_rom.append(&mut prg_rom_upper);
-        } else {
-            prg_rom = Vec::from(&data[16..(prg_rom_size as usize + 16)]);
-        }
-        
-        Rom {
-            prg_rom_size: prg_rom_size, 
-            chr_rom_size: chr_rom_size, 
-            rom_data: prg_rom
+/// A single CPU bus cycle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BusAccess {
+    pub kind: AccessKind,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// The chip being emulated. They share the NMOS instruction set and differ
+/// in decimal mode handling.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CpuVariant {
+    /// The NES CPU, whose ALU ignores the D flag.
+    Ricoh2A03,
+    /// The original 6502. In decimal mode ADC and SBC work in BCD, with N, V
+    /// and Z left as the binary ALU computed them.
+    Nmos6502,
+    /// The CMOS 65C02's decimal mode: N and Z are valid in BCD, which costs
+    /// an extra cycle, and interrupts clear D. Its extended instruction set
+    /// is not emulated.
+    Cmos65C02,
+}
+
+/// How to execute ANE, LXA, SHA, SHX, SHY and TAS, whose results vary
+/// between chips.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnstableOpcodes {
+    /// Use the behavior most chips show. ANE and LXA OR the accumulator with
+    /// `magic` before masking it.
+    Emulate { magic: u8 },
+    /// Stop with `CpuError::UnstableOpcode` without executing anything.
+    Reject,
+}
+
+/// What the JAM (KIL) opcodes do.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JamBehavior {
+    /// Lock up like the real chip: every following step fails with
+    /// `CpuError::Jammed` until the CPU is reset.
+    Halt,
+    /// Treat them as one-byte NOPs.
+    Nop,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CpuError {
+    /// A JAM opcode at `pc` locked up the CPU.
+    Jammed { pc: u16, opcode: u8 },
+    /// The unstable opcode at `pc` was rejected; the CPU is left on it.
+    UnstableOpcode { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::Jammed { pc, opcode } => {
+                write!(f, "CPU jammed by opcode {opcode:02x} at {pc:04x}")
+            }
+            CpuError::UnstableOpcode { pc, opcode } => {
+                let mnemonic = OPCODES[*opcode as usize].mnemonic;
+                write!(f, "unstable opcode {opcode:02x} ({mnemonic}) at {pc:04x}")
+            }
         }
     }
 }
 
-struct Memory {
-    ram: Vec<u8>,
-    io_registers: Vec<u8>,
-    sram: Vec<u8>,
-    expansion_rom: Vec<u8>,
-    prg_rom: Vec<u8>
+impl std::error::Error for CpuError {}
+
+pub const NMI_VECTOR: u16 = 0xfffa;
+pub const RESET_VECTOR: u16 = 0xfffc;
+pub const IRQ_VECTOR: u16 = 0xfffe;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
 }
 
-impl Memory {
-    fn read(&self, idx: u16) -> u8 {
-        match idx {
-            0..=0x1fff => self.ram[idx as usize],
-            0x2000..=0x401f => self.io_registers[(idx - 0x2000) as usize],
-            0x4020..=0x5fff => self.expansion_rom[(idx - 0x4020) as usize],
-            0x6000..=0x7fff => self.sram[(idx - 0x6000) as usize],
-            0x8000..=0xffff => self.prg_rom[(idx - 0x8000) as usize],
-            _ => panic!("Read index out of range: {idx:x?}")
+/// Devices that can hold the shared, level-triggered IRQ line low.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+    Mapper,
+    External,
+}
+
+impl IrqSource {
+    fn mask(self) -> u8 {
+        match self {
+            IrqSource::FrameCounter => 0b0001,
+            IrqSource::Dmc => 0b0010,
+            IrqSource::Mapper => 0b0100,
+            IrqSource::External => 0b1000,
         }
     }
+}
+
+/// What a call to `Cpu::step` did.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Step {
+    pub pc: u16,
+    /// The opcode executed. Interrupt sequences report 0x00, the BRK the
+    /// 6502 forces into its instruction register to run them.
+    pub opcode: u8,
+    pub operands: Vec<u8>,
+    pub cycles: u64,
+    pub accesses: Vec<BusAccess>,
+    /// Set when the step ran an interrupt sequence instead of an instruction.
+    pub interrupt: Option<Interrupt>,
+}
 
-    fn write(&mut self, val: u8, idx: u16) {
-        match idx {
-            0..=0x1fff => self.ram[idx as usize] = val,
-            0x2000..=0x401f => self.io_registers[(idx - 0x2000) as usize] = val,
-            0x4020..=0x5fff => self.expansion_rom[(idx - 0x4020) as usize] = val,
-            0x6000..=0x7fff => self.sram[(idx - 0x6000) as usize] = val,
-            0x8000..=0xffff => self.prg_rom[(idx - 0x8000) as usize] = val,
-            _ => panic!("Read index out of range: {idx:x?}")
+pub struct CpuBuilder<B: Bus = NesBus> {
+    bus: B,
+    start_pc: Option<u16>,
+    variant: CpuVariant,
+    unstable_opcodes: UnstableOpcodes,
+    jam_behavior: JamBehavior,
+}
+
+impl<B: Bus> CpuBuilder<B> {
+    pub fn new(bus: B) -> CpuBuilder<B> {
+        CpuBuilder {
+            bus,
+            start_pc: None,
+            variant: CpuVariant::Ricoh2A03,
+            unstable_opcodes: UnstableOpcodes::Emulate { magic: 0xee },
+            jam_behavior: JamBehavior::Halt,
         }
     }
 
-    pub fn load_rom(&mut self, rom: Rom) {
-        self.prg_rom = rom.rom_data;
+    /// Starts execution at `pc` instead of the reset vector once the reset
+    /// sequence is done, e.g. $C000 for nestest's automation mode.
+    pub fn start_pc(mut self, pc: u16) -> CpuBuilder<B> {
+        self.start_pc = Some(pc);
+        self
     }
-}
 
+    pub fn variant(mut self, variant: CpuVariant) -> CpuBuilder<B> {
+        self.variant = variant;
+        self
+    }
+
+    pub fn unstable_opcodes(mut self, unstable_opcodes: UnstableOpcodes) -> CpuBuilder<B> {
+        self.unstable_opcodes = unstable_opcodes;
+        self
+    }
+
+    pub fn jam_behavior(mut self, jam_behavior: JamBehavior) -> CpuBuilder<B> {
+        self.jam_behavior = jam_behavior;
+        self
+    }
+
+    pub fn build(self) -> Cpu<B> {
+        let mut cpu = Cpu {
+            a: 0,
+            x: 0,
+            y: 0,
+            pc: 0,
+            // Reset moves SP down by three, leaving it at $FD.
+            sp: 0x00,
+            p: Status::from_byte(0x34),
+            accesses: Vec::new(),
+            cycles: 0,
+            page_crossed: false,
+            variant: self.variant,
+            unstable_opcodes: self.unstable_opcodes,
+            jam_behavior: self.jam_behavior,
+            jammed: None,
+            nmi_pending: false,
+            nmi_line: false,
+            irq_lines: 0,
+            polled_interrupt: None,
+            bus: self.bus,
+        };
+
+        cpu.reset();
+        if let Some(pc) = self.start_pc {
+            cpu.pc = pc;
+        }
+        cpu
+    }
+}
 
-pub struct Cpu {
+pub struct Cpu<B: Bus + ?Sized = NesBus> {
     a: u8,
     x: u8,
     y: u8,
     pc: u16,
     sp: u8, //$100 - $1ff
-    p: u8,
-    memory: Memory
+    p: Status,
+    accesses: Vec<BusAccess>,
+    cycles: u64,
+    page_crossed: bool,
+    variant: CpuVariant,
+    unstable_opcodes: UnstableOpcodes,
+    jam_behavior: JamBehavior,
+    jammed: Option<CpuError>,
+    nmi_pending: bool,
+    /// The level of `Bus::nmi` after the last cycle, to catch its edges.
+    nmi_line: bool,
+    irq_lines: u8,
+    /// Interrupt detected by the poll at the end of the last instruction.
+    polled_interrupt: Option<Interrupt>,
+    bus: B,
 }
 
 impl Cpu {
-    pub fn new(rom: Rom) -> Cpu {
-        let mut mem = Memory{
-            ram: vec![0; 0x2000],
-            io_registers: vec![0; 0x2020],
-            sram: vec![0; 0x2000],
-            expansion_rom: vec![0; 0x6000 - 0x4020],
-            prg_rom: Vec::new(),
-        };
+    /// Powers up a NES CPU with `cartridge` inserted and runs the reset
+    /// sequence, so execution starts at the address in the reset vector.
+    pub fn new(cartridge: Cartridge) -> Cpu {
+        CpuBuilder::new(NesBus::new(cartridge)).build()
+    }
 
-        mem.load_rom(rom);
+    pub fn builder(cartridge: Cartridge) -> CpuBuilder {
+        CpuBuilder::new(NesBus::new(cartridge))
+    }
+}
 
-        Cpu {
-            a: 0,
-            x: 0,
-            y: 0,
-            pc: 0xc000,
-            sp: 0xfd,
-            p: 0x34,
-            memory: mem
+impl<B: Bus + 'static> Cpu<B> {
+    /// Powers up a CPU wired to `bus` and runs the reset sequence.
+    pub fn with_bus(bus: B) -> Cpu<B> {
+        CpuBuilder::new(bus).build()
+    }
+
+    /// Runs until the CPU hits an instruction it cannot execute.
+    pub fn run(&mut self) -> CpuError {
+        loop {
+            if let Err(e) = self.step() {
+                return e;
+            }
         }
     }
 
-    fn next_instruction(&mut self) -> u8 {
-        let val = self.memory.read(self.pc);
-        self.pc += 1;
-        val
+    /// Executes instructions until at least `cycles` cycles have elapsed and
+    /// returns how many actually did.
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<u64, CpuError> {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            elapsed += self.step()?.cycles;
+        }
+        Ok(elapsed)
     }
 
-    pub fn run(&mut self) {
-        //self.print_mem();
-        
-        loop {            
-            let opcode = self.next_instruction();
-            print!("{:x?} | OPCODE {:x?} | ", self.pc - 1, opcode);      
-
-            match opcode {
-                0x00 => {
-                    self.stack_push((self.pc & 0x00ff) as u8);
-                    self.stack_push(((self.pc & 0xff00) >> 8) as u8);
-                    self.stack_push(self.p);
-                    let addr1 = (self.memory.read(0xfffe) as u16) << 8;
-                    let addr2 = self.memory.read(0xffff) as u16;
-                    self.pc = addr1 + addr2;
-                    println!("brk");
-                }
-                0x40 => {
-                    self.p = self.stack_pull();
-                    let addr1 = (self.stack_pull() as u16) << 8;
-                    let addr2 = self.stack_pull() as u16;
-                    self.pc = addr1 + addr2;
-                    println!("rti");
-                }
-                0xea => println!("nop"),
-
-                0xa9 => {                    
-                    let val  = self.get_imm();
-                    self.lda(val);
-                    println!("lda immediate {val:x?}");
-                }
-                0xa5 => {                    
-                    let val  = self.get_zero_page();
-                    self.lda(val);
-                    println!("lda zero page {val:x?}");
-                }
-                0xb5 => {                    
-                    let val  = self.get_zero_page_x();
-                    self.lda(val);
-                    println!("lda zero page, X {val:x?}");
-                }
-                0xad => {                    
-                    let val  = self.get_absolute();
-                    self.lda(val);
-                    println!("lda absolute {val:x?}");
-                }
-                0xbd => {                    
-                    let val  = self.get_absolute_x();
-                    self.lda(val);
-                    println!("lda absolute, X {val:x?}");
-                }
-                0xb9 => {                    
-                    let val  = self.get_absolute_y();
-                    self.lda(val);
-                    println!("lda absolute, Y {val:x?}");
-                }
-                0xa1 => {                    
-                    let val  = self.get_indirect_x();
-                    self.lda(val);
-                    println!("lda indirect, X {val:x?}");
-                }
-                0xb1 => {                    
-                    let val  = self.get_indirect_y();
-                    self.lda(val);
-                    println!("lda indirect, Y {val:x?}");
-                }
-
-                0xa2 => {                    
-                    let val  = self.get_imm();
-                    self.ldx(val);
-                    println!("ldx immediate {val:x?}");
-                }
-                0xa6 => {                    
-                    let val  = self.get_zero_page();
-                    self.ldx(val);
-                    println!("ldx zero page {val:x?}");
-                }
-                0xb6 => {                    
-                    let val  = self.get_zero_page_y();
-                    self.ldx(val);
-                    println!("ldx zero page, Y {val:x?}");
-                }
-                0xae => {                    
-                    let val  = self.get_absolute();
-                    self.ldx(val);
-                    println!("ldx absolute {val:x?}");
-                }
-                0xbe => {                    
-                    let val  = self.get_absolute_y();
-                    self.ldx(val);
-                    println!("ldx absolute, Y {val:x?}");
-                }
-
-                0xa0 => {                    
-                    let val  = self.get_imm();
-                    self.ldy(val);
-                    println!("ldy immediate {val:x?}");
-                }
-                0xa4 => {                    
-                    let val  = self.get_zero_page();
-                    self.ldy(val);
-                    println!("ldy zero page {val:x?}");
-                }
-                0xb4 => {                    
-                    let val  = self.get_zero_page_x();
-                    self.ldy(val);
-                    println!("ldy zero page, X {val:x?}");
-                }
-                0xac => {                    
-                    let val  = self.get_absolute();
-                    self.ldy(val);
-                    println!("ldy absolute {val:x?}");
-                }
-                0xbc => {                    
-                    let val  = self.get_absolute_x();
-                    self.ldy(val);
-                    println!("ldy absolute, X {val:x?}");
-                }
-
-                0x85 => {
-                    self.write_zero_page(self.a);
-                    println!("sta zero page {:x?}", self.a);
-                }
-                0x95 => {
-                    self.write_zero_page_x(self.a);
-                    println!("sta zero page, X {:x?}", self.a);
-                }
-                0x8d => {
-                    self.write_absolute(self.a);
-                    println!("sta absolute {:x?}", self.a);
-                }
-                0x9d => {
-                    self.write_absolute_x(self.a);
-                    println!("sta absolute, X {:x?}", self.a);
-                }
-                0x99 => {
-                    self.write_absolute_y(self.a);
-                    println!("sta absolute, Y {:x?}", self.a);
-                }
-                0x81 => {
-                    self.write_indirect_x(self.a);
-                    println!("sta indirect, X {:x?}", self.a);
-                }
-                0x91 => {
-                    self.write_indirect_y(self.a);
-                    println!("sta indirect, Y {:x?}", self.a);
-                }
-
-                0x86 => {
-                    self.write_zero_page(self.x);
-                    println!("stx zero page {:x?}", self.x);
-                }
-                0x96 => {
-                    self.write_zero_page_y(self.x);
-                    println!("stx zero page, Y {:x?}", self.x);
-                }
-                0x8e => {
-                    self.write_absolute(self.x);
-                    println!("stx absolute {:x?}", self.x);
-                }
-
-                0x84 => {
-                    self.write_zero_page(self.y);
-                    println!("sty zero page {:x?}", self.y);
-                }
-                0x94 => {
-                    self.write_zero_page_x(self.y);
-                    println!("sty zero page, X {:x?}", self.y);
-                }
-                0x8c => {
-                    self.write_absolute(self.y);
-                    println!("sty absolute {:x?}", self.y);
-                }
-                
-                0xaa => {
-                    self.ldx(self.a);
-                    println!("tax");
-                }
-                0xa8 => {
-                    self.ldy(self.a);
-                    println!("tay");
-                }
-                0xba => {
-                    self.ldx(self.sp);
-                    println!("tsx");
-                }
-                0x8a => {
-                    self.lda(self.x);
-                    println!("txa");
-                }
-                0x9a => {
-                    self.sp = self.x;
-                    println!("txs");
-                }
-                0x98 => {
-                    self.lda(self.y);
-                    println!("tya");
-                }
-
-                0x48 => {
-                    self.stack_push(self.a);
-                    println!("pha");
-                }
-                0x08 => {
-                    self.stack_push(self.p);
-                    println!("php");
-                }
-                0x68 => {
-                    let val = self.stack_pull();
-                    self.lda(val);
-                    println!("pla {val:x?}");
-                }
-                0x28 => {
-                    self.p = self.stack_pull();
-                    println!("plp");
-                }
-
-                0x29 => {
-                    let val = self.get_imm() & self.a;
-                    self.lda(val);
-                    println!("and immediate {:x?}", val);
-                }
-                0x25 => {
-                    let val = self.get_zero_page() & self.a;
-                    self.lda(val);
-                    println!("and zero page {:x?}", val);
-                }
-                0x35 => {
-                    let val = self.get_zero_page_x() & self.a;
-                    self.lda(val);
-                    println!("and zero page, X {:x?}", val);
-                }
-                0x2d => {
-                    let val = self.get_absolute() & self.a;
-                    self.lda(val);
-                    println!("and absolute {:x?}", val);
-                }
-                0x3d => {
-                    let val = self.get_absolute_x() & self.a;
-                    self.lda(val);
-                    println!("and absolute, X {:x?}", val);
-                }
-                0x39 => {
-                    let val = self.get_absolute_y() & self.a;
-                    self.lda(val);
-                    println!("and absolute, Y {:x?}", val);
-                }
-                0x21 => {
-                    let val = self.get_indirect_x() & self.a;
-                    self.lda(val);
-                    println!("and indirect, X {:x?}", val);
-                }
-                0x31 => {
-                    let val = self.get_indirect_y() & self.a;
-                    self.lda(val);
-                    println!("and indirect, Y {:x?}", val);
-                }
-
-                0x49 => {
-                    let val = self.get_imm() ^ self.a;
-                    self.lda(val);
-                    println!("eor immediate {:x?}", val);
-                }
-                0x45 => {
-                    let val = self.get_zero_page() ^ self.a;
-                    self.lda(val);
-                    println!("eor zero page {:x?}", val);
-                }
-                0x55 => {
-                    let val = self.get_zero_page_x() ^ self.a;
-                    self.lda(val);
-                    println!("eor zero page, X {:x?}", val);
-                }
-                0x4d => {
-                    let val = self.get_absolute() ^ self.a;
-                    self.lda(val);
-                    println!("eor absolute {:x?}", val);
-                }
-                0x5d => {
-                    let val = self.get_absolute_x() ^ self.a;
-                    self.lda(val);
-                    println!("eor absolute, X {:x?}", val);
-                }
-                0x59 => {
-                    let val = self.get_absolute_y() ^ self.a;
-                    self.lda(val);
-                    println!("eor absolute, Y {:x?}", val);
-                }
-                0x41 => {
-                    let val = self.get_indirect_x() ^ self.a;
-                    self.lda(val);
-                    println!("eor indirect, X {:x?}", val);
-                }
-                0x51 => {
-                    let val = self.get_indirect_y() ^ self.a;
-                    self.lda(val);
-                    println!("eor indirect, Y {:x?}", val);
-                }
-
-                0x09 => {
-                    let val = self.get_imm() | self.a;
-                    self.lda(val);
-                    println!("ora immediate {:x?}", val);
-                }
-                0x05 => {
-                    let val = self.get_zero_page() | self.a;
-                    self.lda(val);
-                    println!("ora zero page {:x?}", val);
-                }
-                0x15 => {
-                    let val = self.get_zero_page_x() | self.a;
-                    self.lda(val);
-                    println!("ora zero page, X {:x?}", val);
-                }
-                0x0d => {
-                    let val = self.get_absolute() | self.a;
-                    self.lda(val);
-                    println!("ora absolute {:x?}", val);
-                }
-                0x1d => {
-                    let val = self.get_absolute_x() | self.a;
-                    self.lda(val);
-                    println!("ora absolute, X {:x?}", val);
-                }
-                0x19 => {
-                    let val = self.get_absolute_y() | self.a;
-                    self.lda(val);
-                    println!("ora absolute, Y {:x?}", val);
-                }
-                0x01 => {
-                    let val = self.get_indirect_x() | self.a;
-                    self.lda(val);
-                    println!("ora indirect, X {:x?}", val);
-                }
-                0x11 => {
-                    let val = self.get_indirect_y() | self.a;
-                    self.lda(val);
-                    println!("ora indirect, Y {:x?}", val);
-                }
-
-                0x24 => {
-                    let val = self.get_zero_page() /*& self.a*/;
-                    self.bit_test(val);
-                    println!("bit zero page {:x?}", val);
-                }
-                0x2c => {
-                    let val = self.get_absolute() /*& self.a*/;
-                    self.bit_test(val);
-                    println!("bit absolute {:x?}", val);
-                }
-
-                0x69 => {
-                    let val = self.get_imm();
-                    self.adc(val);
-                    println!("adc immediate {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x65 => {
-                    let val = self.get_zero_page();
-                    self.adc(val);
-                    println!("adc zero page {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x75 => {
-                    let val = self.get_zero_page_x();
-                    self.adc(val);
-                    println!("adc zero page, X {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x6d => {
-                    let val = self.get_absolute();
-                    self.adc(val);
-                    println!("adc absolute {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x7d => {
-                    let val = self.get_absolute_x();
-                    self.adc(val);
-                    println!("adc absolute, X {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x79 => {
-                    let val = self.get_absolute_y();
-                    self.adc(val);
-                    println!("adc absolute, Y {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x61 => {
-                    let val = self.get_indirect_x();
-                    self.adc(val);
-                    println!("adc indirect, X {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-                0x71 => {
-                    let val = self.get_indirect_y();
-                    self.adc(val);
-                    println!("adc indirect, Y {:x?} + {:x?} + {}", self.a, val, self.get_carry_flag());
-                }
-
-                0xe9 => {
-                    let val = self.get_imm();
-                    self.sbc(val);
-                    println!("sbc immediate {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xe5 => {
-                    let val = self.get_zero_page();
-                    self.sbc(val);
-                    println!("sbc zero page {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xf5 => {
-                    let val = self.get_zero_page_x();
-                    self.sbc(val);
-                    println!("sbc zero page, X {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xed => {
-                    let val = self.get_absolute();
-                    self.sbc(val);
-                    println!("sbc absolute {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xfd => {
-                    let val = self.get_absolute_x();
-                    self.sbc(val);
-                    println!("sbc absolute, X {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xf9 => {
-                    let val = self.get_absolute_y();
-                    self.sbc(val);
-                    println!("sbc absolute, Y {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xe1 => {
-                    let val = self.get_indirect_x();
-                    self.sbc(val);
-                    println!("sbc indirect, X {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-                0xf1 => {
-                    let val = self.get_indirect_y();
-                    self.sbc(val);
-                    println!("sbc indirect, Y {:x?} - {:x?} - {}", self.a, val, 1 - self.get_carry_flag());
-                }
-
-                0xc9 => {
-                    let val = self.get_imm();
-                    self.cmp(self.a, val);
-                    println!("cmp immediate {:x?}, {:x?}", self.a, val);
-                }
-                0xc5 => {
-                    let val = self.get_zero_page();
-                    self.cmp(self.a, val);
-                    println!("cmp zero page {:x?}, {:x?}", self.a, val);
-                }
-                0xd5 => {
-                    let val = self.get_zero_page_x();
-                    self.cmp(self.a, val);
-                    println!("cmp zero page, X {:x?}, {:x?}", self.a, val);
-                }
-                0xcd => {
-                    let val = self.get_absolute();
-                    self.cmp(self.a, val);
-                    println!("cmp absolute {:x?}, {:x?}", self.a, val);
-                }
-                0xdd => {
-                    let val = self.get_absolute_x();
-                    self.cmp(self.a, val);
-                    println!("cmp absolute, X {:x?}, {:x?}", self.a, val);
-                }
-                0xd9 => {
-                    let val = self.get_absolute_y();
-                    self.cmp(self.a, val);
-                    println!("cmp absolute, Y {:x?}, {:x?}", self.a, val);
-                }
-                0xc1 => {
-                    let val = self.get_indirect_x();
-                    self.cmp(self.a, val);
-                    println!("cmp indirect, X {:x?}, {:x?}", self.a, val);
-                }
-                0xd1 => {
-                    let val = self.get_indirect_y();
-                    self.cmp(self.a, val);
-                    println!("cmp indirect, Y {:x?}, {:x?}", self.a, val);
-                }
-
-                0xe0 => {
-                    let val = self.get_imm();
-                    self.cmp(self.x, val);
-                    println!("cpx immediate {:x?}, {:x?}", self.x, val);
-                }
-                0xe4 => {
-                    let val = self.get_zero_page();
-                    self.cmp(self.x, val);
-                    println!("cpx zero page {:x?}, {:x?}", self.x, val);
-                }
-                0xec => {
-                    let val = self.get_absolute();
-                    self.cmp(self.x, val);
-                    println!("cpx absolute {:x?}, {:x?}", self.x, val);
-                }
-                
-                0xc0 => {
-                    let val = self.get_imm();
-                    self.cmp(self.y, val);
-                    println!("cpy immediate {:x?}, {:x?}", self.y, val);
-                }
-                0xc4 => {
-                    let val = self.get_zero_page();
-                    self.cmp(self.y, val);
-                    println!("cpy zero page {:x?}, {:x?}", self.y, val);
-                }
-                0xcc => {
-                    let val = self.get_absolute();
-                    self.cmp(self.y, val);
-                    println!("cpy absolute {:x?}, {:x?}", self.y, val);
-                }
-
-                0xe6 => {
-                    let val = self.get_zero_page().overflowing_add(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 1;
-                    self.write_zero_page(val);
-                    println!("inc zero page {:x?}", val);
-                }
-                0xf6 => {
-                    let val = self.get_zero_page_x().overflowing_add(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 1;
-                    self.write_zero_page_x(val);
-                    println!("inc zero page, x {:x?}", val);
-                }
-                0xee => {
-                    let val = self.get_absolute().overflowing_add(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 2;
-                    self.write_absolute(val);
-                    println!("inc absolute {:x?}", val);
-                }
-                0xfe => {
-                    let val = self.get_absolute_x().overflowing_add(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 2;
-                    self.write_absolute_x(val);
-                    println!("inc absolute, X {:x?}", val);
-                }
-
-                0xe8 => {
-                    self.ldx(self.x.overflowing_add(1).0);
-                    println!("inx {:x?}", self.x);
-                }
-                0xc8 => {
-                    self.ldx(self.y.overflowing_add(1).0);
-                    println!("iny {:x?}", self.y);
-                }
-
-                0xc6 => {
-                    let val = self.get_zero_page().overflowing_sub(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 1;
-                    self.write_zero_page(val);
-                    println!("dec zero page {:x?}", val);
-                }
-                0xd6 => {
-                    let val = self.get_zero_page_x().overflowing_sub(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 1;
-                    self.write_zero_page_x(val);
-                    println!("dec zero page, x {:x?}", val);
-                }
-                0xce => {
-                    let val = self.get_absolute().overflowing_sub(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 2;
-                    self.write_absolute(val);
-                    println!("dec absolute {:x?}", val);
-                }
-                0xde => {
-                    let val = self.get_absolute_x().overflowing_sub(1).0;
-                    self.assign_basic_flags(val);
-                    self.pc -= 2;
-                    self.write_absolute_x(val);
-                    println!("dec absolute, X {:x?}", val);
-                }
-
-                0xca => {
-                    self.ldx(self.x.overflowing_sub(1).0);
-                    println!("dex {:x?}", self.x);
-                }
-                0x88 => {
-                    self.ldx(self.y.overflowing_sub(1).0);
-                    println!("dey {:x?}", self.y);
-                }
-
-                0x0a => {
-                    self.set_carry_flag(self.a & 0b10000000 == 0b10000000);
-                    self.lda(self.a << 1);
-                    println!("asl acc {:x?}", self.a);
-                }
-                0x06 => {
-                    let val = self.get_zero_page();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags(val << 1);                    
-                    self.write_zero_page(val << 1);
-                    println!("asl zero page {:x?}", val);
-                }
-                0x16 => {
-                    let val = self.get_zero_page_x();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags(val << 1);                    
-                    self.write_zero_page_x(val << 1);
-                    println!("asl zero page, X {:x?}", val);
-                }
-                0x0e => {
-                    let val = self.get_absolute();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags(val << 1);                    
-                    self.write_absolute(val << 1);
-                    println!("asl absolute {:x?}", val);
-                }
-                0x1e => {
-                    let val = self.get_absolute_x();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags(val << 1);                    
-                    self.write_absolute_x(val << 1);
-                    println!("asl absolute, x {:x?}", val);
-                }
-
-                0x4a => {
-                    self.set_carry_flag(self.a & 0b00000001 == 0b00000001);
-                    self.lda(self.a >> 1);
-                    println!("lsr acc {:x?}", self.a);
-                }
-                0x46 => {
-                    let val = self.get_zero_page();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b00000001 == 0b00000001);
-                    self.assign_basic_flags(val >> 1);                    
-                    self.write_zero_page(val >> 1);
-                    println!("lsr zero page {:x?}", val);
-                }
-                0x56 => {
-                    let val = self.get_zero_page_x();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b00000001 == 0b00000001);
-                    self.assign_basic_flags(val >> 1);                    
-                    self.write_zero_page_x(val >> 1);
-                    println!("lsr zero page, X {:x?}", val);
-                }
-                0x4e => {
-                    let val = self.get_absolute();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b00000001 == 0b00000001);
-                    self.assign_basic_flags(val >> 1);                    
-                    self.write_absolute(val >> 1);
-                    println!("lsr absolute {:x?}", val);
-                }
-
-                0x5e => {
-                    let val = self.get_absolute_x();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b00000001 == 0b00000001);
-                    self.assign_basic_flags(val >> 1);                    
-                    self.write_absolute_x(val >> 1);
-                    println!("lsr absolute, x {:x?}", val);
-                }
-
-                0x2a => {
-                    self.set_carry_flag(self.a & 0b10000000 == 0b10000000);
-                    self.lda((self.a << 1) + self.get_carry_flag());
-                    println!("rol acc {:x?}", self.a);
-                }
-                0x26 => {
-                    let val = self.get_zero_page();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val << 1) + self.get_carry_flag());                    
-                    self.write_zero_page((val << 1) + self.get_carry_flag());
-                    println!("rol zero page {:x?}", val);
-                }
-                0x36 => {
-                    let val = self.get_zero_page_x();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val << 1) + self.get_carry_flag());                    
-                    self.write_zero_page_x((val << 1) + self.get_carry_flag());
-                    println!("rol zero page, X {:x?}", val);
-                }
-                0x2e => {
-                    let val = self.get_absolute();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val << 1) + self.get_carry_flag());                    
-                    self.write_absolute((val << 1) + self.get_carry_flag());
-                    println!("rol absolute {:x?}", val);
-                }
-                0x3e => {
-                    let val = self.get_absolute_x();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val << 1) + self.get_carry_flag());                    
-                    self.write_absolute_x((val << 1) + self.get_carry_flag());
-                    println!("rol absolute, x {:x?}", val);
-                }
-
-                0x6a => {
-                    self.set_carry_flag(self.a & 0b10000000 == 0b10000000);
-                    self.lda((self.a >> 1) + (self.get_carry_flag() << 7));
-                    println!("ror acc {:x?}", self.a);
-                }
-                0x66 => {
-                    let val = self.get_zero_page();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val >> 1) + (self.get_carry_flag() << 7));                    
-                    self.write_zero_page((val >> 1) + (self.get_carry_flag() << 7));
-                    println!("ror zero page {:x?}", val);
-                }
-                0x76 => {
-                    let val = self.get_zero_page_x();
-                    self.pc -= 1;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val >> 1) + (self.get_carry_flag() << 7));                    
-                    self.write_zero_page_x((val >> 1) + (self.get_carry_flag() << 7));
-                    println!("ror zero page, X {:x?}", val);
-                }
-                0x6e => {
-                    let val = self.get_absolute();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val >> 1) + (self.get_carry_flag() << 7));                    
-                    self.write_absolute((val >> 1) + (self.get_carry_flag() << 7));
-                    println!("ror absolute {:x?}", val);
-                }
-                0x7e => {
-                    let val = self.get_absolute_x();
-                    self.pc -= 2;
-                    self.set_carry_flag(val & 0b10000000 == 0b10000000);
-                    self.assign_basic_flags((val >> 1) + (self.get_carry_flag() << 7));                    
-                    self.write_absolute_x((val >> 1) + (self.get_carry_flag() << 7));
-                    println!("ror absolute, x {:x?}", val);
-                }
-
-                0x4c => {
-                    let addr = self.get_absolute_addr();
-                    self.pc = addr;
-                    println!("jmp absolute {:x?}", addr);
-                }
-                0x6c => {
-                    let addr = self.get_indirect_addr();
-                    self.pc = addr;
-                    println!("jmp indirect {:x?}", addr);
-                }
-
-                0x20 => {
-                    let addr = self.get_absolute_addr();
-                    self.stack_push(((self.pc) & 0x00ff) as u8);
-                    self.stack_push((((self.pc) & 0xff00) >> 8) as u8);                 
-                    self.pc = addr;
-                    println!("jsr absolute {:x?}", addr);
-                }
-
-                0x60 => {
-                    let mut addr = (self.stack_pull() as u16) << 8;
-                    addr += self.stack_pull() as u16;
-                    self.pc = addr;
-                    println!("rts {:x?}", addr);
-                }
-
-                0x90 => { // PC + 1???
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_carry_flag() == 0 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bcc {}", displacement);
-                }
-                0xb0 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_carry_flag() == 1 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bcs {}", displacement);
-                }
-                0xf0 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_zero_flag() == 1 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("beq {}", displacement);
-                }
-                0x30 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_negative_flag() == 1 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bmi {}", displacement);
-                }
-                0xd0 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_zero_flag() == 0 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bne {}", displacement);
-                }
-                0x10 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_negative_flag() == 0 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bpl {}", displacement);
-                }
-                0x50 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_overflow_flag() == 0 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bvc {}", displacement);
-                }
-                0x70 => {
-                    let displacement: i8 = self.get_imm() as i8;
-                    if self.get_overflow_flag() == 1 {
-                        self.branch_jump(displacement);
-                    }
-                    println!("bvs {}", displacement);
-                }
-
-                0x18 => {
-                    self.set_carry_flag(false);
-                    println!("clc");
-                }
-                0xd8 => {
-                    self.set_decimal_mode(false);
-                    println!("cld");
-                }
-                0x58 => {
-                    self.set_interrupt_disable(false);
-                    println!("cli");
-                }
-                0xb8 => {
-                    self.set_overflow(false);
-                    println!("clv");
-                }
-                0x38 => {
-                    self.set_carry_flag(true);
-                    println!("sec");
-                }
-                0xf8 => {
-                    self.set_decimal_mode(true);
-                    println!("sed");
-                }
-                0x78 => {
-                    self.set_interrupt_disable(true);
-                    println!("sei");
-                }
-                _ => print!("")
-            }          
+    /// Executes instructions until `predicate` holds before the next one and
+    /// returns how many were executed.
+    pub fn run_until<F: FnMut(&Cpu<B>) -> bool>(&mut self, mut predicate: F) -> Result<u64, CpuError> {
+        let mut steps = 0;
+        while !predicate(self) {
+            self.step()?;
+            steps += 1;
         }
+        Ok(steps)
     }
 
-    fn assign_basic_flags(&mut self, val: u8) {
-        self.set_zero_flag(val == 0);
-        self.set_negative(val & 0b10000000 == 0b10000000);
+    /// Executes the instruction at the current PC.
+    pub fn step(&mut self) -> Result<Step, CpuError> {
+        if let Some(e) = self.jammed {
+            return Err(e);
+        }
+
+        let pc = self.pc;
+        self.accesses.clear();
+        let start = self.cycles;
+
+        let interrupt = self.polled_interrupt.take();
+        let (opcode, operands) = match interrupt {
+            Some(interrupt) => {
+                self.interrupt(interrupt);
+                (0x00, Vec::new())
+            }
+            None => {
+                let operands = (1..OPCODES[self.peek(pc) as usize].len)
+                    .map(|i| self.peek(pc.wrapping_add(i)))
+                    .collect();
+                (self.execute()?, operands)
+            }
+        };
+
+        Ok(Step {
+            pc,
+            opcode,
+            operands,
+            cycles: self.cycles - start,
+            accesses: std::mem::take(&mut self.accesses),
+            interrupt,
+        })
+    }
+
+    fn execute(&mut self) -> Result<u8, CpuError> {
+        let pc = self.pc;
+        let opcode = self.peek(pc);
+        let entry = &OPCODES[opcode as usize];
+        if entry.unstable && self.unstable_opcodes == UnstableOpcodes::Reject {
+            return Err(CpuError::UnstableOpcode { pc, opcode });
+        }
+
+        let interrupt_disable = self.p.get(Flag::InterruptDisable);
+        self.next_instruction();
+        self.page_crossed = false;
+        if entry.len == 1 {
+            // One-byte instructions still read the byte after the opcode.
+            self.read(self.pc);
+        }
+        // The opcode table is shared by all buses, so handlers see the bus as
+        // a trait object.
+        let cpu: &mut Cpu<dyn Bus> = self;
+        (entry.handler)(cpu, entry.mode);
+        self.cycles += entry.cycles as u64;
+
+        if let Some(e) = self.jammed {
+            return Err(e);
+        }
+        if let Some(page) = self.bus.take_oam_dma() {
+            self.oam_dma(page);
+        }
+
+        // CLI ($58), SEI ($78) and PLP ($28) change I after the CPU has
+        // polled for interrupts, so the new value only matters from the next
+        // instruction on.
+        let interrupt_disable = match opcode {
+            0x58 | 0x78 | 0x28 => interrupt_disable,
+            _ => self.p.get(Flag::InterruptDisable),
+        };
+        self.poll_interrupts(interrupt_disable);
+
+        Ok(opcode)
     }
+}
 
-    fn lda(&mut self, val: u8) {
-        self.a = val;
-        self.assign_basic_flags(self.a);
+impl<B: Bus + ?Sized> Cpu<B> {
+    pub fn a(&self) -> u8 {
+        self.a
     }
 
-    fn ldx(&mut self, val: u8) {
-        self.x = val;
-        self.assign_basic_flags(self.x);
+    pub fn x(&self) -> u8 {
+        self.x
     }
 
-    fn ldy(&mut self, val: u8) {
-        self.y = val;
-        self.assign_basic_flags(self.y);
+    pub fn y(&self) -> u8 {
+        self.y
     }
-    fn bit_test(&mut self, val: u8) {
-        self.assign_basic_flags(val);
-        self.set_overflow(val & 0b01000000 == 0b01000000);
+
+    /// P as it would be pushed by an interrupt, with bit 5 set and B clear.
+    pub fn p(&self) -> u8 {
+        self.p.to_byte(false)
     }
 
-    fn adc(&mut self, val: u8) {
-        let sum_1 = self.a.overflowing_add(val);
-        let sum_2 = sum_1.0.overflowing_add(self.get_carry_flag());        
+    pub fn status(&self) -> Status {
+        self.p
+    }
 
-        self.a = sum_2.0;
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
 
-        self.set_zero_flag(self.a == 0);
-        self.set_carry_flag(sum_1.1 || sum_2.1);
-        self.set_negative(self.a & 0b10000000 == 0b10000000);
-        self.set_overflow(sum_1.1 || sum_2.1);
+    pub fn pc(&self) -> u16 {
+        self.pc
     }
 
-    fn sbc(&mut self, val: u8) {
-        let sub_1 = self.a.overflowing_sub(val);
-        let sub_2 = sub_1.0.overflowing_sub(1 - (1 - self.get_carry_flag()));        
+    /// Total number of CPU cycles elapsed since power-up.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
 
-        self.a = sub_2.0;
-        
-        self.set_zero_flag(self.a == 0);
-        self.set_carry_flag(!(sub_1.1 || sub_2.1));
-        self.set_negative(self.a & 0b10000000 == 0b10000000);
-        self.set_overflow(self.a & 0b10000000 == 0b10000000 && !(sub_1.1 || sub_2.1));
+    pub fn bus(&self) -> &B {
+        &self.bus
     }
 
-    fn cmp(&mut self,val_1: u8, val_2: u8) {
-        self.set_carry_flag(val_1 >= val_2);
-        self.set_zero_flag(val_1 == val_2);
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
     }
 
-    fn branch_jump(&mut self, displacement: i8) {
-        if displacement < 0 {
-            self.pc -= (-displacement as u8) as u16;
+    /// Signals a falling edge on the NMI line, for devices outside the bus.
+    /// Devices on it use `Bus::nmi`.
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Asserts (`true`) or releases the IRQ line on behalf of `source`. The
+    /// line stays asserted while any source holds it.
+    pub fn set_irq_line(&mut self, source: IrqSource, level: bool) {
+        if level {
+            self.irq_lines |= source.mask();
         } else {
-            self.pc += displacement as u16;
+            self.irq_lines &= !source.mask();
         }
     }
 
-    fn get_imm(&mut self) -> u8 {
-        self.next_instruction()
-    }
+    /// Runs the 6502 reset sequence: the stack pointer moves as if three
+    /// bytes were pushed, I is set and PC is loaded from the reset vector.
+    pub fn reset(&mut self) {
+        self.read(self.pc);
+        self.read(self.pc);
+        for _ in 0..3 {
+            self.read(0x0100 + self.sp as u16);
+            self.sp = self.sp.wrapping_sub(1);
+        }
+        self.p.set(Flag::InterruptDisable, true);
+        self.pc = self.read_vector(RESET_VECTOR);
+        self.cycles += 7;
 
-    fn get_zero_page(&mut self) -> u8 {
-        let addr  = self.next_instruction();
-        self.memory.read(addr as u16)
+        self.jammed = None;
+        self.nmi_pending = false;
+        self.polled_interrupt = None;
     }
 
-    fn get_zero_page_x(&mut self) -> u8 {
-        let addr  = Wrapping(self.next_instruction()) + Wrapping(self.x);
-        self.memory.read(addr.0 as u16)
+    /// Reads memory without executing anything, for debuggers and traces.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
     }
 
-    fn get_zero_page_y(&mut self) -> u8 {
-        let addr  = Wrapping(self.next_instruction()) + Wrapping(self.y);
-        self.memory.read(addr.0 as u16)
+    fn read(&mut self, addr: u16) -> u8 {
+        self.bus.tick();
+        let value = self.bus.read(addr);
+        self.accesses.push(BusAccess { kind: AccessKind::Read, addr, value });
+        self.sample_nmi_line();
+        value
     }
 
-    fn get_absolute_addr(&mut self) -> u16 {
-        let mut addr  = self.next_instruction() as u16;
-        addr += (self.next_instruction() as u16) << 8;
-        addr
+    fn write(&mut self, value: u8, addr: u16) {
+        self.bus.tick();
+        self.bus.write(value, addr);
+        self.accesses.push(BusAccess { kind: AccessKind::Write, addr, value });
+        self.sample_nmi_line();
     }
 
-    fn get_indirect_addr(&mut self) -> u16 {
-        let addr = self.get_absolute_addr();
-        let addr1 = self.memory.read(addr) as u16;
-        let addr2 = (self.memory.read(addr + 1) as u16 )<< 8;
-        addr1 + addr2
+    /// Checks the bus's NMI line at the end of a cycle, after the access.
+    fn sample_nmi_line(&mut self) {
+        let level = self.bus.nmi();
+        if level && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = level;
     }
 
-    fn get_absolute(&mut self) -> u8 {
-        let addr  = self.get_absolute_addr();
-        self.memory.read(addr)
+    fn next_instruction(&mut self) -> u8 {
+        let val = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        val
     }
 
-    fn get_absolute_x(&mut self) -> u8 {
-        let addr  = self.get_absolute_addr() + (self.x as u16);
-        self.memory.read(addr)
+    fn poll_interrupts(&mut self, interrupt_disable: bool) {
+        self.polled_interrupt = if self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if (self.irq_lines != 0 || self.bus.irq()) && !interrupt_disable {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
     }
 
-    fn get_absolute_y(&mut self) -> u8 {
-        let addr  = self.get_absolute_addr() + (self.y as u16);
-        self.memory.read(addr)
+    /// Copies `page` to the PPU's OAM through $2004. The CPU halts for a
+    /// cycle, and for one more if it has to wait for a read cycle, then
+    /// alternates reads and writes: 513 or 514 cycles in all.
+    fn oam_dma(&mut self, page: u8) {
+        let halt_cycles = if self.cycles & 1 == 1 { 2 } else { 1 };
+        for _ in 0..halt_cycles {
+            self.read(self.pc);
+        }
+        for i in 0..=0xff {
+            let val = self.read((page as u16) << 8 | i);
+            self.write(val, 0x2004);
+        }
+        self.cycles += 512 + halt_cycles;
     }
 
-    fn get_indirect_x(&mut self) -> u8 {
-        let ind_addr  = Wrapping(self.next_instruction()) + Wrapping(self.x);
-        let addr = (self.memory.read(ind_addr.0 as u16) as u16) << 8;
-        self.memory.read(addr)
+    /// Runs the 7-cycle hardware interrupt sequence.
+    fn interrupt(&mut self, interrupt: Interrupt) {
+        self.read(self.pc);
+        self.read(self.pc);
+        let vector = match interrupt {
+            Interrupt::Nmi => NMI_VECTOR,
+            Interrupt::Irq => IRQ_VECTOR,
+        };
+        self.push_interrupt_frame(vector, false);
+        self.cycles += 7;
+    }
+
+    /// Pushes PC and P, sets I and jumps through `vector`, shared by BRK, IRQ
+    /// and NMI. B is only set in the pushed copy of P for BRK. An NMI that
+    /// arrives before the vector is fetched hijacks the sequence.
+    fn push_interrupt_frame(&mut self, vector: u16, brk: bool) {
+        self.stack_push((self.pc >> 8) as u8);
+        self.stack_push((self.pc & 0x00ff) as u8);
+        self.stack_push(self.p.to_byte(brk));
+        self.p.set(Flag::InterruptDisable, true);
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.p.set(Flag::Decimal, false);
+        }
+
+        let vector = if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            vector
+        };
+        self.pc = self.read_vector(vector);
     }
 
-    fn get_indirect_y(&mut self) -> u8 {
-        let mut addr = self.next_instruction() as u16;
-        addr = (self.memory.read(addr) as u16) << 8;
-        addr += self.y as u16;
-        self.memory.read(addr)
+    fn read_vector(&mut self, vector: u16) -> u16 {
+        let lo = self.read(vector) as u16;
+        let hi = self.read(vector + 1) as u16;
+        (hi << 8) | lo
     }
 
-    fn write_zero_page(&mut self, val: u8) {
-        let addr  = self.next_instruction();
-        self.memory.write(val, addr as u16);
+    /// Executes a JAM opcode, which has already been fetched.
+    fn jam(&mut self) {
+        if self.jam_behavior == JamBehavior::Halt {
+            self.pc = self.pc.wrapping_sub(1);
+            let opcode = self.peek(self.pc);
+            self.jammed = Some(CpuError::Jammed { pc: self.pc, opcode });
+        }
     }
 
-    fn write_zero_page_x(&mut self, val: u8) {
-        let addr  = Wrapping(self.next_instruction()) + Wrapping(self.x);
-        self.memory.write(val, addr.0 as u16);
+    fn unstable_magic(&self) -> u8 {
+        match self.unstable_opcodes {
+            UnstableOpcodes::Emulate { magic } => magic,
+            UnstableOpcodes::Reject => unreachable!("unstable opcodes are rejected before executing"),
+        }
     }
 
-    fn write_zero_page_y(&mut self, val: u8) {
-        let addr  = Wrapping(self.next_instruction()) + Wrapping(self.y);
-        self.memory.write(val, addr.0 as u16);
+    fn assign_basic_flags(&mut self, val: u8) {
+        self.p.set(Flag::Zero, val == 0);
+        self.p.set(Flag::Negative, val & 0b10000000 == 0b10000000);
     }
 
-    fn write_absolute(&mut self, val: u8) {
-        let addr = self.get_absolute_addr();
-        self.memory.write(val, addr);
+    fn lda(&mut self, val: u8) {
+        self.a = val;
+        self.assign_basic_flags(self.a);
     }
 
-    fn write_absolute_x(&mut self, val: u8) {
-        let addr = self.get_absolute_addr() + (self.x as u16);
-        self.memory.write(val, addr);
+    fn ldx(&mut self, val: u8) {
+        self.x = val;
+        self.assign_basic_flags(self.x);
     }
 
-    fn write_absolute_y(&mut self, val: u8) {
-        let addr = self.get_absolute_addr() + (self.y as u16);
-        self.memory.write(val, addr);
+    fn ldy(&mut self, val: u8) {
+        self.y = val;
+        self.assign_basic_flags(self.y);
+    }
+    fn bit_test(&mut self, val: u8) {
+        self.p.set(Flag::Zero, self.a & val == 0);
+        self.p.set(Flag::Negative, val & 0b10000000 == 0b10000000);
+        self.p.set(Flag::Overflow, val & 0b01000000 == 0b01000000);
     }
 
-    fn write_indirect_x(&mut self, val: u8) {
-        let ind_addr  = Wrapping(self.next_instruction()) + Wrapping(self.x);
-        let addr = (self.memory.read(ind_addr.0 as u16) as u16) << 8;
-        self.memory.write(val, addr);
+    fn adc(&mut self, val: u8) {
+        if self.decimal_arithmetic() {
+            self.adc_decimal(val);
+            return;
+        }
+
+        self.add_binary(val);
     }
 
-    fn write_indirect_y(&mut self, val: u8) {
-        let mut addr = self.next_instruction() as u16;
-        addr = (self.memory.read(addr) as u16) << 8;
-        addr += self.y as u16;
-        self.memory.write(val, addr);
+    /// Binary ADC. SBC is the same operation on the inverted operand.
+    fn add_binary(&mut self, val: u8) {
+        let sum = self.a as u16 + val as u16 + self.p.get(Flag::Carry) as u16;
+        let result = sum as u8;
+
+        // Overflow when both operands have the same sign and the result
+        // does not.
+        self.p.set(Flag::Overflow, (self.a ^ result) & (val ^ result) & 0b10000000 != 0);
+        self.p.set(Flag::Carry, sum > 0xff);
+        self.lda(result);
     }
 
-    fn stack_push(&mut self, val: u8) {
-        let addr = 0x0100 + (self.sp as u16);
-        self.memory.write(val, addr);
-        if self.sp == 0 {
-            panic!("Stack overflow");
+    fn sbc(&mut self, val: u8) {
+        if self.decimal_arithmetic() {
+            self.sbc_decimal(val);
+            return;
         }
-        self.sp -= 1;
+
+        self.add_binary(!val);
     }
 
-    fn stack_pull(&mut self) -> u8 {
-        if self.sp < 0xff {
-            self.sp += 1;
-            self.memory.read(0x0100 + (self.sp as u16))         
+    fn decimal_arithmetic(&self) -> bool {
+        self.variant != CpuVariant::Ricoh2A03 && self.p.get(Flag::Decimal)
+    }
+
+    // BCD arithmetic follows Bruce Clark's "Decimal Mode" tutorial on
+    // 6502.org, including the flags for invalid BCD operands.
+    fn adc_decimal(&mut self, val: u8) {
+        let a = self.a;
+        let carry = self.p.get(Flag::Carry) as u8;
+
+        let mut low = (a & 0x0f) + (val & 0x0f) + carry;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (a & 0xf0) as u16 + (val & 0xf0) as u16 + low as u16;
+
+        // N and V come from the sum before the high digit is adjusted.
+        let signed = (a & 0xf0) as i8 as i16 + (val & 0xf0) as i8 as i16 + low as i16;
+        self.p.set(Flag::Overflow, !(-128..=127).contains(&signed));
+        self.p.set(Flag::Negative, sum & 0x80 == 0x80);
+
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        self.a = sum as u8;
+        self.p.set(Flag::Carry, sum >= 0x100);
+
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.assign_basic_flags(self.a);
+            self.decimal_fixup_cycle();
         } else {
-            panic!("Empty stack");
+            self.p.set(Flag::Zero, a.wrapping_add(val).wrapping_add(carry) == 0);
         }
     }
 
-    fn set_carry_flag(&mut self, carry: bool) {
-        match carry {
-            true => self.p = self.p | 0b00000001,
-            false => self.p = self.p & 0b11111110
+    fn sbc_decimal(&mut self, val: u8) {
+        let a = self.a;
+        let borrow = 1 - self.p.get(Flag::Carry) as i16;
+
+        // C, V, and on NMOS also N and Z, are those of the binary subtraction.
+        let binary = a as i16 - val as i16 - borrow;
+        let signed = a as i8 as i16 - val as i8 as i16 - borrow;
+        self.p.set(Flag::Carry, binary >= 0);
+        self.p.set(Flag::Overflow, !(-128..=127).contains(&signed));
+        self.assign_basic_flags(binary as u8);
+
+        let mut low = (a & 0x0f) as i16 - (val & 0x0f) as i16 - borrow;
+        let result = if self.variant == CpuVariant::Cmos65C02 {
+            let mut result = binary;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if low < 0 {
+                result -= 0x06;
+            }
+            result
+        } else {
+            if low < 0 {
+                low = ((low - 0x06) & 0x0f) - 0x10;
+            }
+            let mut result = (a & 0xf0) as i16 - (val & 0xf0) as i16 + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+            result
+        };
+        self.a = result as u8;
+
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.assign_basic_flags(self.a);
+            self.decimal_fixup_cycle();
         }
     }
 
-    fn get_carry_flag(&self) -> u8 {
-        self.p & 0b00000001
+    /// The extra cycle the 65C02 takes to fix up the flags in decimal mode,
+    /// spent reading the operand address again.
+    fn decimal_fixup_cycle(&mut self) {
+        let addr = self.accesses.last().map_or(self.pc, |access| access.addr);
+        self.read(addr);
+        self.cycles += 1;
+    }
+
+    fn cmp(&mut self,val_1: u8, val_2: u8) {
+        self.p.set(Flag::Carry, val_1 >= val_2);
+        self.assign_basic_flags(val_1.wrapping_sub(val_2));
     }
 
-    fn set_zero_flag(&mut self, zero: bool) {
-        match zero {
-            true => self.p = self.p | 0b00000010,
-            false => self.p = self.p & 0b11111101
+    fn branch_jump(&mut self, displacement: i8) {
+        let from = self.pc;
+        self.pc = self.pc.wrapping_add(displacement as u16);
+
+        // A taken branch costs one cycle, spent reading the next opcode, and
+        // another one to fix up PCH, spent reading from the wrong page.
+        self.read(from);
+        self.cycles += 1;
+        if from & 0xff00 != self.pc & 0xff00 {
+            self.read((from & 0xff00) | (self.pc & 0x00ff));
+            self.cycles += 1;
         }
     }
 
-    fn get_zero_flag(&self) -> u8 {
-        (self.p & 0b00000010) >> 1
+    /// Fetches the operand of the current instruction and resolves it to the
+    /// address it refers to, making the dummy reads the addressing mode does
+    /// along the way. `kind` is how the instruction uses the address: reads
+    /// only touch the wrong page when indexing crosses one, while writes and
+    /// read-modify-writes always do.
+    fn operand_addr(&mut self, mode: AddressingMode, kind: AccessKind) -> u16 {
+        match mode {
+            AddressingMode::Immediate | AddressingMode::Relative => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+                addr
+            }
+            AddressingMode::ZeroPage => self.next_instruction() as u16,
+            AddressingMode::ZeroPageX => {
+                let base = self.next_instruction();
+                self.read(base as u16);
+                (Wrapping(base) + Wrapping(self.x)).0 as u16
+            }
+            AddressingMode::ZeroPageY => {
+                let base = self.next_instruction();
+                self.read(base as u16);
+                (Wrapping(base) + Wrapping(self.y)).0 as u16
+            }
+            AddressingMode::Absolute => self.get_absolute_addr(),
+            AddressingMode::AbsoluteX => {
+                let base = self.get_absolute_addr();
+                self.indexed(base, self.x, kind)
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.get_absolute_addr();
+                self.indexed(base, self.y, kind)
+            }
+            AddressingMode::Indirect => self.get_indirect_addr(),
+            AddressingMode::IndirectX => {
+                let ind_addr = self.next_instruction();
+                self.read(ind_addr as u16);
+                self.read_zero_page_pointer((Wrapping(ind_addr) + Wrapping(self.x)).0)
+            }
+            AddressingMode::IndirectY => {
+                let ind_addr = self.next_instruction();
+                let base = self.read_zero_page_pointer(ind_addr);
+                self.indexed(base, self.y, kind)
+            }
+            AddressingMode::Implied | AddressingMode::Accumulator => {
+                unreachable!("{mode:?} has no operand address")
+            }
+        }
     }
 
-    fn get_negative_flag(&self) -> u8 {
-        (self.p & 0b10000000) >> 7
+    /// Reads the value an instruction operates on. Indexed reads that cross
+    /// a page take an extra cycle.
+    fn read_operand(&mut self, mode: AddressingMode) -> u8 {
+        if mode == AddressingMode::Accumulator {
+            return self.a;
+        }
+
+        let addr = self.operand_addr(mode, AccessKind::Read);
+        let val = self.read(addr);
+        if self.page_crossed {
+            self.cycles += 1;
+        }
+        val
     }
 
-    fn get_overflow_flag(&self) -> u8 {
-        (self.p & 0b01000000) >> 6
+    fn write_operand(&mut self, mode: AddressingMode, val: u8) {
+        let addr = self.operand_addr(mode, AccessKind::Write);
+        self.write(val, addr);
     }
 
-    fn set_interrupt_disable(&mut self, interrupt_disable: bool) {
-        match interrupt_disable {
-            true => self.p = self.p | 0b00000100,
-            false => self.p = self.p & 0b11111011
+    /// Read-modify-write on the accumulator or memory, setting N and Z from
+    /// the result, which is returned. Memory gets the unmodified value
+    /// written back while the ALU works.
+    fn modify<F: FnOnce(&mut Cpu<B>, u8) -> u8>(&mut self, mode: AddressingMode, f: F) -> u8 {
+        if mode == AddressingMode::Accumulator {
+            let val = f(self, self.a);
+            self.lda(val);
+            return val;
         }
+
+        let addr = self.operand_addr(mode, AccessKind::Write);
+        let val = self.read(addr);
+        self.write(val, addr);
+        let val = f(self, val);
+        self.assign_basic_flags(val);
+        self.write(val, addr);
+        val
     }
 
-    fn set_decimal_mode(&mut self, decimal_mode: bool) {
-        match decimal_mode {
-            true => self.p = self.p | 0b00001000,
-            false => self.p = self.p & 0b11110111
+    /// Adds an index register to `base`, wrapping around $FFFF, and records
+    /// whether that carried into the high byte. The 6502 first reads from
+    /// the address with the carry left out, a dummy read unless `kind` is a
+    /// read that did not cross a page.
+    fn indexed(&mut self, base: u16, index: u8, kind: AccessKind) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        self.page_crossed = base & 0xff00 != addr & 0xff00;
+        if self.page_crossed || kind == AccessKind::Write {
+            self.read((base & 0xff00) | (addr & 0x00ff));
         }
+        addr
     }
 
-    fn set_break_command(&mut self, break_command: bool) {
-        match break_command {
-            true => self.p = self.p | 0b00010000,
-            false => self.p = self.p & 0b11101111
-        }
+    /// Reads a pointer from zero page. The high byte of a pointer at $FF
+    /// comes from $00, not $0100.
+    fn read_zero_page_pointer(&mut self, addr: u8) -> u16 {
+        let lo = self.read(addr as u16) as u16;
+        let hi = self.read(addr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
     }
 
-    fn set_overflow(&mut self, overflow: bool) {
-        match overflow {
-            true => self.p = self.p | 0b01000000,
-            false => self.p = self.p & 0b10111111
-        }
+    fn get_absolute_addr(&mut self) -> u16 {
+        let mut addr  = self.next_instruction() as u16;
+        addr += (self.next_instruction() as u16) << 8;
+        addr
     }
 
-    fn set_negative(&mut self, negative: bool) {
-        match negative {
-            true => self.p = self.p | 0b10000000,
-            false => self.p = self.p & 0b01111111
-        }
+    /// JMP ($xxFF) fetches the high byte from $xx00 instead of crossing into
+    /// the next page, except on the 65C02 which fixed the bug.
+    fn get_indirect_addr(&mut self) -> u16 {
+        let addr = self.get_absolute_addr();
+        let hi_addr = if self.variant == CpuVariant::Cmos65C02 {
+            addr.wrapping_add(1)
+        } else {
+            (addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff)
+        };
+        let addr1 = self.read(addr) as u16;
+        let addr2 = (self.read(hi_addr) as u16 )<< 8;
+        addr1 + addr2
+    }
+
+    fn stack_push(&mut self, val: u8) {
+        let addr = 0x0100 + (self.sp as u16);
+        self.write(val, addr);
+        self.sp = self.sp.wrapping_sub(1);
     }
 
-    pub fn print_mem(&self) {
-        // println!("=======================RAM=======================");
-        // println!("{:x?}", &self.memory.data[..0x800]);
-        // println!("=======================RAM MIRRORS=======================");
-        // println!("{:x?}", &self.memory.data[0x800..0x2000]);
-        // println!("=======================PPU REGISTERS=======================");
-        // println!("{:x?}", &self.memory.data[0x2000..0x2008]);
-        // println!("=======================PPU REGISTERS MIRRORS=======================");
-        // println!("{:x?}", &self.memory.data[0x2008..0x4000]);
-        // println!("=======================APU AND I/O REGISTERS=======================");
-        // println!("{:x?}", &self.memory.data[0x4000..0x4018]);
-        // println!("=======================APU AND I/O FUNCTIONALITY=======================");
-        // println!("{:x?}", &self.memory.data[0x4018..0x4020]);
-        println!("=======================PRG ROM, PRG RAM AND MAPPER REGISTERS=======================");
-        println!("{:x?}", &self.memory.prg_rom);
+    /// The read of the stack a pull makes before incrementing SP.
+    fn stack_dummy_read(&mut self) {
+        self.read(0x0100 + (self.sp as u16));
     }
+
+    fn stack_pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read(0x0100 + (self.sp as u16))
+    }
+
 }
\ No newline at end of file
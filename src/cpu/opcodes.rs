@@ -0,0 +1,762 @@
+use super::{AccessKind, Cpu, Flag, Status, IRQ_VECTOR};
+use crate::bus::Bus;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddressingMode {
+    /// Number of operand bytes following the opcode.
+    pub const fn operand_len(self) -> u16 {
+        use AddressingMode::*;
+        match self {
+            Implied | Accumulator => 0,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | IndirectX | IndirectY | Relative => 1,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 2,
+        }
+    }
+}
+
+pub(crate) type Handler = fn(&mut Cpu<dyn Bus>, AddressingMode);
+
+/// Everything the CPU, the disassembler and the tracer need to know about an
+/// opcode.
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Instruction length in bytes, including the opcode.
+    pub len: u16,
+    /// Base cycle count, before page-crossing and branch penalties.
+    pub cycles: u8,
+    /// Whether the opcode is part of the documented instruction set.
+    pub official: bool,
+    /// Whether the result depends on analog effects and varies between chips.
+    pub unstable: bool,
+    pub(crate) handler: Handler,
+}
+
+const fn op(mnemonic: &'static str, mode: AddressingMode, cycles: u8, handler: Handler) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        len: 1 + mode.operand_len(),
+        cycles,
+        official: true,
+        unstable: false,
+        handler,
+    }
+}
+
+const fn ill(mnemonic: &'static str, mode: AddressingMode, cycles: u8, handler: Handler) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        len: 1 + mode.operand_len(),
+        cycles,
+        official: false,
+        unstable: false,
+        handler,
+    }
+}
+
+const fn uns(mnemonic: &'static str, mode: AddressingMode, cycles: u8, handler: Handler) -> Opcode {
+    Opcode {
+        unstable: true,
+        ..ill(mnemonic, mode, cycles, handler)
+    }
+}
+
+use AddressingMode::{
+    Absolute as ABS, AbsoluteX as ABX, AbsoluteY as ABY, Accumulator as ACC, Immediate as IMM,
+    Implied as IMP, Indirect as IND, IndirectX as IZX, IndirectY as IZY, Relative as REL,
+    ZeroPage as ZP, ZeroPageX as ZPX, ZeroPageY as ZPY,
+};
+
+pub static OPCODES: [Opcode; 256] = [
+    op("BRK", IMP, 7, brk), // 0x00
+    op("ORA", IZX, 6, ora), // 0x01
+    ill("JAM", IMP, 2, jam), // 0x02
+    ill("SLO", IZX, 8, slo), // 0x03
+    ill("NOP", ZP, 3, nop), // 0x04
+    op("ORA", ZP, 3, ora), // 0x05
+    op("ASL", ZP, 5, asl), // 0x06
+    ill("SLO", ZP, 5, slo), // 0x07
+    op("PHP", IMP, 3, php), // 0x08
+    op("ORA", IMM, 2, ora), // 0x09
+    op("ASL", ACC, 2, asl), // 0x0a
+    ill("ANC", IMM, 2, anc), // 0x0b
+    ill("NOP", ABS, 4, nop), // 0x0c
+    op("ORA", ABS, 4, ora), // 0x0d
+    op("ASL", ABS, 6, asl), // 0x0e
+    ill("SLO", ABS, 6, slo), // 0x0f
+    op("BPL", REL, 2, bpl), // 0x10
+    op("ORA", IZY, 5, ora), // 0x11
+    ill("JAM", IMP, 2, jam), // 0x12
+    ill("SLO", IZY, 8, slo), // 0x13
+    ill("NOP", ZPX, 4, nop), // 0x14
+    op("ORA", ZPX, 4, ora), // 0x15
+    op("ASL", ZPX, 6, asl), // 0x16
+    ill("SLO", ZPX, 6, slo), // 0x17
+    op("CLC", IMP, 2, clc), // 0x18
+    op("ORA", ABY, 4, ora), // 0x19
+    ill("NOP", IMP, 2, nop), // 0x1a
+    ill("SLO", ABY, 7, slo), // 0x1b
+    ill("NOP", ABX, 4, nop), // 0x1c
+    op("ORA", ABX, 4, ora), // 0x1d
+    op("ASL", ABX, 7, asl), // 0x1e
+    ill("SLO", ABX, 7, slo), // 0x1f
+    op("JSR", ABS, 6, jsr), // 0x20
+    op("AND", IZX, 6, and), // 0x21
+    ill("JAM", IMP, 2, jam), // 0x22
+    ill("RLA", IZX, 8, rla), // 0x23
+    op("BIT", ZP, 3, bit), // 0x24
+    op("AND", ZP, 3, and), // 0x25
+    op("ROL", ZP, 5, rol), // 0x26
+    ill("RLA", ZP, 5, rla), // 0x27
+    op("PLP", IMP, 4, plp), // 0x28
+    op("AND", IMM, 2, and), // 0x29
+    op("ROL", ACC, 2, rol), // 0x2a
+    ill("ANC", IMM, 2, anc), // 0x2b
+    op("BIT", ABS, 4, bit), // 0x2c
+    op("AND", ABS, 4, and), // 0x2d
+    op("ROL", ABS, 6, rol), // 0x2e
+    ill("RLA", ABS, 6, rla), // 0x2f
+    op("BMI", REL, 2, bmi), // 0x30
+    op("AND", IZY, 5, and), // 0x31
+    ill("JAM", IMP, 2, jam), // 0x32
+    ill("RLA", IZY, 8, rla), // 0x33
+    ill("NOP", ZPX, 4, nop), // 0x34
+    op("AND", ZPX, 4, and), // 0x35
+    op("ROL", ZPX, 6, rol), // 0x36
+    ill("RLA", ZPX, 6, rla), // 0x37
+    op("SEC", IMP, 2, sec), // 0x38
+    op("AND", ABY, 4, and), // 0x39
+    ill("NOP", IMP, 2, nop), // 0x3a
+    ill("RLA", ABY, 7, rla), // 0x3b
+    ill("NOP", ABX, 4, nop), // 0x3c
+    op("AND", ABX, 4, and), // 0x3d
+    op("ROL", ABX, 7, rol), // 0x3e
+    ill("RLA", ABX, 7, rla), // 0x3f
+    op("RTI", IMP, 6, rti), // 0x40
+    op("EOR", IZX, 6, eor), // 0x41
+    ill("JAM", IMP, 2, jam), // 0x42
+    ill("SRE", IZX, 8, sre), // 0x43
+    ill("NOP", ZP, 3, nop), // 0x44
+    op("EOR", ZP, 3, eor), // 0x45
+    op("LSR", ZP, 5, lsr), // 0x46
+    ill("SRE", ZP, 5, sre), // 0x47
+    op("PHA", IMP, 3, pha), // 0x48
+    op("EOR", IMM, 2, eor), // 0x49
+    op("LSR", ACC, 2, lsr), // 0x4a
+    ill("ALR", IMM, 2, alr), // 0x4b
+    op("JMP", ABS, 3, jmp), // 0x4c
+    op("EOR", ABS, 4, eor), // 0x4d
+    op("LSR", ABS, 6, lsr), // 0x4e
+    ill("SRE", ABS, 6, sre), // 0x4f
+    op("BVC", REL, 2, bvc), // 0x50
+    op("EOR", IZY, 5, eor), // 0x51
+    ill("JAM", IMP, 2, jam), // 0x52
+    ill("SRE", IZY, 8, sre), // 0x53
+    ill("NOP", ZPX, 4, nop), // 0x54
+    op("EOR", ZPX, 4, eor), // 0x55
+    op("LSR", ZPX, 6, lsr), // 0x56
+    ill("SRE", ZPX, 6, sre), // 0x57
+    op("CLI", IMP, 2, cli), // 0x58
+    op("EOR", ABY, 4, eor), // 0x59
+    ill("NOP", IMP, 2, nop), // 0x5a
+    ill("SRE", ABY, 7, sre), // 0x5b
+    ill("NOP", ABX, 4, nop), // 0x5c
+    op("EOR", ABX, 4, eor), // 0x5d
+    op("LSR", ABX, 7, lsr), // 0x5e
+    ill("SRE", ABX, 7, sre), // 0x5f
+    op("RTS", IMP, 6, rts), // 0x60
+    op("ADC", IZX, 6, adc), // 0x61
+    ill("JAM", IMP, 2, jam), // 0x62
+    ill("RRA", IZX, 8, rra), // 0x63
+    ill("NOP", ZP, 3, nop), // 0x64
+    op("ADC", ZP, 3, adc), // 0x65
+    op("ROR", ZP, 5, ror), // 0x66
+    ill("RRA", ZP, 5, rra), // 0x67
+    op("PLA", IMP, 4, pla), // 0x68
+    op("ADC", IMM, 2, adc), // 0x69
+    op("ROR", ACC, 2, ror), // 0x6a
+    ill("ARR", IMM, 2, arr), // 0x6b
+    op("JMP", IND, 5, jmp), // 0x6c
+    op("ADC", ABS, 4, adc), // 0x6d
+    op("ROR", ABS, 6, ror), // 0x6e
+    ill("RRA", ABS, 6, rra), // 0x6f
+    op("BVS", REL, 2, bvs), // 0x70
+    op("ADC", IZY, 5, adc), // 0x71
+    ill("JAM", IMP, 2, jam), // 0x72
+    ill("RRA", IZY, 8, rra), // 0x73
+    ill("NOP", ZPX, 4, nop), // 0x74
+    op("ADC", ZPX, 4, adc), // 0x75
+    op("ROR", ZPX, 6, ror), // 0x76
+    ill("RRA", ZPX, 6, rra), // 0x77
+    op("SEI", IMP, 2, sei), // 0x78
+    op("ADC", ABY, 4, adc), // 0x79
+    ill("NOP", IMP, 2, nop), // 0x7a
+    ill("RRA", ABY, 7, rra), // 0x7b
+    ill("NOP", ABX, 4, nop), // 0x7c
+    op("ADC", ABX, 4, adc), // 0x7d
+    op("ROR", ABX, 7, ror), // 0x7e
+    ill("RRA", ABX, 7, rra), // 0x7f
+    ill("NOP", IMM, 2, nop), // 0x80
+    op("STA", IZX, 6, sta), // 0x81
+    ill("NOP", IMM, 2, nop), // 0x82
+    ill("SAX", IZX, 6, sax), // 0x83
+    op("STY", ZP, 3, sty), // 0x84
+    op("STA", ZP, 3, sta), // 0x85
+    op("STX", ZP, 3, stx), // 0x86
+    ill("SAX", ZP, 3, sax), // 0x87
+    op("DEY", IMP, 2, dey), // 0x88
+    ill("NOP", IMM, 2, nop), // 0x89
+    op("TXA", IMP, 2, txa), // 0x8a
+    uns("ANE", IMM, 2, ane), // 0x8b
+    op("STY", ABS, 4, sty), // 0x8c
+    op("STA", ABS, 4, sta), // 0x8d
+    op("STX", ABS, 4, stx), // 0x8e
+    ill("SAX", ABS, 4, sax), // 0x8f
+    op("BCC", REL, 2, bcc), // 0x90
+    op("STA", IZY, 6, sta), // 0x91
+    ill("JAM", IMP, 2, jam), // 0x92
+    uns("SHA", IZY, 6, sha), // 0x93
+    op("STY", ZPX, 4, sty), // 0x94
+    op("STA", ZPX, 4, sta), // 0x95
+    op("STX", ZPY, 4, stx), // 0x96
+    ill("SAX", ZPY, 4, sax), // 0x97
+    op("TYA", IMP, 2, tya), // 0x98
+    op("STA", ABY, 5, sta), // 0x99
+    op("TXS", IMP, 2, txs), // 0x9a
+    uns("TAS", ABY, 5, tas), // 0x9b
+    uns("SHY", ABX, 5, shy), // 0x9c
+    op("STA", ABX, 5, sta), // 0x9d
+    uns("SHX", ABY, 5, shx), // 0x9e
+    uns("SHA", ABY, 5, sha), // 0x9f
+    op("LDY", IMM, 2, ldy), // 0xa0
+    op("LDA", IZX, 6, lda), // 0xa1
+    op("LDX", IMM, 2, ldx), // 0xa2
+    ill("LAX", IZX, 6, lax), // 0xa3
+    op("LDY", ZP, 3, ldy), // 0xa4
+    op("LDA", ZP, 3, lda), // 0xa5
+    op("LDX", ZP, 3, ldx), // 0xa6
+    ill("LAX", ZP, 3, lax), // 0xa7
+    op("TAY", IMP, 2, tay), // 0xa8
+    op("LDA", IMM, 2, lda), // 0xa9
+    op("TAX", IMP, 2, tax), // 0xaa
+    uns("LXA", IMM, 2, lxa), // 0xab
+    op("LDY", ABS, 4, ldy), // 0xac
+    op("LDA", ABS, 4, lda), // 0xad
+    op("LDX", ABS, 4, ldx), // 0xae
+    ill("LAX", ABS, 4, lax), // 0xaf
+    op("BCS", REL, 2, bcs), // 0xb0
+    op("LDA", IZY, 5, lda), // 0xb1
+    ill("JAM", IMP, 2, jam), // 0xb2
+    ill("LAX", IZY, 5, lax), // 0xb3
+    op("LDY", ZPX, 4, ldy), // 0xb4
+    op("LDA", ZPX, 4, lda), // 0xb5
+    op("LDX", ZPY, 4, ldx), // 0xb6
+    ill("LAX", ZPY, 4, lax), // 0xb7
+    op("CLV", IMP, 2, clv), // 0xb8
+    op("LDA", ABY, 4, lda), // 0xb9
+    op("TSX", IMP, 2, tsx), // 0xba
+    ill("LAS", ABY, 4, las), // 0xbb
+    op("LDY", ABX, 4, ldy), // 0xbc
+    op("LDA", ABX, 4, lda), // 0xbd
+    op("LDX", ABY, 4, ldx), // 0xbe
+    ill("LAX", ABY, 4, lax), // 0xbf
+    op("CPY", IMM, 2, cpy), // 0xc0
+    op("CMP", IZX, 6, cmp), // 0xc1
+    ill("NOP", IMM, 2, nop), // 0xc2
+    ill("DCP", IZX, 8, dcp), // 0xc3
+    op("CPY", ZP, 3, cpy), // 0xc4
+    op("CMP", ZP, 3, cmp), // 0xc5
+    op("DEC", ZP, 5, dec), // 0xc6
+    ill("DCP", ZP, 5, dcp), // 0xc7
+    op("INY", IMP, 2, iny), // 0xc8
+    op("CMP", IMM, 2, cmp), // 0xc9
+    op("DEX", IMP, 2, dex), // 0xca
+    ill("SBX", IMM, 2, sbx), // 0xcb
+    op("CPY", ABS, 4, cpy), // 0xcc
+    op("CMP", ABS, 4, cmp), // 0xcd
+    op("DEC", ABS, 6, dec), // 0xce
+    ill("DCP", ABS, 6, dcp), // 0xcf
+    op("BNE", REL, 2, bne), // 0xd0
+    op("CMP", IZY, 5, cmp), // 0xd1
+    ill("JAM", IMP, 2, jam), // 0xd2
+    ill("DCP", IZY, 8, dcp), // 0xd3
+    ill("NOP", ZPX, 4, nop), // 0xd4
+    op("CMP", ZPX, 4, cmp), // 0xd5
+    op("DEC", ZPX, 6, dec), // 0xd6
+    ill("DCP", ZPX, 6, dcp), // 0xd7
+    op("CLD", IMP, 2, cld), // 0xd8
+    op("CMP", ABY, 4, cmp), // 0xd9
+    ill("NOP", IMP, 2, nop), // 0xda
+    ill("DCP", ABY, 7, dcp), // 0xdb
+    ill("NOP", ABX, 4, nop), // 0xdc
+    op("CMP", ABX, 4, cmp), // 0xdd
+    op("DEC", ABX, 7, dec), // 0xde
+    ill("DCP", ABX, 7, dcp), // 0xdf
+    op("CPX", IMM, 2, cpx), // 0xe0
+    op("SBC", IZX, 6, sbc), // 0xe1
+    ill("NOP", IMM, 2, nop), // 0xe2
+    ill("ISB", IZX, 8, isb), // 0xe3
+    op("CPX", ZP, 3, cpx), // 0xe4
+    op("SBC", ZP, 3, sbc), // 0xe5
+    op("INC", ZP, 5, inc), // 0xe6
+    ill("ISB", ZP, 5, isb), // 0xe7
+    op("INX", IMP, 2, inx), // 0xe8
+    op("SBC", IMM, 2, sbc), // 0xe9
+    op("NOP", IMP, 2, nop), // 0xea
+    ill("SBC", IMM, 2, sbc), // 0xeb
+    op("CPX", ABS, 4, cpx), // 0xec
+    op("SBC", ABS, 4, sbc), // 0xed
+    op("INC", ABS, 6, inc), // 0xee
+    ill("ISB", ABS, 6, isb), // 0xef
+    op("BEQ", REL, 2, beq), // 0xf0
+    op("SBC", IZY, 5, sbc), // 0xf1
+    ill("JAM", IMP, 2, jam), // 0xf2
+    ill("ISB", IZY, 8, isb), // 0xf3
+    ill("NOP", ZPX, 4, nop), // 0xf4
+    op("SBC", ZPX, 4, sbc), // 0xf5
+    op("INC", ZPX, 6, inc), // 0xf6
+    ill("ISB", ZPX, 6, isb), // 0xf7
+    op("SED", IMP, 2, sed), // 0xf8
+    op("SBC", ABY, 4, sbc), // 0xf9
+    ill("NOP", IMP, 2, nop), // 0xfa
+    ill("ISB", ABY, 7, isb), // 0xfb
+    ill("NOP", ABX, 4, nop), // 0xfc
+    op("SBC", ABX, 4, sbc), // 0xfd
+    op("INC", ABX, 7, inc), // 0xfe
+    ill("ISB", ABX, 7, isb), // 0xff
+];
+
+fn brk(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    // BRK skips the byte after it, so the return address is PC + 2.
+    cpu.pc = cpu.pc.wrapping_add(1);
+    cpu.push_interrupt_frame(IRQ_VECTOR, true);
+}
+
+fn rti(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.stack_dummy_read();
+    cpu.p = Status::from_byte(cpu.stack_pull());
+    let lo = cpu.stack_pull() as u16;
+    let hi = cpu.stack_pull() as u16;
+    cpu.pc = (hi << 8) | lo;
+}
+
+fn nop(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    // Unofficial NOPs with an operand still perform the read.
+    if mode != AddressingMode::Implied {
+        cpu.read_operand(mode);
+    }
+}
+
+fn lda(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.lda(val);
+}
+
+fn ldx(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.ldx(val);
+}
+
+fn ldy(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.ldy(val);
+}
+
+fn sta(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.write_operand(mode, cpu.a);
+}
+
+fn stx(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.write_operand(mode, cpu.x);
+}
+
+fn sty(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.write_operand(mode, cpu.y);
+}
+
+fn tax(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldx(cpu.a);
+}
+
+fn tay(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldy(cpu.a);
+}
+
+fn tsx(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldx(cpu.sp);
+}
+
+fn txa(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.lda(cpu.x);
+}
+
+fn txs(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.sp = cpu.x;
+}
+
+fn tya(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.lda(cpu.y);
+}
+
+fn pha(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.stack_push(cpu.a);
+}
+
+fn php(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.stack_push(cpu.p.to_byte(true));
+}
+
+fn pla(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.stack_dummy_read();
+    let val = cpu.stack_pull();
+    cpu.lda(val);
+}
+
+fn plp(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.stack_dummy_read();
+    cpu.p = Status::from_byte(cpu.stack_pull());
+}
+
+fn and(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) & cpu.a;
+    cpu.lda(val);
+}
+
+fn eor(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) ^ cpu.a;
+    cpu.lda(val);
+}
+
+fn ora(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) | cpu.a;
+    cpu.lda(val);
+}
+
+fn bit(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.bit_test(val);
+}
+
+fn adc(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.adc(val);
+}
+
+fn sbc(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.sbc(val);
+}
+
+fn cmp(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.cmp(cpu.a, val);
+}
+
+fn cpx(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.cmp(cpu.x, val);
+}
+
+fn cpy(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.cmp(cpu.y, val);
+}
+
+fn inc(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.modify(mode, |_, val| val.wrapping_add(1));
+}
+
+fn dec(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.modify(mode, |_, val| val.wrapping_sub(1));
+}
+
+fn inx(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldx(cpu.x.wrapping_add(1));
+}
+
+fn iny(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldy(cpu.y.wrapping_add(1));
+}
+
+fn dex(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldx(cpu.x.wrapping_sub(1));
+}
+
+fn dey(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.ldy(cpu.y.wrapping_sub(1));
+}
+
+fn shift_left(cpu: &mut Cpu<dyn Bus>, val: u8) -> u8 {
+    cpu.p.set(Flag::Carry, val & 0b10000000 == 0b10000000);
+    val << 1
+}
+
+fn shift_right(cpu: &mut Cpu<dyn Bus>, val: u8) -> u8 {
+    cpu.p.set(Flag::Carry, val & 0b00000001 == 0b00000001);
+    val >> 1
+}
+
+fn rotate_left(cpu: &mut Cpu<dyn Bus>, val: u8) -> u8 {
+    let carry_in = cpu.p.get(Flag::Carry) as u8;
+    cpu.p.set(Flag::Carry, val & 0b10000000 == 0b10000000);
+    (val << 1) | carry_in
+}
+
+fn rotate_right(cpu: &mut Cpu<dyn Bus>, val: u8) -> u8 {
+    let carry_in = cpu.p.get(Flag::Carry) as u8;
+    cpu.p.set(Flag::Carry, val & 0b00000001 == 0b00000001);
+    (val >> 1) | (carry_in << 7)
+}
+
+fn asl(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.modify(mode, shift_left);
+}
+
+fn lsr(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.modify(mode, shift_right);
+}
+
+fn rol(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.modify(mode, rotate_left);
+}
+
+fn ror(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.modify(mode, rotate_right);
+}
+
+fn jmp(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.pc = cpu.operand_addr(mode, AccessKind::Read);
+}
+
+fn jsr(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    // JSR pushes the return address between fetching the low and high
+    // bytes of the target, so the address pushed is that of the last byte
+    // of the JSR.
+    let lo = cpu.next_instruction() as u16;
+    cpu.stack_dummy_read();
+    cpu.stack_push((cpu.pc >> 8) as u8);
+    cpu.stack_push((cpu.pc & 0x00ff) as u8);
+    let hi = cpu.next_instruction() as u16;
+    cpu.pc = (hi << 8) | lo;
+}
+
+fn rts(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.stack_dummy_read();
+    let lo = cpu.stack_pull() as u16;
+    let hi = cpu.stack_pull() as u16;
+    cpu.pc = (hi << 8) | lo;
+    cpu.next_instruction();
+}
+
+/// Branches if `flag` is `set`.
+fn branch(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode, flag: Flag, set: bool) {
+    let displacement = cpu.read_operand(mode) as i8;
+    if cpu.p.get(flag) == set {
+        cpu.branch_jump(displacement);
+    }
+}
+
+fn bcc(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Carry, false);
+}
+
+fn bcs(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Carry, true);
+}
+
+fn beq(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Zero, true);
+}
+
+fn bmi(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Negative, true);
+}
+
+fn bne(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Zero, false);
+}
+
+fn bpl(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Negative, false);
+}
+
+fn bvc(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Overflow, false);
+}
+
+fn bvs(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    branch(cpu, mode, Flag::Overflow, true);
+}
+
+fn clc(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::Carry, false);
+}
+
+fn cld(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::Decimal, false);
+}
+
+fn cli(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::InterruptDisable, false);
+}
+
+fn clv(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::Overflow, false);
+}
+
+fn sec(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::Carry, true);
+}
+
+fn sed(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::Decimal, true);
+}
+
+fn sei(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.p.set(Flag::InterruptDisable, true);
+}
+
+fn jam(cpu: &mut Cpu<dyn Bus>, _: AddressingMode) {
+    cpu.jam();
+}
+
+fn slo(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.modify(mode, shift_left);
+    cpu.lda(cpu.a | val);
+}
+
+fn rla(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.modify(mode, rotate_left);
+    cpu.lda(cpu.a & val);
+}
+
+fn sre(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.modify(mode, shift_right);
+    cpu.lda(cpu.a ^ val);
+}
+
+fn rra(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.modify(mode, rotate_right);
+    cpu.adc(val);
+}
+
+fn dcp(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.modify(mode, |_, val| val.wrapping_sub(1));
+    cpu.cmp(cpu.a, val);
+}
+
+fn isb(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.modify(mode, |_, val| val.wrapping_add(1));
+    cpu.sbc(val);
+}
+
+fn sax(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.write_operand(mode, cpu.a & cpu.x);
+}
+
+fn lax(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.lda(val);
+    cpu.ldx(val);
+}
+
+fn las(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) & cpu.sp;
+    cpu.sp = val;
+    cpu.lda(val);
+    cpu.ldx(val);
+}
+
+fn anc(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) & cpu.a;
+    cpu.lda(val);
+    cpu.p.set(Flag::Carry, val & 0b10000000 == 0b10000000);
+}
+
+fn alr(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) & cpu.a;
+    let val = shift_right(cpu, val);
+    cpu.lda(val);
+}
+
+fn arr(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode) & cpu.a;
+    let val = (val >> 1) | ((cpu.p.get(Flag::Carry) as u8) << 7);
+    cpu.lda(val);
+    cpu.p.set(Flag::Carry, val & 0b01000000 == 0b01000000);
+    cpu.p.set(Flag::Overflow, ((val >> 6) ^ (val >> 5)) & 1 == 1);
+}
+
+fn sbx(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    let and = cpu.a & cpu.x;
+    cpu.p.set(Flag::Carry, and >= val);
+    cpu.ldx(and.wrapping_sub(val));
+}
+
+fn ane(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.read_operand(mode);
+    cpu.lda((cpu.a | cpu.unstable_magic()) & cpu.x & val);
+}
+
+fn lxa(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = (cpu.a | cpu.unstable_magic()) & cpu.read_operand(mode);
+    cpu.lda(val);
+    cpu.ldx(val);
+}
+
+/// Stores `val & (H + 1)`, H being the high byte of the unindexed address.
+/// When indexing crosses a page the stored value also replaces the high
+/// byte of the address written to.
+fn store_and_high(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode, val: u8) {
+    let index = if mode == AddressingMode::AbsoluteX { cpu.x } else { cpu.y };
+    let addr = cpu.operand_addr(mode, AccessKind::Write);
+    let high = (addr.wrapping_sub(index as u16) >> 8) as u8;
+    let val = val & high.wrapping_add(1);
+
+    let addr = if cpu.page_crossed {
+        ((val as u16) << 8) | (addr & 0x00ff)
+    } else {
+        addr
+    };
+    cpu.write(val, addr);
+}
+
+fn sha(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.a & cpu.x;
+    store_and_high(cpu, mode, val);
+}
+
+fn shx(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.x;
+    store_and_high(cpu, mode, val);
+}
+
+fn shy(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    let val = cpu.y;
+    store_and_high(cpu, mode, val);
+}
+
+fn tas(cpu: &mut Cpu<dyn Bus>, mode: AddressingMode) {
+    cpu.sp = cpu.a & cpu.x;
+    let val = cpu.sp;
+    store_and_high(cpu, mode, val);
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flag {
+    Carry,
+    Zero,
+    InterruptDisable,
+    Decimal,
+    Overflow,
+    Negative,
+}
+
+impl Flag {
+    const fn mask(self) -> u8 {
+        match self {
+            Flag::Carry => 0b00000001,
+            Flag::Zero => 0b00000010,
+            Flag::InterruptDisable => 0b00000100,
+            Flag::Decimal => 0b00001000,
+            Flag::Overflow => 0b01000000,
+            Flag::Negative => 0b10000000,
+        }
+    }
+}
+
+/// Bit 5 of P always reads back as set.
+const UNUSED: u8 = 0b00100000;
+/// The B "flag" only exists in copies of P pushed by BRK and PHP.
+const BREAK: u8 = 0b00010000;
+
+/// The processor status register P. Only the six real flags are stored; bits
+/// 4 and 5 appear when P is pushed to the stack.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Status(u8);
+
+impl Status {
+    pub fn get(self, flag: Flag) -> bool {
+        self.0 & flag.mask() != 0
+    }
+
+    pub fn set(&mut self, flag: Flag, value: bool) {
+        if value {
+            self.0 |= flag.mask();
+        } else {
+            self.0 &= !flag.mask();
+        }
+    }
+
+    /// P as pushed by an instruction (PHP, BRK), with B set, or by an IRQ or
+    /// NMI, with B clear.
+    pub fn to_byte(self, from_instruction: bool) -> u8 {
+        if from_instruction {
+            self.0 | UNUSED | BREAK
+        } else {
+            self.0 | UNUSED
+        }
+    }
+
+    /// P as pulled by PLP and RTI, which ignore bits 4 and 5.
+    pub fn from_byte(val: u8) -> Status {
+        Status(val & !(UNUSED | BREAK))
+    }
+}
+
+impl fmt::Debug for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, flag)| {
+                if self.to_byte(false) & (0x80 >> i) != 0 {
+                    flag
+                } else {
+                    flag.to_ascii_lowercase()
+                }
+            })
+            .collect();
+        write!(f, "Status({flags})")
+    }
+}
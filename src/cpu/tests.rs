@@ -0,0 +1,984 @@
+use super::*;
+use crate::bus::FlatBus;
+use AccessKind::{Read as R, Write as W};
+
+/// A CPU on a `FlatBus`, configured by `configure`, that starts at `addr`,
+/// where `program` is.
+fn cpu_with(configure: impl FnOnce(CpuBuilder<FlatBus>) -> CpuBuilder<FlatBus>, addr: u16, program: &[u8]) -> Cpu<FlatBus> {
+    let mut cpu = configure(CpuBuilder::new(FlatBus::new()).start_pc(addr)).build();
+    cpu.bus.load(addr, program);
+    cpu
+}
+
+/// A CPU on a `FlatBus` with the default configuration that starts at
+/// `addr`, where `program` is.
+fn cpu_at(addr: u16, program: &[u8]) -> Cpu<FlatBus> {
+    cpu_with(|builder| builder, addr, program)
+}
+
+#[test]
+fn step_reports_the_executed_instruction() {
+    // LDA $1234,X
+    let mut cpu = cpu_at(0x0200, &[0xbd, 0x34, 0x12]);
+    let step = cpu.step().unwrap();
+    assert_eq!((step.pc, step.opcode, step.operands), (0x0200, 0xbd, vec![0x34, 0x12]));
+    let accesses: Vec<_> = step.accesses.iter().map(|access| (access.kind, access.addr)).collect();
+    assert_eq!(accesses, [(R, 0x0200), (R, 0x0201), (R, 0x0202), (R, 0x1234)]);
+    assert_eq!(step.cycles, 4);
+    assert_eq!(cpu.pc(), 0x0203);
+}
+
+#[test]
+fn run_for_cycles_finishes_the_instruction_that_crosses_the_budget() {
+    // LDA #$01; LDA $1234; LDA #$02
+    let mut cpu = cpu_at(0x0200, &[0xa9, 0x01, 0xad, 0x34, 0x12, 0xa9, 0x02]);
+    assert_eq!(cpu.run_for_cycles(3), Ok(6));
+    assert_eq!(cpu.pc(), 0x0205);
+
+    assert_eq!(cpu.run_for_cycles(2), Ok(2));
+    assert_eq!(cpu.run_for_cycles(0), Ok(0));
+    assert_eq!((cpu.pc(), cpu.a()), (0x0207, 0x02));
+}
+
+#[test]
+fn run_until_stops_before_the_instruction_where_the_predicate_holds() {
+    let mut cpu = cpu_at(0x0200, &[0xea; 8]);
+    assert_eq!(cpu.run_until(|cpu| cpu.pc() == 0x0205), Ok(5));
+    assert_eq!(cpu.pc(), 0x0205);
+
+    assert_eq!(cpu.run_until(|cpu| cpu.pc() == 0x0205), Ok(0));
+    assert_eq!(cpu.pc(), 0x0205);
+
+    // The first error ends the run.
+    cpu.bus.load(0x0206, &[0x02]);
+    let error = CpuError::Jammed { pc: 0x0206, opcode: 0x02 };
+    assert_eq!(cpu.run_until(|_| false), Err(error));
+    assert_eq!(cpu.run_for_cycles(10), Err(error));
+}
+
+#[test]
+fn indexed_loads_pay_for_crossing_a_page() {
+    // LDA $02F0,X / LDA $02F0,Y / LDA ($10),Y / LDX $02F0,Y
+    for (program, cycles) in [
+        (&[0xbd, 0xf0, 0x02][..], 4),
+        (&[0xb9, 0xf0, 0x02], 4),
+        (&[0xb1, 0x10], 5),
+        (&[0xbe, 0xf0, 0x02], 4),
+    ] {
+        for (index, cycles) in [(0x0f, cycles), (0x10, cycles + 1)] {
+            let mut cpu = cpu_at(0x0300, program);
+            // ($10) points at $02F0.
+            cpu.bus.load(0x0010, &[0xf0, 0x02]);
+            cpu.x = index;
+            cpu.y = index;
+            assert_eq!(cpu.step().unwrap().cycles, cycles, "{program:02x?} indexed by {index:02x}");
+        }
+    }
+}
+
+#[test]
+fn stores_and_read_modify_writes_never_pay_for_crossing_a_page() {
+    // STA $02F0,X / STA $02F0,Y / STA ($10),Y / INC $02F0,X / ASL $02F0,X
+    for (program, cycles) in [
+        (&[0x9d, 0xf0, 0x02][..], 5),
+        (&[0x99, 0xf0, 0x02], 5),
+        (&[0x91, 0x10], 6),
+        (&[0xfe, 0xf0, 0x02], 7),
+        (&[0x1e, 0xf0, 0x02], 7),
+    ] {
+        for index in [0x0f, 0x10] {
+            let mut cpu = cpu_at(0x0300, program);
+            // ($10) points at $02F0.
+            cpu.bus.load(0x0010, &[0xf0, 0x02]);
+            cpu.x = index;
+            cpu.y = index;
+            assert_eq!(cpu.step().unwrap().cycles, cycles, "{program:02x?} indexed by {index:02x}");
+        }
+    }
+}
+
+#[test]
+fn taken_branches_cost_one_more_cycle_and_two_across_a_page() {
+    // Z is clear, so BEQ falls through and BNE branches.
+    for (addr, program, cycles, target) in [
+        (0x0300, [0xf0, 0x10], 2, 0x0302),
+        (0x0300, [0xd0, 0x10], 3, 0x0312),
+        (0x03f0, [0xd0, 0x10], 4, 0x0402),
+        (0x0400, [0xd0, 0xf0], 4, 0x03f2),
+    ] {
+        let mut cpu = cpu_at(addr, &program);
+        assert_eq!(cpu.step().unwrap().cycles, cycles, "{program:02x?} at {addr:04x}");
+        assert_eq!(cpu.pc(), target);
+    }
+}
+
+#[test]
+fn opcode_table_decodes_official_and_unofficial_opcodes() {
+    use AddressingMode::*;
+    for (opcode, mnemonic, mode, len, cycles, official) in [
+        (0x00, "BRK", Implied, 1, 7, true),
+        (0x0a, "ASL", Accumulator, 1, 2, true),
+        (0x20, "JSR", Absolute, 3, 6, true),
+        (0x6c, "JMP", Indirect, 3, 5, true),
+        (0x9d, "STA", AbsoluteX, 3, 5, true),
+        (0xa9, "LDA", Immediate, 2, 2, true),
+        (0xb1, "LDA", IndirectY, 2, 5, true),
+        (0xd0, "BNE", Relative, 2, 2, true),
+        (0x02, "JAM", Implied, 1, 2, false),
+        (0x0c, "NOP", Absolute, 3, 4, false),
+        (0xa7, "LAX", ZeroPage, 2, 3, false),
+        (0xeb, "SBC", Immediate, 2, 2, false),
+    ] {
+        let entry = &OPCODES[opcode as usize];
+        assert_eq!(
+            (entry.mnemonic, entry.mode, entry.len, entry.cycles, entry.official),
+            (mnemonic, mode, len, cycles, official),
+            "opcode {opcode:02x}"
+        );
+    }
+
+    assert_eq!(OPCODES.iter().filter(|entry| entry.official).count(), 151);
+    assert!(OPCODES.iter().all(|entry| entry.len == 1 + entry.mode.operand_len()));
+    assert_eq!(OPCODES.iter().filter(|entry| entry.unstable).count(), 7);
+    assert!(OPCODES[0x8b].unstable && !OPCODES[0xa7].unstable);
+}
+
+#[test]
+fn unstable_opcodes_can_be_rejected() {
+    // ANE #$ff
+    let mut cpu = cpu_with(|builder| builder.unstable_opcodes(UnstableOpcodes::Reject), 0x0200, &[0x8b, 0xff]);
+    cpu.a = 0x12;
+    let cycles = cpu.cycles();
+
+    let error = CpuError::UnstableOpcode { pc: 0x0200, opcode: 0x8b };
+    assert_eq!(cpu.step(), Err(error));
+    assert_eq!((cpu.pc(), cpu.a(), cpu.cycles()), (0x0200, 0x12, cycles));
+
+    // Stable unofficial opcodes still run. LAX $00
+    cpu.bus.load(0x0200, &[0xa7, 0x00]);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc(), 0x0202);
+}
+
+#[test]
+fn unstable_opcodes_are_emulated_with_the_magic_constant() {
+    // ANE #$ff: A = (A | magic) & X & operand
+    let magic = UnstableOpcodes::Emulate { magic: 0xee };
+    let mut cpu = cpu_with(|builder| builder.unstable_opcodes(magic), 0x0200, &[0x8b, 0xff]);
+    cpu.a = 0x01;
+    cpu.x = 0x0f;
+    cpu.step().unwrap();
+    assert_eq!(cpu.a(), 0x0f);
+}
+
+#[test]
+fn jam_halts_until_reset() {
+    let mut cpu = cpu_at(0x0200, &[0x02]);
+    let error = CpuError::Jammed { pc: 0x0200, opcode: 0x02 };
+    assert_eq!(cpu.step(), Err(error));
+    assert_eq!(cpu.step(), Err(error));
+    assert_eq!(cpu.pc(), 0x0200);
+
+    // Only a reset gets it going again.
+    cpu.reset();
+    assert!(cpu.step().is_ok());
+
+    // The PC stays on the opcode even when it sits at $FFFF.
+    let mut cpu = cpu_at(0xffff, &[0x02]);
+    assert_eq!(cpu.step(), Err(CpuError::Jammed { pc: 0xffff, opcode: 0x02 }));
+    assert_eq!(cpu.pc(), 0xffff);
+}
+
+#[test]
+fn jam_can_be_a_nop() {
+    let mut cpu = cpu_with(|builder| builder.jam_behavior(JamBehavior::Nop), 0x0200, &[0x02, 0x12, 0xe8]);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc(), 0x0202);
+    cpu.step().unwrap();
+    assert_eq!(cpu.x(), 1);
+
+    // The fetch wraps around $FFFF like any other one-byte instruction.
+    cpu.bus.load(0xffff, &[0x02]);
+    cpu.pc = 0xffff;
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc(), 0x0000);
+}
+
+/// NMI at $9000, reset at $8000 and IRQ at $A000, to load at `NMI_VECTOR`.
+const VECTORS: [u8; 6] = [0x00, 0x90, 0x00, 0x80, 0x00, 0xa0];
+
+/// The status byte the last interrupt sequence pushed.
+fn pushed_status(cpu: &Cpu<FlatBus>) -> u8 {
+    cpu.peek(0x0100 + cpu.sp() as u16 + 1)
+}
+
+#[test]
+fn irq_is_masked_by_i() {
+    // NOP; NOP; CLI; NOP; NOP
+    let mut cpu = cpu_at(0x8000, &[0xea, 0xea, 0x58, 0xea, 0xea]);
+    cpu.bus.load(NMI_VECTOR, &VECTORS);
+    cpu.bus.load(0xa000, &[0xea; 2]);
+    cpu.set_irq_line(IrqSource::External, true);
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+
+    // CLI takes effect after the instruction that follows it.
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().pc, 0x8003);
+    assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Irq));
+    assert_eq!(cpu.pc(), 0xa000);
+
+    // Releasing the line stops further IRQs once the handler clears I.
+    cpu.set_irq_line(IrqSource::External, false);
+    cpu.p.set(Flag::InterruptDisable, false);
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+}
+
+#[test]
+fn sei_lets_a_pending_irq_through_once() {
+    // SEI; NOP
+    let mut cpu = cpu_at(0x8000, &[0x78, 0xea]);
+    cpu.bus.load(NMI_VECTOR, &VECTORS);
+    cpu.bus.load(0xa000, &[0xea]);
+    cpu.p.set(Flag::InterruptDisable, false);
+    cpu.set_irq_line(IrqSource::Mapper, true);
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Irq));
+    // The handler runs with I set.
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+}
+
+#[test]
+fn plp_changes_i_after_the_poll() {
+    // PLP; NOP; NOP, with P = 0 on the stack.
+    let mut cpu = cpu_at(0x8000, &[0x28, 0xea, 0xea]);
+    cpu.bus.load(NMI_VECTOR, &VECTORS);
+    let top = 0x0100 + cpu.sp() as u16 + 1;
+    cpu.bus.load(top, &[0x00]);
+    cpu.set_irq_line(IrqSource::FrameCounter, true);
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+    assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Irq));
+}
+
+#[test]
+fn hardware_interrupts_take_seven_cycles_and_push_b_clear() {
+    let mut cpu = cpu_at(0x8000, &[0xea, 0xea]);
+    cpu.bus.load(NMI_VECTOR, &VECTORS);
+    cpu.bus.load(0xa000, &[0xea]);
+    cpu.p.set(Flag::InterruptDisable, false);
+    cpu.set_irq_line(IrqSource::Dmc, true);
+    cpu.step().unwrap();
+    let step = cpu.step().unwrap();
+    assert_eq!(step.interrupt, Some(Interrupt::Irq));
+    assert_eq!(step.cycles, 7);
+    assert_eq!(step.accesses.len(), 7);
+    assert_eq!(pushed_status(&cpu) & 0b00110000, 0b00100000);
+    assert!(cpu.status().get(Flag::InterruptDisable));
+    // The return address is the instruction the interrupt preempted.
+    assert_eq!(cpu.peek(0x0100 + cpu.sp() as u16 + 2), 0x01);
+    assert_eq!(cpu.peek(0x0100 + cpu.sp() as u16 + 3), 0x80);
+
+    cpu.nmi();
+    let step = cpu.step().unwrap();
+    assert_eq!(step.interrupt, None);
+    let step = cpu.step().unwrap();
+    assert_eq!((step.interrupt, step.cycles), (Some(Interrupt::Nmi), 7));
+    assert_eq!(pushed_status(&cpu) & 0b00110000, 0b00100000);
+    assert_eq!(cpu.pc(), 0x9000);
+}
+
+#[test]
+fn nmi_hijacks_brk() {
+    // BRK
+    let mut cpu = cpu_at(0x8000, &[0x00, 0x00]);
+    cpu.bus.load(NMI_VECTOR, &VECTORS);
+    cpu.nmi();
+    let step = cpu.step().unwrap();
+    assert_eq!(step.cycles, 7);
+    assert_eq!(cpu.pc(), 0x9000);
+    // The pushed status still says BRK.
+    assert_eq!(pushed_status(&cpu) & 0b00110000, 0b00110000);
+    assert_eq!(cpu.peek(0x0100 + cpu.sp() as u16 + 2), 0x02);
+    // The NMI was used up by the hijack.
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+}
+
+#[test]
+fn nmi_hijacks_irq() {
+    let mut cpu = cpu_at(0x8000, &[0xea, 0xea]);
+    cpu.bus.load(NMI_VECTOR, &VECTORS);
+    cpu.p.set(Flag::InterruptDisable, false);
+    cpu.set_irq_line(IrqSource::External, true);
+    cpu.step().unwrap();
+    cpu.nmi();
+    let step = cpu.step().unwrap();
+    assert_eq!(step.interrupt, Some(Interrupt::Irq));
+    assert_eq!(cpu.pc(), 0x9000);
+    assert_eq!(pushed_status(&cpu) & 0b00110000, 0b00100000);
+}
+
+#[test]
+fn start_pc_overrides_the_reset_vector() {
+    let mut cpu = cpu_at(0xc000, &[]);
+    assert_eq!((cpu.pc(), cpu.sp(), cpu.cycles()), (0xc000, 0xfd, 7));
+    assert!(cpu.status().get(Flag::InterruptDisable));
+
+    // It only applies at power-up.
+    cpu.bus.load(RESET_VECTOR, &[0x34, 0x12]);
+    cpu.reset();
+    assert_eq!(cpu.pc(), 0x1234);
+}
+
+#[test]
+fn reset_jumps_through_the_reset_vector() {
+    let mut cpu = cpu_at(0x0200, &[]);
+    cpu.bus.load(RESET_VECTOR, &[0x34, 0x12]);
+    cpu.sp = 0x80;
+    cpu.p.set(Flag::InterruptDisable, false);
+    cpu.accesses.clear();
+    cpu.reset();
+    assert_eq!((cpu.pc(), cpu.sp(), cpu.cycles()), (0x1234, 0x7d, 14));
+    assert!(cpu.status().get(Flag::InterruptDisable));
+
+    // It only reads, so SP moves down without writing the stack.
+    assert_eq!(cpu.accesses.len(), 7);
+    assert!(cpu.accesses.iter().all(|access| access.kind == R));
+}
+
+const N: u8 = 0b10000000;
+const V: u8 = 0b01000000;
+const D: u8 = 0b00001000;
+const Z: u8 = 0b00000010;
+const C: u8 = 0b00000001;
+
+/// A result and the flags it sets.
+type Outcome = (u8, u8);
+
+/// Decimal ADC and SBC cases: opcode, A, operand, carry in, then the
+/// outcome on the NMOS 6502 and on the 65C02. Results follow Bruce
+/// Clark's "Decimal Mode" tutorial, invalid BCD included.
+const DECIMAL_CASES: [(u8, u8, u8, u8, Outcome, Outcome); 11] = [
+    (0x69, 0x09, 0x01, 0, (0x10, 0), (0x10, 0)),
+    // NMOS takes Z from the binary sum and N and V from the sum before
+    // the high digit is adjusted.
+    (0x69, 0x99, 0x01, 0, (0x00, N | C), (0x00, Z | C)),
+    (0x69, 0x50, 0x50, 0, (0x00, N | V | C), (0x00, V | Z | C)),
+    (0x69, 0x58, 0x46, 1, (0x05, N | V | C), (0x05, V | C)),
+    (0x69, 0x99, 0x99, 0, (0x98, V | C), (0x98, N | V | C)),
+    (0x69, 0x0f, 0x0f, 0, (0x14, 0), (0x14, 0)),
+    // NMOS SBC flags are all those of the binary subtraction.
+    (0xe9, 0x00, 0x01, 1, (0x99, N), (0x99, N)),
+    (0xe9, 0x10, 0x10, 1, (0x00, Z | C), (0x00, Z | C)),
+    (0xe9, 0x40, 0x13, 1, (0x27, C), (0x27, C)),
+    (0xe9, 0x80, 0x01, 1, (0x79, V | C), (0x79, V | C)),
+    // The variants adjust invalid BCD differently.
+    (0xe9, 0x20, 0x0f, 1, (0x1b, C), (0x0b, C)),
+];
+
+#[test]
+fn decimal_mode_matches_reference_results() {
+    for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02] {
+        let mut cpu = cpu_with(|builder| builder.variant(variant), 0x0000, &[]);
+        for (opcode, a, val, carry, nmos, cmos) in DECIMAL_CASES {
+            let (result, flags) = if variant == CpuVariant::Nmos6502 { nmos } else { cmos };
+            cpu.bus.load(0x0000, &[opcode, val]);
+            cpu.pc = 0x0000;
+            cpu.a = a;
+            cpu.p = Status::from_byte(D | carry);
+            let step = cpu.step().unwrap();
+
+            let case = format!("{variant:?} {opcode:02x} #${val:02x} with A={a:02x} C={carry}");
+            assert_eq!((cpu.a(), cpu.p()), (result, BASE_P | D | flags), "{case}");
+            // The 65C02 spends a cycle re-reading the operand to fix up
+            // the flags.
+            let cycles = if variant == CpuVariant::Cmos65C02 { 3 } else { 2 };
+            assert_eq!(step.cycles, cycles, "{case}");
+            assert_eq!(step.accesses.len() as u64, step.cycles, "{case}: one bus access per cycle");
+            let last = step.accesses.last().unwrap();
+            assert_eq!((last.kind, last.addr), (R, 0x0001), "{case}");
+        }
+    }
+}
+
+/// Runs a single two-byte instruction from zero page with the given A and P.
+fn exec(cpu: &mut Cpu<FlatBus>, opcode: u8, operand: u8, a: u8, p: u8) {
+    cpu.bus.load(0x0000, &[opcode, operand]);
+    cpu.pc = 0x0000;
+    cpu.a = a;
+    cpu.p = Status::from_byte(p);
+    cpu.step().unwrap();
+}
+
+fn nz(val: u8) -> u8 {
+    let mut p = val & 0b10000000;
+    if val == 0 {
+        p |= 0b00000010;
+    }
+    p
+}
+
+/// P as read back through `Cpu::p`: bit 5 set, B clear.
+const BASE_P: u8 = 0b00100000;
+
+#[test]
+fn adc_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for a in 0..=255u8 {
+        for val in 0..=255u8 {
+            for carry in 0..=1u8 {
+                exec(&mut cpu, 0x69, val, a, carry);
+
+                let unsigned = a as u16 + val as u16 + carry as u16;
+                let signed = a as i8 as i16 + val as i8 as i16 + carry as i16;
+                let result = unsigned as u8;
+                let mut p = BASE_P | nz(result) | (unsigned > 0xff) as u8;
+                if !(-128..=127).contains(&signed) {
+                    p |= 0b01000000;
+                }
+                assert_eq!((cpu.a(), cpu.p()), (result, p), "ADC #${val:02x} with A={a:02x} C={carry}");
+            }
+        }
+    }
+}
+
+#[test]
+fn sbc_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for opcode in [0xe9, 0xeb] {
+        for a in 0..=255u8 {
+            for val in 0..=255u8 {
+                for carry in 0..=1u8 {
+                    exec(&mut cpu, opcode, val, a, carry);
+
+                    let borrow = 1 - carry as i16;
+                    let unsigned = a as i16 - val as i16 - borrow;
+                    let signed = a as i8 as i16 - val as i8 as i16 - borrow;
+                    let result = unsigned as u8;
+                    let mut p = BASE_P | nz(result) | (unsigned >= 0) as u8;
+                    if !(-128..=127).contains(&signed) {
+                        p |= 0b01000000;
+                    }
+                    assert_eq!(
+                        (cpu.a(), cpu.p()),
+                        (result, p),
+                        "SBC({opcode:02x}) #${val:02x} with A={a:02x} C={carry}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn ignores_decimal_flag_on_2a03() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    exec(&mut cpu, 0x69, 0x01, 0x09, 0b00001000);
+    assert_eq!(cpu.a(), 0x0a);
+    exec(&mut cpu, 0xe9, 0x01, 0x10, 0b00001001);
+    assert_eq!(cpu.a(), 0x0f);
+}
+
+#[test]
+fn compare_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for (opcode, register) in [(0xc9, "A"), (0xe0, "X"), (0xc0, "Y")] {
+        for reg in 0..=255u8 {
+            for val in 0..=255u8 {
+                cpu.x = reg;
+                cpu.y = reg;
+                // Carry and overflow must not leak into the result.
+                exec(&mut cpu, opcode, val, reg, 0b01000001);
+
+                let p = BASE_P | 0b01000000 | nz(reg.wrapping_sub(val)) | (reg >= val) as u8;
+                assert_eq!(cpu.p(), p, "compare {register}={reg:02x} with #${val:02x}");
+            }
+        }
+    }
+}
+
+#[test]
+fn bit_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for a in 0..=255u8 {
+        for val in 0..=255u8 {
+            cpu.bus.load(0x0010, &[val]);
+            exec(&mut cpu, 0x24, 0x10, a, 0b00000001);
+
+            let mut p = BASE_P | 0b00000001 | (val & 0b11000000);
+            if a & val == 0 {
+                p |= 0b00000010;
+            }
+            assert_eq!((cpu.a(), cpu.p()), (a, p), "BIT ${val:02x} with A={a:02x}");
+        }
+    }
+}
+
+#[test]
+fn logic_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for opcode in [0x29, 0x09, 0x49] {
+        for a in 0..=255u8 {
+            for val in 0..=255u8 {
+                exec(&mut cpu, opcode, val, a, 0b11000001);
+
+                let result = match opcode {
+                    0x29 => a & val,
+                    0x09 => a | val,
+                    _ => a ^ val,
+                };
+                let p = BASE_P | 0b01000001 | nz(result);
+                assert_eq!((cpu.a(), cpu.p()), (result, p), "{opcode:02x} #${val:02x} with A={a:02x}");
+            }
+        }
+    }
+}
+
+#[test]
+fn shift_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for a in 0..=255u8 {
+        for carry in 0..=1u8 {
+            let cases = [
+                (0x0a, a << 1, a >> 7),
+                (0x4a, a >> 1, a & 1),
+                (0x2a, (a << 1) | carry, a >> 7),
+                (0x6a, (a >> 1) | (carry << 7), a & 1),
+            ];
+            for (opcode, result, carry_out) in cases {
+                exec(&mut cpu, opcode, 0x00, a, carry);
+
+                let p = BASE_P | nz(result) | carry_out;
+                assert_eq!((cpu.a(), cpu.p()), (result, p), "{opcode:02x} with A={a:02x} C={carry}");
+            }
+        }
+    }
+}
+
+#[test]
+fn inc_dec_truth_table() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for val in 0..=255u8 {
+        for (opcode, result) in [(0xe6, val.wrapping_add(1)), (0xc6, val.wrapping_sub(1))] {
+            cpu.bus.load(0x0010, &[val]);
+            exec(&mut cpu, opcode, 0x10, 0x00, 0b00000001);
+
+            assert_eq!(cpu.peek(0x0010), result);
+            assert_eq!(cpu.p(), BASE_P | 0b00000001 | nz(result), "{opcode:02x} with M={val:02x}");
+        }
+    }
+}
+
+#[test]
+fn php_pushes_break_and_unused_bits() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for p in 0..=255u8 {
+        cpu.sp = 0xff;
+        exec(&mut cpu, 0x08, 0x00, 0x00, p);
+        assert_eq!(cpu.peek(0x01ff), p | 0b00110000);
+    }
+}
+
+#[test]
+fn plp_ignores_break_and_unused_bits() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    for p in 0..=255u8 {
+        cpu.sp = 0xfe;
+        cpu.bus.load(0x01ff, &[p]);
+        exec(&mut cpu, 0x28, 0x00, 0x00, 0x00);
+        assert_eq!(cpu.status().to_byte(false), (p & 0b11001111) | BASE_P);
+    }
+}
+
+#[test]
+fn interrupts_push_break_only_for_brk() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.sp = 0xff;
+    exec(&mut cpu, 0x00, 0x00, 0x00, 0b11001011);
+    assert_eq!(cpu.peek(0x01fd), 0b11111011);
+
+    cpu.sp = 0xff;
+    cpu.p = Status::from_byte(0b11001011);
+    cpu.nmi();
+    exec(&mut cpu, 0xea, 0x00, 0x00, 0b11001011);
+    cpu.step().unwrap();
+    assert_eq!(cpu.peek(0x01fd), 0b11101011);
+}
+
+/// Places `operands` at $0300 and resolves them with `mode`, returning the
+/// effective address and whether indexing crossed a page.
+fn resolve(cpu: &mut Cpu<FlatBus>, mode: AddressingMode, operands: &[u8]) -> (u16, bool) {
+    cpu.bus.load(0x0300, operands);
+    cpu.pc = 0x0300;
+    cpu.page_crossed = false;
+
+    let addr = cpu.operand_addr(mode, AccessKind::Read);
+    assert_eq!(cpu.pc, 0x0300 + operands.len() as u16, "{mode:?} consumed the wrong number of bytes");
+    (addr, cpu.page_crossed)
+}
+
+#[test]
+fn immediate_and_relative_address_the_operand_byte() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::Immediate, &[0x42]), (0x0300, false));
+    assert_eq!(resolve(&mut cpu, AddressingMode::Relative, &[0x80]), (0x0300, false));
+}
+
+#[test]
+fn zero_page_indexing_wraps_within_zero_page() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::ZeroPage, &[0x80]), (0x0080, false));
+
+    cpu.x = 0x01;
+    assert_eq!(resolve(&mut cpu, AddressingMode::ZeroPageX, &[0xff]), (0x0000, false));
+    cpu.x = 0x10;
+    assert_eq!(resolve(&mut cpu, AddressingMode::ZeroPageX, &[0x20]), (0x0030, false));
+
+    cpu.y = 0x02;
+    assert_eq!(resolve(&mut cpu, AddressingMode::ZeroPageY, &[0xff]), (0x0001, false));
+}
+
+#[test]
+fn absolute_indexing_wraps_around_ffff() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::Absolute, &[0x34, 0x12]), (0x1234, false));
+
+    cpu.x = 0x01;
+    assert_eq!(resolve(&mut cpu, AddressingMode::AbsoluteX, &[0x00, 0x02]), (0x0201, false));
+    assert_eq!(resolve(&mut cpu, AddressingMode::AbsoluteX, &[0xff, 0x02]), (0x0300, true));
+    assert_eq!(resolve(&mut cpu, AddressingMode::AbsoluteX, &[0xff, 0xff]), (0x0000, true));
+
+    cpu.y = 0xff;
+    assert_eq!(resolve(&mut cpu, AddressingMode::AbsoluteY, &[0x00, 0x02]), (0x02ff, false));
+    assert_eq!(resolve(&mut cpu, AddressingMode::AbsoluteY, &[0x01, 0x02]), (0x0300, true));
+    assert_eq!(resolve(&mut cpu, AddressingMode::AbsoluteY, &[0x02, 0xff]), (0x0001, true));
+}
+
+#[test]
+fn indirect_jmp_does_not_cross_pages() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.bus.load(0x0200, &[0x34, 0x12]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::Indirect, &[0x00, 0x02]), (0x1234, false));
+
+    cpu.bus.load(0x04ff, &[0x78, 0x56]);
+    cpu.bus.load(0x0400, &[0x9a]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::Indirect, &[0xff, 0x04]), (0x9a78, false));
+
+    cpu.variant = CpuVariant::Cmos65C02;
+    assert_eq!(resolve(&mut cpu, AddressingMode::Indirect, &[0xff, 0x04]), (0x5678, false));
+}
+
+#[test]
+fn indexed_indirect_pointer_wraps_within_zero_page() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.bus.load(0x0024, &[0x34, 0x12]);
+    cpu.x = 0x04;
+    assert_eq!(resolve(&mut cpu, AddressingMode::IndirectX, &[0x20]), (0x1234, false));
+
+    // $FE + 4 wraps to $02.
+    cpu.bus.load(0x0002, &[0x78, 0x56]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::IndirectX, &[0xfe]), (0x5678, false));
+
+    // A pointer at $FF takes its high byte from $00.
+    cpu.bus.load(0x00ff, &[0xcd]);
+    cpu.bus.load(0x0000, &[0xab]);
+    cpu.bus.load(0x0100, &[0x99]);
+    cpu.x = 0x00;
+    assert_eq!(resolve(&mut cpu, AddressingMode::IndirectX, &[0xff]), (0xabcd, false));
+}
+
+#[test]
+fn indirect_indexed_pointer_wraps_and_index_crosses_pages() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.bus.load(0x0040, &[0x00, 0x02]);
+    cpu.y = 0x10;
+    assert_eq!(resolve(&mut cpu, AddressingMode::IndirectY, &[0x40]), (0x0210, false));
+
+    cpu.bus.load(0x0040, &[0xf8]);
+    assert_eq!(resolve(&mut cpu, AddressingMode::IndirectY, &[0x40]), (0x0308, true));
+
+    cpu.bus.load(0x00ff, &[0xff]);
+    cpu.bus.load(0x0000, &[0xff]);
+    cpu.y = 0x02;
+    assert_eq!(resolve(&mut cpu, AddressingMode::IndirectY, &[0xff]), (0x0001, true));
+}
+
+#[test]
+fn branches_wrap_around_the_address_space() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.pc = 0x0002;
+    cpu.branch_jump(-0x04);
+    assert_eq!(cpu.pc, 0xfffe);
+
+    cpu.branch_jump(0x05);
+    assert_eq!(cpu.pc, 0x0003);
+
+    cpu.branch_jump(-0x80);
+    assert_eq!(cpu.pc, 0xff83);
+}
+
+/// Runs the instruction in `program`, placed at $0300, and returns its bus
+/// accesses.
+fn accesses(cpu: &mut Cpu<FlatBus>, program: &[u8]) -> Vec<(AccessKind, u16, u8)> {
+    cpu.bus.load(0x0300, program);
+    cpu.pc = 0x0300;
+
+    let step = cpu.step().unwrap();
+    assert_eq!(step.accesses.len() as u64, step.cycles, "one bus access per cycle");
+    step.accesses.iter().map(|a| (a.kind, a.addr, a.value)).collect()
+}
+
+#[test]
+fn implied_instructions_read_the_next_byte() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    assert_eq!(accesses(&mut cpu, &[0xe8, 0x55]), [(R, 0x0300, 0xe8), (R, 0x0301, 0x55)]);
+}
+
+#[test]
+fn indexed_reads_touch_the_wrong_page_only_when_crossing() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.bus.load(0x0210, &[0x11]);
+    cpu.bus.load(0x0310, &[0x22]);
+    cpu.x = 0x20;
+    assert_eq!(
+        accesses(&mut cpu, &[0xbd, 0xf0, 0x02]),
+        [(R, 0x0300, 0xbd), (R, 0x0301, 0xf0), (R, 0x0302, 0x02), (R, 0x0210, 0x11), (R, 0x0310, 0x22)]
+    );
+
+    cpu.x = 0x01;
+    assert_eq!(
+        accesses(&mut cpu, &[0xbd, 0x0f, 0x02]),
+        [(R, 0x0300, 0xbd), (R, 0x0301, 0x0f), (R, 0x0302, 0x02), (R, 0x0210, 0x11)]
+    );
+}
+
+#[test]
+fn indexed_writes_always_read_first() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.a = 0x42;
+    cpu.x = 0x01;
+    assert_eq!(
+        accesses(&mut cpu, &[0x9d, 0x00, 0x02]),
+        [(R, 0x0300, 0x9d), (R, 0x0301, 0x00), (R, 0x0302, 0x02), (R, 0x0201, 0x00), (W, 0x0201, 0x42)]
+    );
+}
+
+#[test]
+fn read_modify_write_writes_twice() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.bus.load(0x0010, &[0x7f]);
+    assert_eq!(
+        accesses(&mut cpu, &[0xe6, 0x10]),
+        [(R, 0x0300, 0xe6), (R, 0x0301, 0x10), (R, 0x0010, 0x7f), (W, 0x0010, 0x7f), (W, 0x0010, 0x80)]
+    );
+}
+
+#[test]
+fn zero_page_indexing_reads_the_base_address() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.bus.load(0x0080, &[0x33]);
+    cpu.bus.load(0x0000, &[0x44]);
+    cpu.x = 0x80;
+    assert_eq!(
+        accesses(&mut cpu, &[0xb5, 0x80]),
+        [(R, 0x0300, 0xb5), (R, 0x0301, 0x80), (R, 0x0080, 0x33), (R, 0x0000, 0x44)]
+    );
+}
+
+#[test]
+fn taken_branches_read_the_next_opcode_and_the_wrong_page() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.p.set(Flag::Zero, false);
+    cpu.bus.load(0x0302, &[0x00]);
+    cpu.bus.load(0x0382, &[0x00]);
+    assert_eq!(
+        accesses(&mut cpu, &[0xd0, 0x80]),
+        [(R, 0x0300, 0xd0), (R, 0x0301, 0x80), (R, 0x0302, 0x00), (R, 0x0382, 0x00)]
+    );
+    assert_eq!(cpu.pc, 0x0282);
+}
+
+#[test]
+fn jsr_and_rts_touch_the_stack_in_order() {
+    let mut cpu = cpu_at(0x0000, &[]);
+    cpu.sp = 0xfd;
+    cpu.bus.load(0x01fd, &[0x00]);
+    assert_eq!(
+        accesses(&mut cpu, &[0x20, 0x00, 0x04]),
+        [
+            (R, 0x0300, 0x20),
+            (R, 0x0301, 0x00),
+            (R, 0x01fd, 0x00),
+            (W, 0x01fd, 0x03),
+            (W, 0x01fc, 0x02),
+            (R, 0x0302, 0x04),
+        ]
+    );
+    assert_eq!(cpu.pc, 0x0400);
+
+    cpu.bus.load(0x0400, &[0x60, 0x00]);
+    cpu.pc = 0x0400;
+    let step = cpu.step().unwrap();
+    let rts: Vec<_> = step.accesses.iter().map(|a| (a.kind, a.addr, a.value)).collect();
+    assert_eq!(
+        rts,
+        [
+            (R, 0x0400, 0x60),
+            (R, 0x0401, 0x00),
+            (R, 0x01fb, 0x00),
+            (R, 0x01fc, 0x02),
+            (R, 0x01fd, 0x03),
+            (R, 0x0302, 0x04),
+        ]
+    );
+    assert_eq!(cpu.pc, 0x0303);
+}
+
+/// A bus that counts its ticks, to check the CPU ticks once per cycle.
+struct CountingBus {
+    ram: FlatBus,
+    ticks: u64,
+}
+
+impl Bus for CountingBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, val: u8, addr: u16) {
+        self.ram.write(val, addr);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+}
+
+#[test]
+fn user_buses_are_ticked_once_per_cycle() {
+    let mut ram = FlatBus::new();
+    // LDX #$03; loop: DEX; BNE loop; INC $0200,X
+    ram.load(0x8000, &[0xa2, 0x03, 0xca, 0xd0, 0xfd, 0xfe, 0x00, 0x02]);
+    ram.load(RESET_VECTOR, &[0x00, 0x80]);
+
+    let mut cpu = Cpu::with_bus(CountingBus { ram, ticks: 0 });
+    assert_eq!(cpu.pc(), 0x8000);
+    assert_eq!(cpu.bus().ticks, cpu.cycles());
+
+    cpu.run_until(|cpu| cpu.pc() == 0x8008).unwrap();
+    assert_eq!(cpu.cycles(), 7 + 2 + 3 * 2 + 2 * 3 + 2 + 7);
+    assert_eq!(cpu.bus().ticks, cpu.cycles());
+    assert_eq!(cpu.peek(0x0200), 1);
+}
+
+/// A bus that requests OAM DMA on writes to $4014 and collects what
+/// reaches $2004.
+struct DmaBus {
+    ram: FlatBus,
+    oam_dma: Option<u8>,
+    oam: Vec<u8>,
+}
+
+impl Bus for DmaBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x2004 => self.oam.push(val),
+            0x4014 => self.oam_dma = Some(val),
+            _ => self.ram.write(val, addr),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+}
+
+#[test]
+fn oam_dma_halts_for_513_or_514_cycles() {
+    let mut ram = FlatBus::new();
+    // STA $4014; BIT $00; STA $4014
+    ram.load(0x8000, &[0x8d, 0x14, 0x40, 0x24, 0x00, 0x8d, 0x14, 0x40]);
+    for i in 0..=0xff {
+        ram.load(0x0200 + i, &[i as u8 ^ 0x55]);
+    }
+    ram.load(RESET_VECTOR, &[0x00, 0x80]);
+    let mut cpu = Cpu::with_bus(DmaBus { ram, oam_dma: None, oam: Vec::new() });
+    cpu.a = 0x02;
+
+    // After reset and the STA, the count is odd: the CPU waits a cycle to
+    // line up with the DMA unit's reads.
+    let step = cpu.step().unwrap();
+    assert_eq!(step.cycles, 4 + 514);
+    assert_eq!(step.accesses.len() as u64, step.cycles, "one bus access per cycle");
+    assert_eq!(cpu.bus().oam, (0..=0xff).map(|i| i as u8 ^ 0x55).collect::<Vec<_>>());
+
+    // BIT takes three cycles, so this time the count is even.
+    cpu.step().unwrap();
+    let step = cpu.step().unwrap();
+    assert_eq!(step.cycles, 4 + 513);
+    assert_eq!(step.accesses.len() as u64, step.cycles, "one bus access per cycle");
+}
+
+/// A bus with an NMI line the test drives.
+struct NmiBus {
+    ram: FlatBus,
+    nmi: bool,
+}
+
+impl Bus for NmiBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, val: u8, addr: u16) {
+        self.ram.write(val, addr);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+
+    fn nmi(&self) -> bool {
+        self.nmi
+    }
+}
+
+#[test]
+fn bus_nmi_line_triggers_on_rising_edges() {
+    let mut ram = FlatBus::new();
+    // NOPs, with the NMI handler at $9000.
+    ram.load(0x8000, &[0xea; 16]);
+    ram.load(0x9000, &[0xea; 16]);
+    ram.load(NMI_VECTOR, &[0x00, 0x90]);
+    ram.load(RESET_VECTOR, &[0x00, 0x80]);
+    let mut cpu = Cpu::with_bus(NmiBus { ram, nmi: false });
+
+    cpu.bus_mut().nmi = true;
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Nmi));
+    assert_eq!(cpu.pc(), 0x9000);
+
+    // Holding the line asserted does not run the handler again.
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().interrupt, None);
+
+    cpu.bus_mut().nmi = false;
+    cpu.step().unwrap();
+    cpu.bus_mut().nmi = true;
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Nmi));
+}
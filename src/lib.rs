@@ -0,0 +1,6 @@
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod mapper;
+pub mod ppu;
+pub mod trace;
@@ -1,36 +1,94 @@
-use std::{fs};
-mod cpu;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-fn read_header(data: &Vec<u8>) {
-    println!("File length: {}", data.len());
+use nes_emulator::{cartridge, cpu};
 
-    let header = &data[..16];
+const USAGE: &str = "Usage: nes-emulator [--start-pc <hex>] <rom.nes>";
 
-    let id = Vec::from(&header[..4]);
-    println!("Id: {:?}", String::from_utf8(id));
+/// CPU cycles between checks for a termination request, about one frame.
+const CHECK_INTERVAL: u64 = 29_781;
 
-    let prg_rom_size = (header[4] as u16) * 16384;
-    println!("PRG ROM size: {} bytes", prg_rom_size);
+/// CPU cycles between saves of battery-backed RAM, about ten seconds.
+const SAVE_INTERVAL: u64 = 17_897_730;
 
-    let chr_rom_size = (header[5] as u16) * 8192;
-    println!("CHR ROM size: {} bytes", chr_rom_size);
-
-    let flags6 = header[6];
-    let trainer_present = (flags6 & 0b00000100) >> 2;
-    println!("Trainer present: {}", trainer_present);
+/// The ROM to run and, if given, the PC to start at instead of the reset
+/// vector, e.g. $C000 for nestest's automation mode.
+struct Args {
+    path: String,
+    start_pc: Option<u16>,
 }
 
 fn main() {
-    let data = fs::read("nestest.nes");
-    match data {
-        Ok(_data) => {
-            //read_header(&_data);
-            let r = cpu::Rom::new(_data);
-            let mut cpu = cpu::Cpu::new(r);
-            cpu.run();
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut path = None;
+    let mut start_pc = None;
+    while let Some(arg) = args.next() {
+        if arg == "--start-pc" {
+            let hex = args.next().ok_or(USAGE)?;
+            let pc = u16::from_str_radix(hex.trim_start_matches('$'), 16)
+                .map_err(|e| format!("Invalid --start-pc {hex}: {e}"))?;
+            start_pc = Some(pc);
+        } else if path.is_none() && !arg.starts_with('-') {
+            path = Some(arg);
+        } else {
+            return Err(USAGE.to_string());
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    Ok(Args { path, start_pc })
+}
+
+fn run() -> Result<(), String> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let cartridge = cartridge::Cartridge::load(&args.path).map_err(|e| format!("Error loading {}: {e}", args.path))?;
+    println!("{:?}", cartridge.header());
+
+    let mut builder = cpu::Cpu::builder(cartridge);
+    if let Some(pc) = args.start_pc {
+        builder = builder.start_pc(pc);
+    }
+    let mut cpu = builder.build();
+
+    // Ctrl-C and termination signals only raise the flag, so the loop below
+    // gets to save before exiting.
+    let terminated = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&terminated);
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))
+        .map_err(|e| format!("Error installing the termination handler: {e}"))?;
+
+    let mut since_save = 0;
+    let result = loop {
+        if terminated.load(Ordering::SeqCst) {
+            break Ok(());
         }
-        Err(e) => {
-            println!("Error reading ROM file: {e}");
+        match cpu.run_for_cycles(CHECK_INTERVAL) {
+            Ok(cycles) => since_save += cycles,
+            Err(e) => break Err(format!("CPU stopped: {e}")),
         }
+        if since_save >= SAVE_INTERVAL {
+            if let Err(e) = save(cpu.bus_mut().cartridge_mut()) {
+                eprintln!("{e}");
+            }
+            since_save = 0;
+        }
+    };
+
+    let saved = save(cpu.bus_mut().cartridge_mut());
+    if let (Err(e), Err(_)) = (&result, &saved) {
+        eprintln!("{e}");
     }
+    result.and(saved)
+}
+
+fn save(cartridge: &mut cartridge::Cartridge) -> Result<(), String> {
+    cartridge
+        .save()
+        .map_err(|e| format!("Error saving {}: {e}", cartridge.save_path().unwrap().display()))
 }
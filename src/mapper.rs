@@ -0,0 +1,291 @@
+use crate::cartridge::{Mirroring, Rom, RomError};
+
+mod axrom;
+mod cnrom;
+mod fme7;
+mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc4;
+mod mmc5;
+mod namco163;
+mod nrom;
+#[cfg(test)]
+mod tests;
+mod uxrom;
+mod vrc4;
+mod vrc6;
+mod vrc_irq;
+
+pub use axrom::Axrom;
+pub use cnrom::Cnrom;
+pub use fme7::Fme7;
+pub use mmc1::Mmc1;
+pub use mmc2::Mmc2;
+pub use mmc3::Mmc3;
+pub use mmc4::Mmc4;
+pub use mmc5::Mmc5;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use uxrom::Uxrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+
+/// The logic on a cartridge board: which PRG and CHR banks the CPU and PPU
+/// see, how the nametables are mirrored and whether the board asserts IRQ.
+///
+/// CPU addresses are in $6000-$FFFF, PPU addresses in $0000-$1FFF. Boards
+/// with registers or memory elsewhere implement the `expansion_*` and
+/// `nametable_*` hooks.
+pub trait Mapper {
+    /// Reads without side effects. `None` means nothing on the board
+    /// drives the data bus, leaving it open.
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16);
+
+    /// Reads the expansion area at $4020-$5FFF without side effects.
+    fn expansion_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+        self.expansion_peek(addr)
+    }
+
+    fn expansion_write(&mut self, _val: u8, _addr: u16) {}
+
+    /// Called with CPU writes to the PPU registers at $2000-$2007, for
+    /// boards that follow the PPU's settings by watching them.
+    fn ppu_register_write(&mut self, _val: u8, _addr: u16) {}
+
+    /// Reads without side effects.
+    fn ppu_peek(&self, addr: u16) -> u8;
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16);
+
+    /// Reads nametable `addr` in $2000-$2FFF from memory on the board,
+    /// without side effects. `None` leaves the read to the console's
+    /// nametable RAM, at the page `nametable_page` picks. By default only
+    /// pages 2 and 3 of four-screen boards are on the board.
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        self.memory().read_vram(self.nametable_page(addr), addr)
+    }
+
+    /// Called for every nametable and attribute fetch the PPU makes.
+    fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+        self.nametable_peek(addr)
+    }
+
+    /// Returns whether the board took the write. Otherwise it goes to the
+    /// console's nametable RAM.
+    fn nametable_write(&mut self, val: u8, addr: u16) -> bool {
+        let page = self.nametable_page(addr);
+        self.memory_mut().write_vram(page, val, addr)
+    }
+
+    /// The 1KB page of nametable RAM that `addr` in $2000-$2FFF selects.
+    fn nametable_page(&self, addr: u16) -> usize {
+        self.mirroring().nametable_page(addr)
+    }
+
+    /// Called with every address the PPU puts on its bus, nametable
+    /// fetches included, for boards that watch the PPU's address lines.
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Whether the board is holding the CPU's IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called once per CPU cycle.
+    fn cpu_tick(&mut self) {}
+
+    /// The memory chips on the board.
+    fn memory(&self) -> &CartridgeMemory;
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory;
+
+    /// The board's expansion audio, from 0.0 for silence to 1.0 for the
+    /// loudest the chip can play.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+}
+
+/// Builds the mapper for the board `rom` was dumped from.
+pub fn from_rom(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+    let mirroring = rom.header.mirroring;
+    let submapper = rom.header.submapper;
+    let mapper: Box<dyn Mapper> = match rom.header.mapper {
+        0 => Box::new(Nrom::new(CartridgeMemory::new(rom), mirroring)),
+        1 => Box::new(Mmc1::new(CartridgeMemory::new(rom))),
+        2 => Box::new(Uxrom::new(CartridgeMemory::new(rom), mirroring)),
+        3 => Box::new(Cnrom::new(CartridgeMemory::new(rom), mirroring)),
+        4 => Box::new(Mmc3::new(CartridgeMemory::new(rom), mirroring)),
+        5 => Box::new(Mmc5::new(CartridgeMemory::new(rom))),
+        7 => Box::new(Axrom::new(CartridgeMemory::new(rom))),
+        9 => Box::new(Mmc2::new(CartridgeMemory::new(rom))),
+        10 => Box::new(Mmc4::new(CartridgeMemory::new(rom))),
+        19 => Box::new(Namco163::new(CartridgeMemory::new(rom), mirroring)),
+        number @ (21 | 22 | 23 | 25) => Box::new(Vrc4::new(CartridgeMemory::new(rom), number, submapper)),
+        number @ (24 | 26) => Box::new(Vrc6::new(CartridgeMemory::new(rom), number)),
+        69 => Box::new(Fme7::new(CartridgeMemory::new(rom))),
+        mapper => return Err(RomError::UnsupportedMapper { mapper }),
+    };
+    Ok(mapper)
+}
+
+/// The memory chips on a board, with bank lookups shared by all mappers.
+/// Bank numbers wrap around the size of the chip, as the unconnected high
+/// bank bits do on real boards.
+pub struct CartridgeMemory {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// Nametable pages 2 and 3, on four-screen boards only.
+    vram: Vec<u8>,
+    /// Whether PRG-RAM was written since it was last saved.
+    prg_ram_dirty: bool,
+}
+
+impl CartridgeMemory {
+    pub fn new(rom: Rom) -> CartridgeMemory {
+        let chr_is_ram = rom.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            // NES 2.0 headers may leave the CHR-RAM size out; every board
+            // without CHR ROM has at least 8KB of it.
+            let size = rom.header.chr_ram_size + rom.header.chr_nvram_size;
+            vec![0; size.max(0x2000)]
+        } else {
+            rom.chr_rom
+        };
+
+        let vram = match rom.header.mirroring {
+            Mirroring::FourScreen => vec![0; 0x800],
+            _ => Vec::new(),
+        };
+
+        // Every board gets at least the 8KB at $6000-$7FFF.
+        let prg_ram_size = rom.header.prg_ram_size + rom.header.prg_nvram_size;
+        let mut prg_ram = vec![0; prg_ram_size.max(0x2000)];
+        // Copiers loaded the trainer to $7000 before starting the game,
+        // which then expects to find it there.
+        prg_ram[0x1000..0x1000 + rom.trainer.len()].copy_from_slice(&rom.trainer);
+
+        CartridgeMemory {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            vram,
+            prg_ram_dirty: false,
+        }
+    }
+
+    /// Number of `bank_size` banks in PRG ROM.
+    pub fn prg_banks(&self, bank_size: usize) -> usize {
+        (self.prg_rom.len() / bank_size).max(1)
+    }
+
+    /// Reads `addr` within PRG ROM bank `bank` of `bank_size` bytes.
+    pub fn read_prg(&self, bank_size: usize, bank: usize, addr: u16) -> u8 {
+        let offset = (bank % self.prg_banks(bank_size)) * bank_size + addr as usize % bank_size;
+        self.prg_rom[offset % self.prg_rom.len()]
+    }
+
+    /// Reads $6000-$7FFF.
+    pub fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram[(addr - 0x6000) as usize]
+    }
+
+    pub fn write_prg_ram(&mut self, val: u8, addr: u16) {
+        self.prg_ram[(addr - 0x6000) as usize] = val;
+        self.prg_ram_dirty = true;
+    }
+
+    fn prg_ram_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = self.prg_ram.len() / 0x2000;
+        (bank % banks) * 0x2000 + (addr & 0x1fff) as usize
+    }
+
+    /// Reads `addr` within 8KB PRG-RAM bank `bank`, for boards that bank
+    /// more than 8KB or map it outside $6000-$7FFF.
+    pub fn read_prg_ram_bank(&self, bank: usize, addr: u16) -> u8 {
+        self.prg_ram[self.prg_ram_offset(bank, addr)]
+    }
+
+    pub fn write_prg_ram_bank(&mut self, bank: usize, val: u8, addr: u16) {
+        let offset = self.prg_ram_offset(bank, addr);
+        self.prg_ram[offset] = val;
+        self.prg_ram_dirty = true;
+    }
+
+    /// All of PRG-RAM, battery-backed or not.
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    /// Fills PRG-RAM from the start of `data`, such as a save file. Bytes
+    /// past the end of either are left alone.
+    pub fn load_prg_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn prg_ram_dirty(&self) -> bool {
+        self.prg_ram_dirty
+    }
+
+    pub fn mark_prg_ram_saved(&mut self) {
+        self.prg_ram_dirty = false;
+    }
+
+    fn vram_offset(&self, page: usize, addr: u16) -> Option<usize> {
+        (page >= 2 && !self.vram.is_empty()).then(|| (page & 1) * 0x400 + (addr & 0x03ff) as usize)
+    }
+
+    /// Reads `addr` within nametable page `page` if the board has VRAM for
+    /// it.
+    pub fn read_vram(&self, page: usize, addr: u16) -> Option<u8> {
+        self.vram_offset(page, addr).map(|offset| self.vram[offset])
+    }
+
+    /// Returns whether the board has VRAM for page `page` and took the
+    /// write.
+    pub fn write_vram(&mut self, page: usize, val: u8, addr: u16) -> bool {
+        let Some(offset) = self.vram_offset(page, addr) else {
+            return false;
+        };
+        self.vram[offset] = val;
+        true
+    }
+
+    fn chr_offset(&self, bank_size: usize, bank: usize, addr: u16) -> usize {
+        let banks = (self.chr.len() / bank_size).max(1);
+        ((bank % banks) * bank_size + addr as usize % bank_size) % self.chr.len()
+    }
+
+    pub fn read_chr(&self, bank_size: usize, bank: usize, addr: u16) -> u8 {
+        self.chr[self.chr_offset(bank_size, bank, addr)]
+    }
+
+    /// Writes CHR-RAM. CHR ROM ignores writes.
+    pub fn write_chr(&mut self, bank_size: usize, bank: usize, val: u8, addr: u16) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(bank_size, bank, addr);
+            self.chr[offset] = val;
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 7: a switchable 32KB PRG bank and a register bit choosing which
+/// nametable fills the screen. CHR is 8KB of RAM.
+pub struct Axrom {
+    memory: CartridgeMemory,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    pub fn new(memory: CartridgeMemory) -> Axrom {
+        Axrom {
+            memory,
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            0x6000..=0x7fff => self.memory.read_prg_ram(addr),
+            _ => self.memory.read_prg(0x8000, self.prg_bank, addr),
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x6000..=0x7fff => self.memory.write_prg_ram(val, addr),
+            _ => {
+                self.prg_bank = (val & 0b00000111) as usize;
+                self.mirroring = if val & 0b00010000 != 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, 0, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x2000, 0, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
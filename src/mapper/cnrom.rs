@@ -0,0 +1,56 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 3: NROM's PRG layout with a switchable 8KB CHR ROM bank.
+pub struct Cnrom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Cnrom {
+        Cnrom {
+            memory,
+            mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            0x6000..=0x7fff => self.memory.read_prg_ram(addr),
+            _ => self.memory.read_prg(0x8000, 0, addr),
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x6000..=0x7fff => self.memory.write_prg_ram(val, addr),
+            _ => self.chr_bank = val as usize,
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, self.chr_bank, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x2000, self.chr_bank, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
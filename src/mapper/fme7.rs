@@ -0,0 +1,298 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 69: Sunsoft's FME-7 and its 5B variant. Four switchable 8KB PRG
+/// banks, one of which can be PRG-RAM at $6000, eight 1KB CHR banks, a
+/// 16-bit IRQ counter decremented every CPU cycle and, on the 5B, three
+/// square wave channels with noise and an envelope.
+pub struct Fme7 {
+    memory: CartridgeMemory,
+    /// Register the next $A000 write goes to.
+    command: u8,
+    chr_banks: [u8; 8],
+    /// Bank at $6000: bits 0-5 pick it, bit 6 selects RAM over ROM and bit
+    /// 7 enables the RAM.
+    prg_6000: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq: bool,
+    audio: Sunsoft5bAudio,
+}
+
+impl Fme7 {
+    pub fn new(memory: CartridgeMemory) -> Fme7 {
+        Fme7 {
+            memory,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_6000: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq: false,
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    fn write_register(&mut self, val: u8) {
+        match self.command {
+            0..=7 => self.chr_banks[self.command as usize] = val,
+            8 => self.prg_6000 = val,
+            9..=0xb => self.prg_banks[(self.command - 9) as usize] = val & 0b00111111,
+            0xc => {
+                self.mirroring = match val & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xd => {
+                self.irq_enabled = val & 0b00000001 != 0;
+                self.counter_enabled = val & 0b10000000 != 0;
+                self.irq = false;
+            }
+            0xe => self.counter = (self.counter & 0xff00) | val as u16,
+            _ => self.counter = (self.counter & 0x00ff) | (val as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let bank = match addr {
+            0x6000..=0x7fff => {
+                let bank = (self.prg_6000 & 0b00111111) as usize;
+                return match self.prg_6000 >> 6 {
+                    0b11 => Some(self.memory.read_prg_ram_bank(bank, addr)),
+                    0b01 => None,
+                    _ => Some(self.memory.read_prg(0x2000, bank, addr)),
+                };
+            }
+            0xe000..=0xffff => self.memory.prg_banks(0x2000) - 1,
+            _ => self.prg_banks[((addr - 0x8000) / 0x2000) as usize] as usize,
+        };
+        Some(self.memory.read_prg(0x2000, bank, addr))
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x6000..=0x7fff => {
+                if self.prg_6000 >> 6 == 0b11 {
+                    self.memory.write_prg_ram_bank((self.prg_6000 & 0b00111111) as usize, val, addr);
+                }
+            }
+            0x8000..=0x9fff => self.command = val & 0x0f,
+            0xa000..=0xbfff => self.write_register(val),
+            0xc000..=0xdfff => self.audio.select(val),
+            _ => self.audio.write(val),
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x400, self.chr_banks[(addr / 0x400) as usize] as usize, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x400, self.chr_banks[(addr / 0x400) as usize] as usize, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.counter_enabled {
+            self.counter = self.counter.wrapping_sub(1);
+            if self.counter == 0xffff && self.irq_enabled {
+                self.irq = true;
+            }
+        }
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+/// The 5B's sound: a YM2149F with its clock halved, so tone, noise and
+/// envelope advance once per 16 CPU cycles.
+struct Sunsoft5bAudio {
+    /// Register the next $E000 write goes to.
+    select: u8,
+    tone_periods: [u16; 3],
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_period: u8,
+    noise_counter: u8,
+    /// 17-bit linear feedback shift register.
+    noise: u32,
+    /// Active low: bits 0-2 disable the tones, bits 3-5 the noise.
+    mixer: u8,
+    /// Bits 0-3 are the volume, bit 4 plays the envelope instead.
+    volumes: [u8; 3],
+    envelope_period: u16,
+    envelope_counter: u16,
+    envelope_shape: u8,
+    /// 0-31 within the current ramp.
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+    prescaler: u8,
+}
+
+impl Sunsoft5bAudio {
+    fn new() -> Sunsoft5bAudio {
+        Sunsoft5bAudio {
+            select: 0,
+            tone_periods: [0; 3],
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_period: 0,
+            noise_counter: 0,
+            noise: 1,
+            mixer: 0,
+            volumes: [0; 3],
+            envelope_period: 0,
+            envelope_counter: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+            prescaler: 0,
+        }
+    }
+
+    fn select(&mut self, val: u8) {
+        self.select = val & 0x0f;
+    }
+
+    fn write(&mut self, val: u8) {
+        match self.select {
+            0..=5 => {
+                let period = &mut self.tone_periods[(self.select / 2) as usize];
+                *period = if self.select & 1 == 0 {
+                    (*period & 0xf00) | val as u16
+                } else {
+                    (*period & 0x0ff) | ((val & 0x0f) as u16) << 8
+                };
+            }
+            6 => self.noise_period = val & 0b00011111,
+            7 => self.mixer = val,
+            8..=0xa => self.volumes[(self.select - 8) as usize] = val & 0b00011111,
+            0xb => self.envelope_period = (self.envelope_period & 0xff00) | val as u16,
+            0xc => self.envelope_period = (self.envelope_period & 0x00ff) | (val as u16) << 8,
+            0xd => {
+                self.envelope_shape = val & 0x0f;
+                self.envelope_step = 0;
+                self.envelope_attack = val & 0b0100 != 0;
+                self.envelope_holding = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.prescaler = (self.prescaler + 1) % 16;
+        if self.prescaler != 0 {
+            return;
+        }
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_periods[channel] {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period {
+            self.noise_counter = 0;
+            let feedback = (self.noise ^ (self.noise >> 3)) & 1;
+            self.noise = (self.noise >> 1) | (feedback << 16);
+        }
+
+        self.envelope_counter += 1;
+        if self.envelope_counter >= self.envelope_period {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        let (keep_going, alternate, hold) = (
+            self.envelope_shape & 0b1000 != 0,
+            self.envelope_shape & 0b0010 != 0,
+            self.envelope_shape & 0b0001 != 0,
+        );
+        if !keep_going {
+            // Fall silent at the end of the first ramp.
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if hold {
+            self.envelope_attack ^= alternate;
+            self.envelope_holding = true;
+        } else {
+            self.envelope_attack ^= alternate;
+            self.envelope_step = 0;
+        }
+    }
+
+    /// The 5-bit level of `channel`, on the envelope's scale.
+    fn level(&self, channel: usize) -> u8 {
+        let volume = self.volumes[channel];
+        if volume & 0b10000 != 0 {
+            if self.envelope_attack {
+                self.envelope_step
+            } else {
+                31 - self.envelope_step
+            }
+        } else if volume == 0 {
+            0
+        } else {
+            volume * 2 + 1
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise & 1 != 0;
+        let mut sum = 0.0;
+        for channel in 0..3 {
+            let tone_on = self.tone_outputs[channel] || self.mixer & (1 << channel) != 0;
+            let noise_on = noise || self.mixer & (8 << channel) != 0;
+            let level = self.level(channel);
+            if tone_on && noise_on && level > 0 {
+                // Each level is 1.5dB above the one below.
+                sum += 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0);
+            }
+        }
+        sum / 3.0
+    }
+}
@@ -0,0 +1,150 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 1: registers loaded one bit at a time through a 5-bit shift
+/// register, switching PRG in 16KB or 32KB banks and CHR in 4KB or 8KB
+/// banks, with software-controlled mirroring.
+pub struct Mmc1 {
+    memory: CartridgeMemory,
+    shift: u8,
+    /// Number of bits in `shift`.
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    /// Cycles since the last write to the shift register.
+    cycles_since_write: u64,
+}
+
+impl Mmc1 {
+    pub fn new(memory: CartridgeMemory) -> Mmc1 {
+        Mmc1 {
+            memory,
+            shift: 0,
+            shift_count: 0,
+            // Power up with the last PRG bank fixed at $C000, so the reset
+            // vector is always found.
+            control: 0b01100,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycles_since_write: u64::MAX,
+        }
+    }
+
+    fn write_register(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x8000..=0x9fff => self.control = val,
+            0xa000..=0xbfff => self.chr_bank_0 = val,
+            0xc000..=0xdfff => self.chr_bank_1 = val,
+            _ => self.prg_bank = val,
+        }
+    }
+
+    /// 512KB boards (SUROM) use bit 4 of the CHR bank to pick which 256KB
+    /// half of PRG ROM the PRG bank indexes.
+    fn prg_outer_bank(&self) -> usize {
+        if self.memory.prg_banks(0x4000) > 16 {
+            (self.chr_bank_0 & 0b00010000) as usize
+        } else {
+            0
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b00010000 == 0
+    }
+
+    /// The 4KB CHR bank at `addr`.
+    fn chr_bank(&self, addr: u16) -> usize {
+        if self.control & 0b10000 == 0 {
+            // 8KB mode ignores the low bit.
+            (self.chr_bank_0 & !1) as usize + (addr >= 0x1000) as usize
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 {
+            return self.prg_ram_enabled().then(|| self.memory.read_prg_ram(addr));
+        }
+
+        let bank = (self.prg_bank & 0b01111) as usize;
+        let last = 0b01111;
+        let bank = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) + (addr >= 0xc000) as usize,
+            2 if addr < 0xc000 => 0,
+            2 => bank,
+            _ if addr < 0xc000 => bank,
+            _ => last,
+        };
+        Some(self.memory.read_prg(0x4000, self.prg_outer_bank() | bank, addr))
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        if addr < 0x8000 {
+            if self.prg_ram_enabled() {
+                self.memory.write_prg_ram(val, addr);
+            }
+            return;
+        }
+
+        // The serial port ignores the second of two writes on consecutive
+        // cycles, such as the dummy write of a read-modify-write.
+        let consecutive = self.cycles_since_write == 1;
+        self.cycles_since_write = 0;
+        if consecutive {
+            return;
+        }
+
+        if val & 0b10000000 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0b01100;
+            return;
+        }
+
+        self.shift |= (val & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+            self.write_register(self.shift, addr);
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x1000, self.chr_bank(addr), addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x1000, self.chr_bank(addr), val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycles_since_write = self.cycles_since_write.saturating_add(1);
+    }
+}
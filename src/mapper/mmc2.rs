@@ -0,0 +1,123 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// The CHR switching the MMC2 and MMC4 share: each 4KB pattern table has a
+/// bank for when its latch holds $FD and one for $FE, and the PPU fetching
+/// tile $FD or $FE flips the latch.
+pub(super) struct ChrLatches {
+    /// The $FD and $FE banks for $0000, then for $1000.
+    banks: [u8; 4],
+    /// Whether each pattern table's latch holds $FE.
+    latches: [bool; 2],
+}
+
+impl ChrLatches {
+    pub(super) fn new() -> ChrLatches {
+        ChrLatches {
+            banks: [0; 4],
+            latches: [true; 2],
+        }
+    }
+
+    /// Writes one of the bank registers at $B000-$EFFF.
+    pub(super) fn write(&mut self, val: u8, addr: u16) {
+        self.banks[((addr >> 12) - 0xb) as usize] = val & 0b00011111;
+    }
+
+    /// The 4KB CHR bank at `addr`.
+    pub(super) fn bank(&self, addr: u16) -> usize {
+        let table = (addr >> 12) as usize & 1;
+        self.banks[table * 2 + self.latches[table] as usize] as usize
+    }
+
+    /// Flips a latch if the PPU just read from tile $FD or $FE. The switch
+    /// takes effect from the next fetch on. The MMC2 only watches the
+    /// second half of the tile at $0000, the MMC4 all of it, like both do
+    /// for $1000.
+    pub(super) fn watch(&mut self, addr: u16, whole_low_tile: bool) {
+        match addr {
+            0x0fd8 => self.latches[0] = false,
+            0x0fe8 => self.latches[0] = true,
+            0x0fd9..=0x0fdf if whole_low_tile => self.latches[0] = false,
+            0x0fe9..=0x0fef if whole_low_tile => self.latches[0] = true,
+            0x1fd8..=0x1fdf => self.latches[1] = false,
+            0x1fe8..=0x1fef => self.latches[1] = true,
+            _ => {}
+        }
+    }
+}
+
+/// Mapper 9: a switchable 8KB PRG bank at $8000 with the rest fixed to the
+/// last three banks, and CHR latches switched by the tiles being drawn.
+/// Punch-Out!! uses it to swap pattern tables mid-screen without IRQs.
+pub struct Mmc2 {
+    memory: CartridgeMemory,
+    prg_bank: u8,
+    chr: ChrLatches,
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(memory: CartridgeMemory) -> Mmc2 {
+        Mmc2 {
+            memory,
+            prg_bank: 0,
+            chr: ChrLatches::new(),
+            mirroring: Mirroring::Vertical,
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 {
+            // PNROM boards have no PRG-RAM.
+            return None;
+        }
+
+        let banks = self.memory.prg_banks(0x2000);
+        let bank = match addr {
+            0x8000..=0x9fff => self.prg_bank as usize,
+            // The last three banks, whatever the ROM size.
+            _ => (banks + ((addr - 0x8000) / 0x2000) as usize).wrapping_sub(4),
+        };
+        Some(self.memory.read_prg(0x2000, bank, addr))
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0xa000..=0xafff => self.prg_bank = val & 0b00001111,
+            0xb000..=0xefff => self.chr.write(val, addr),
+            0xf000..=0xffff => {
+                self.mirroring = if val & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x1000, self.chr.bank(addr), addr)
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let val = self.ppu_peek(addr);
+        self.chr.watch(addr, false);
+        val
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x1000, self.chr.bank(addr), val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
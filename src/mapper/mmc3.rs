@@ -0,0 +1,168 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// CPU cycles PPU A12 has to stay low before a rise clocks the IRQ counter.
+/// The MMC3 filters out the short drops between sprite pattern fetches this
+/// way.
+const A12_FILTER_CYCLES: u64 = 3;
+
+/// Mapper 4: 8KB PRG banks, 1KB and 2KB CHR banks, and a scanline counter
+/// clocked by rising edges of PPU A12 that raises IRQ when it reaches zero.
+pub struct Mmc3 {
+    memory: CartridgeMemory,
+    /// Register written by the next $8001 write.
+    bank_select: u8,
+    /// R0-R5 are CHR banks, R6 and R7 PRG banks.
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    a12: bool,
+    /// Value of `cycles` when A12 last went low.
+    a12_low_at: u64,
+    cycles: u64,
+}
+
+impl Mmc3 {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Mmc3 {
+        Mmc3 {
+            memory,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            a12: false,
+            a12_low_at: 0,
+            cycles: 0,
+        }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize {
+        let second_last = self.memory.prg_banks(0x2000).saturating_sub(2);
+        let swapped = self.bank_select & 0b01000000 != 0;
+        match (addr - 0x8000) / 0x2000 {
+            0 if swapped => second_last,
+            0 => self.registers[6] as usize,
+            1 => self.registers[7] as usize,
+            2 if swapped => self.registers[6] as usize,
+            2 => second_last,
+            _ => second_last + 1,
+        }
+    }
+
+    /// The 1KB CHR bank at `addr`.
+    fn chr_bank(&self, addr: u16) -> usize {
+        // Inversion swaps the 2KB banks at $0000 with the 1KB banks at $1000.
+        let addr = if self.bank_select & 0b10000000 != 0 { addr ^ 0x1000 } else { addr };
+        match addr / 0x400 {
+            0 => (self.registers[0] & !1) as usize,
+            1 => (self.registers[0] | 1) as usize,
+            2 => (self.registers[1] & !1) as usize,
+            3 => (self.registers[1] | 1) as usize,
+            page => self.registers[page as usize - 2] as usize,
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram_enabled.then(|| self.memory.read_prg_ram(addr)),
+            _ => Some(self.memory.read_prg(0x2000, self.prg_bank(addr), addr)),
+        }
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7fff => {
+                if self.prg_ram_enabled && !self.prg_ram_write_protected {
+                    self.memory.write_prg_ram(val, addr);
+                }
+            }
+            0x8000..=0x9fff if even => self.bank_select = val,
+            0x8000..=0x9fff => self.registers[(self.bank_select & 0b111) as usize] = val,
+            0xa000..=0xbfff if even => {
+                if self.mirroring != Mirroring::FourScreen {
+                    self.mirroring = if val & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+                }
+            }
+            0xa000..=0xbfff => {
+                self.prg_ram_enabled = val & 0b10000000 != 0;
+                self.prg_ram_write_protected = val & 0b01000000 != 0;
+            }
+            0xc000..=0xdfff if even => self.irq_latch = val,
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            _ if even => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x400, self.chr_bank(addr), addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x400, self.chr_bank(addr), val, addr);
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycles - self.a12_low_at >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_low_at = self.cycles;
+        }
+        self.a12 = a12;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycles += 1;
+    }
+}
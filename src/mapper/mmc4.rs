@@ -0,0 +1,75 @@
+use super::mmc2::ChrLatches;
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 10: the MMC2's CHR latches with a switchable 16KB PRG bank at
+/// $8000, the last bank fixed at $C000, and 8KB of PRG-RAM.
+pub struct Mmc4 {
+    memory: CartridgeMemory,
+    prg_bank: u8,
+    chr: ChrLatches,
+    mirroring: Mirroring,
+}
+
+impl Mmc4 {
+    pub fn new(memory: CartridgeMemory) -> Mmc4 {
+        Mmc4 {
+            memory,
+            prg_bank: 0,
+            chr: ChrLatches::new(),
+            mirroring: Mirroring::Vertical,
+        }
+    }
+}
+
+impl Mapper for Mmc4 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            0x6000..=0x7fff => self.memory.read_prg_ram(addr),
+            0x8000..=0xbfff => self.memory.read_prg(0x4000, self.prg_bank as usize, addr),
+            _ => {
+                let last = self.memory.prg_banks(0x4000) - 1;
+                self.memory.read_prg(0x4000, last, addr)
+            }
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x6000..=0x7fff => self.memory.write_prg_ram(val, addr),
+            0xa000..=0xafff => self.prg_bank = val & 0b00001111,
+            0xb000..=0xefff => self.chr.write(val, addr),
+            0xf000..=0xffff => {
+                self.mirroring = if val & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x1000, self.chr.bank(addr), addr)
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let val = self.ppu_peek(addr);
+        self.chr.watch(addr, true);
+        val
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x1000, self.chr.bank(addr), val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
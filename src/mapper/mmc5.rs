@@ -0,0 +1,614 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// CPU cycles without a PPU fetch after which the MMC5 decides the PPU has
+/// stopped rendering.
+const IDLE_CYCLES: u8 = 3;
+
+/// Pattern fetches on a scanline before the eight sprites' fetches, which
+/// take the next 16.
+const BACKGROUND_FETCHES: u8 = 64;
+const SPRITE_FETCHES: u8 = 16;
+
+/// Mapper 5: Nintendo's MMC5, the most capable board licensed for the NES.
+///
+/// PRG is banked in one of four layouts mixing ROM and up to 64KB of RAM,
+/// and CHR in 1KB to 8KB banks with separate sets for 8x16 sprites and the
+/// background. 1KB of ExRAM can serve as a third nametable, as per-tile
+/// attributes and CHR banks, or as plain RAM, and a fill mode paints a
+/// whole nametable with one tile. The chip also splits the screen
+/// vertically, multiplies, counts scanlines for an IRQ and has two pulse
+/// channels and a PCM channel of its own.
+///
+/// The MMC5 has no scanline input: it works out where the PPU is from the
+/// fetches it sees. Three fetches of the same nametable byte in a row
+/// start a scanline, after which it counts pattern fetches to tell the
+/// sprites from the background, and a few idle cycles mean rendering has
+/// stopped.
+pub struct Mmc5 {
+    memory: CartridgeMemory,
+    prg_mode: u8,
+    chr_mode: u8,
+    /// $5102 and $5103, which must hold 2 and 1 for PRG-RAM writes.
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    /// Two bits per nametable: 0 and 1 are nametable RAM pages, 2 is ExRAM
+    /// and 3 the fill tile.
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_ram_bank: u8,
+    /// Banks at $8000-$FFFF as written to $5114-$5117. Bit 7 selects ROM.
+    prg_banks: [u8; 4],
+    /// $5120-$5127 for sprites, $5128-$512B for the background.
+    chr_banks: [u16; 12],
+    /// Bits 8 and 9 for the next CHR bank written.
+    chr_upper: u8,
+    /// Whether the last CHR bank written was a background one.
+    background_chr_written: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    exram: [u8; 0x400],
+    /// PPUCTRL's sprite size bit.
+    large_sprites: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_fetch: u16,
+    /// How many times in a row `last_fetch` was fetched again.
+    fetch_repeats: u8,
+    cycles_since_fetch: u8,
+    /// Pattern fetches since the scanline started.
+    pattern_fetches: u8,
+    /// Column of the tile being fetched, and of the next one.
+    tile: u8,
+    next_tile: u8,
+    /// The tile's ExRAM byte in extended attribute mode.
+    tile_exram: u8,
+    /// Whether the tile is in the split region.
+    split_tile: bool,
+    audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    pub fn new(memory: CartridgeMemory) -> Mmc5 {
+        Mmc5 {
+            memory,
+            // Power up in 8KB mode with the last bank at $E000.
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_ram_bank: 0,
+            prg_banks: [0x80, 0x80, 0x80, 0xff],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            background_chr_written: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            exram: [0; 0x400],
+            large_sprites: false,
+            in_frame: false,
+            scanline: 0,
+            last_fetch: 0,
+            fetch_repeats: 0,
+            cycles_since_fetch: 0,
+            pattern_fetches: 0,
+            tile: 0,
+            next_tile: 0,
+            tile_exram: 0,
+            split_tile: false,
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    /// The 8KB bank at `addr` in $8000-$FFFF, with bit 7 set for ROM.
+    fn prg_bank(&self, addr: u16) -> u8 {
+        let slot = ((addr - 0x8000) / 0x2000) as u8;
+        match (self.prg_mode, slot) {
+            (0, _) => (self.prg_banks[3] & !0b11) | slot,
+            (1, 0 | 1) => (self.prg_banks[1] & !1) | slot,
+            (1, _) => (self.prg_banks[3] & !1) | (slot & 1),
+            (2, 0 | 1) => (self.prg_banks[1] & !1) | slot,
+            _ => self.prg_banks[slot as usize],
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect[0] & 0b11 == 0b10 && self.prg_ram_protect[1] & 0b11 == 0b01
+    }
+
+    /// The bank size and bank for `addr` in the pattern tables.
+    fn chr_bank(&self, addr: u16, background: bool) -> (usize, usize) {
+        let pages = 1 << (3 - self.chr_mode);
+        let page = (addr / 0x400) as usize | (pages - 1);
+        let register = if background { 8 + (page & 0b11) } else { page };
+        (0x400 * pages, self.chr_banks[register] as usize)
+    }
+
+    /// Outside rendering the sprite and background sets can't be told
+    /// apart, so the one written last applies. They are only separate with
+    /// 8x16 sprites.
+    fn idle_chr_bank(&self, addr: u16) -> (usize, usize) {
+        self.chr_bank(addr, self.large_sprites && self.background_chr_written)
+    }
+
+    fn fetching_sprites(&self) -> bool {
+        (BACKGROUND_FETCHES..BACKGROUND_FETCHES + SPRITE_FETCHES).contains(&self.pattern_fetches)
+    }
+
+    /// The scanline within the split region's own scroll. The last two
+    /// tiles of a scanline are fetched for the next one.
+    fn split_y(&self) -> usize {
+        let next_line = self.pattern_fetches >= BACKGROUND_FETCHES + SPRITE_FETCHES;
+        (self.split_scroll as usize + self.scanline as usize + next_line as usize) % 240
+    }
+
+    fn in_split(&self, column: u8) -> bool {
+        if self.split_control & 0b10000000 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let threshold = self.split_control & 0b00011111;
+        if self.split_control & 0b01000000 != 0 {
+            column >= threshold
+        } else {
+            column < threshold
+        }
+    }
+
+    fn start_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_scanline {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+        self.pattern_fetches = 0;
+        // The fetch that gave the scanline away is its third tile's.
+        self.next_tile = 2;
+    }
+
+    fn leave_frame(&mut self) {
+        self.in_frame = false;
+        self.fetch_repeats = 0;
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let bank = match addr {
+            0x6000..=0x7fff => return Some(self.memory.read_prg_ram_bank(self.prg_ram_bank as usize, addr)),
+            _ => self.prg_bank(addr),
+        };
+        let val = if bank & 0x80 != 0 {
+            self.memory.read_prg(0x2000, (bank & 0x7f) as usize, addr)
+        } else {
+            self.memory.read_prg_ram_bank((bank & 0b111) as usize, addr)
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        if !self.prg_ram_writable() {
+            return;
+        }
+        let bank = match addr {
+            0x6000..=0x7fff => self.prg_ram_bank,
+            0x8000..=0xdfff => self.prg_bank(addr),
+            _ => return,
+        };
+        if bank & 0x80 == 0 {
+            self.memory.write_prg_ram_bank((bank & 0b111) as usize, val, addr);
+        }
+    }
+
+    fn expansion_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5015 => Some(self.audio.status()),
+            0x5204 => Some((self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6),
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5c00..=0x5fff if self.exram_mode >= 2 => Some(self.exram[(addr - 0x5c00) as usize]),
+            _ => None,
+        }
+    }
+
+    fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+        let val = self.expansion_peek(addr);
+        if addr == 0x5204 {
+            self.irq_pending = false;
+        }
+        val
+    }
+
+    fn expansion_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x5000..=0x5015 => self.audio.write(val, addr),
+            0x5100 => self.prg_mode = val & 0b11,
+            0x5101 => self.chr_mode = val & 0b11,
+            0x5102 | 0x5103 => self.prg_ram_protect[(addr - 0x5102) as usize] = val,
+            0x5104 => self.exram_mode = val & 0b11,
+            0x5105 => self.nametable_mapping = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attribute = val & 0b11,
+            0x5113 => self.prg_ram_bank = val & 0b111,
+            0x5114..=0x5116 => self.prg_banks[(addr - 0x5114) as usize] = val,
+            // $E000 is always ROM.
+            0x5117 => self.prg_banks[3] = val | 0x80,
+            0x5120..=0x512b => {
+                self.chr_banks[(addr - 0x5120) as usize] = val as u16 | (self.chr_upper as u16) << 8;
+                self.background_chr_written = addr >= 0x5128;
+            }
+            0x5130 => self.chr_upper = val & 0b11,
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_scanline = val,
+            0x5204 => self.irq_enabled = val & 0b10000000 != 0,
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            0x5c00..=0x5fff => {
+                let i = (addr - 0x5c00) as usize;
+                match self.exram_mode {
+                    // While the PPU may be using it, writes only land
+                    // during rendering; at other times they write zero.
+                    0 | 1 => self.exram[i] = if self.in_frame { val } else { 0 },
+                    2 => self.exram[i] = val,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_register_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x2000 => self.large_sprites = val & 0b00100000 != 0,
+            0x2001 if val & 0b00011000 == 0 => self.leave_frame(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        let (size, bank) = self.idle_chr_bank(addr);
+        self.memory.read_chr(size, bank, addr)
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.cycles_since_fetch = 0;
+        self.last_fetch = addr;
+        self.fetch_repeats = 0;
+        if !self.in_frame {
+            return self.ppu_peek(addr);
+        }
+
+        let sprite = self.fetching_sprites();
+        let val = if !sprite && self.split_tile {
+            let addr = (addr & !0b111) | (self.split_y() & 0b111) as u16;
+            self.memory.read_chr(0x1000, self.split_bank as usize, addr)
+        } else if !sprite && self.exram_mode == 1 {
+            let bank = (self.tile_exram & 0b00111111) as usize | (self.chr_upper as usize) << 6;
+            self.memory.read_chr(0x1000, bank, addr)
+        } else {
+            let (size, bank) = self.chr_bank(addr, self.large_sprites && !sprite);
+            self.memory.read_chr(size, bank, addr)
+        };
+
+        self.pattern_fetches = self.pattern_fetches.saturating_add(1);
+        if self.pattern_fetches == BACKGROUND_FETCHES + SPRITE_FETCHES {
+            // On to the first two tiles of the next scanline.
+            self.next_tile = 0;
+        }
+        val
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        let (size, bank) = self.idle_chr_bank(addr);
+        self.memory.write_chr(size, bank, val, addr);
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        let offset = (addr & 0x3ff) as usize;
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset]),
+            2 => Some(0),
+            3 if offset < 0x3c0 => Some(self.fill_tile),
+            3 => Some(self.fill_attribute * 0b01010101),
+            _ => None,
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+        // Sprites fetch their nametable bytes twice too, but with pattern
+        // fetches in between.
+        self.cycles_since_fetch = 0;
+        if addr == self.last_fetch {
+            self.fetch_repeats = self.fetch_repeats.saturating_add(1);
+            if self.fetch_repeats == 2 {
+                self.start_scanline();
+            }
+        } else {
+            self.fetch_repeats = 0;
+            self.last_fetch = addr;
+        }
+
+        if !self.in_frame || self.fetching_sprites() {
+            return self.nametable_peek(addr);
+        }
+
+        let offset = (addr & 0x3ff) as usize;
+        if offset < 0x3c0 {
+            self.tile = self.next_tile;
+            self.next_tile = self.next_tile.wrapping_add(1);
+            self.split_tile = self.in_split(self.tile);
+            if self.split_tile {
+                let row = self.split_y() / 8;
+                return Some(self.exram[row * 32 + (self.tile & 0x1f) as usize]);
+            }
+            self.tile_exram = self.exram[offset];
+            self.nametable_peek(addr)
+        } else if self.split_tile {
+            let y = self.split_y();
+            let column = (self.tile & 0x1f) as usize;
+            let attribute = self.exram[0x3c0 + y / 32 * 8 + column / 4];
+            let shift = (y / 16 % 2) * 4 + (column / 2 % 2) * 2;
+            Some(((attribute >> shift) & 0b11) * 0b01010101)
+        } else if self.exram_mode == 1 {
+            Some((self.tile_exram >> 6) * 0b01010101)
+        } else {
+            self.nametable_peek(addr)
+        }
+    }
+
+    fn nametable_write(&mut self, val: u8, addr: u16) -> bool {
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[(addr & 0x3ff) as usize] = val;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn nametable_page(&self, addr: u16) -> usize {
+        ((self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 1) as usize
+    }
+
+    /// The closest standard arrangement to the nametable mapping;
+    /// `nametable_page` has the exact one.
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x50 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycles_since_fetch = self.cycles_since_fetch.saturating_add(1);
+        if self.cycles_since_fetch == IDLE_CYCLES {
+            self.leave_frame();
+        }
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+/// CPU cycles between the 240Hz clocks of the envelopes and length
+/// counters.
+const QUARTER_FRAME_CYCLES: u16 = 7457;
+
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16,
+    28, 32, 30,
+];
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Two copies of the APU's pulse channel, without sweep, and an 8-bit PCM
+/// channel written directly by the CPU.
+struct Mmc5Audio {
+    pulses: [Mmc5Pulse; 2],
+    pcm: u8,
+    frame_cycles: u16,
+    /// The pulse timers run at half the CPU clock.
+    odd_cycle: bool,
+}
+
+impl Mmc5Audio {
+    fn new() -> Mmc5Audio {
+        Mmc5Audio {
+            pulses: [Mmc5Pulse::new(), Mmc5Pulse::new()],
+            pcm: 0,
+            frame_cycles: 0,
+            odd_cycle: false,
+        }
+    }
+
+    fn write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x5000..=0x5003 => self.pulses[0].write(val, addr & 0b11),
+            0x5004..=0x5007 => self.pulses[1].write(val, addr & 0b11),
+            // Writing zero does nothing in write mode.
+            0x5011 if val != 0 => self.pcm = val,
+            0x5015 => {
+                self.pulses[0].set_enabled(val & 0b01 != 0);
+                self.pulses[1].set_enabled(val & 0b10 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    fn status(&self) -> u8 {
+        (self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1
+    }
+
+    fn tick(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in &mut self.pulses {
+                pulse.tick();
+            }
+        }
+
+        self.frame_cycles += 1;
+        if self.frame_cycles == QUARTER_FRAME_CYCLES {
+            self.frame_cycles = 0;
+            for pulse in &mut self.pulses {
+                pulse.quarter_frame();
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        // Full-scale PCM is about as loud as a full-volume pulse.
+        let pulses = self.pulses[0].output() + self.pulses[1].output();
+        (pulses as f32 + self.pcm as f32 / 17.0) / 45.0
+    }
+}
+
+struct Mmc5Pulse {
+    duty: u8,
+    /// Halts the length counter and loops the envelope.
+    halt: bool,
+    constant_volume: bool,
+    /// The constant volume, or the envelope's period.
+    volume: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+    enabled: bool,
+    length: u8,
+    envelope_start: bool,
+    envelope_divider: u8,
+    decay: u8,
+}
+
+impl Mmc5Pulse {
+    fn new() -> Mmc5Pulse {
+        Mmc5Pulse {
+            duty: 0,
+            halt: false,
+            constant_volume: false,
+            volume: 0,
+            period: 0,
+            timer: 0,
+            step: 0,
+            enabled: false,
+            length: 0,
+            envelope_start: false,
+            envelope_divider: 0,
+            decay: 0,
+        }
+    }
+
+    fn write(&mut self, val: u8, reg: u16) {
+        match reg {
+            0 => {
+                self.duty = val >> 6;
+                self.halt = val & 0b00100000 != 0;
+                self.constant_volume = val & 0b00010000 != 0;
+                self.volume = val & 0x0f;
+            }
+            2 => self.period = (self.period & 0x700) | val as u16,
+            3 => {
+                self.period = (self.period & 0x0ff) | ((val & 0b111) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTHS[(val >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn quarter_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.halt {
+                self.decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// CPU cycles the sound hardware spends on each channel before moving on.
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+/// Mapper 19: Namco's 163. Three switchable 8KB PRG banks, eight 1KB CHR
+/// banks, nametables that can come from CHR ROM, a 15-bit IRQ counter and
+/// up to eight wavetable sound channels played from 128 bytes of internal
+/// RAM.
+///
+/// CHR banks $E0-$FF can select the console's nametable RAM as pattern
+/// tables on the real chip; here they index CHR ROM like any other bank.
+pub struct Namco163 {
+    memory: CartridgeMemory,
+    chr_banks: [u8; 8],
+    /// Values of $E0 and up select nametable RAM page 0 or 1, anything
+    /// else a 1KB page of CHR ROM.
+    nametable_banks: [u8; 4],
+    prg_banks: [u8; 3],
+    /// Also the address port for the sound RAM: bits 0-6 are the address,
+    /// bit 7 auto-increments it. The high nibble must be $4 for PRG-RAM
+    /// writes, and bits 0-3 protect its 2KB quarters.
+    f800: u8,
+    mirroring: Mirroring,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq: bool,
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Namco163 {
+        Namco163 {
+            memory,
+            chr_banks: [0; 8],
+            nametable_banks: [0xe0, 0xe1, 0xe0, 0xe1],
+            prg_banks: [0; 3],
+            f800: 0,
+            mirroring,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn sound_address(&self) -> usize {
+        (self.f800 & 0b01111111) as usize
+    }
+
+    fn increment_sound_address(&mut self) {
+        if self.f800 & 0b10000000 != 0 {
+            self.f800 = 0b10000000 | (self.f800.wrapping_add(1) & 0b01111111);
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let bank = match addr {
+            0x6000..=0x7fff => return Some(self.memory.read_prg_ram(addr)),
+            0xe000..=0xffff => self.memory.prg_banks(0x2000) - 1,
+            _ => self.prg_banks[((addr - 0x8000) / 0x2000) as usize] as usize,
+        };
+        Some(self.memory.read_prg(0x2000, bank, addr))
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x6000..=0x7fff => {
+                let quarter = (addr - 0x6000) / 0x800;
+                if self.f800 & 0xf0 == 0x40 && self.f800 & (1 << quarter) == 0 {
+                    self.memory.write_prg_ram(val, addr);
+                }
+            }
+            0x8000..=0xbfff => self.chr_banks[((addr - 0x8000) / 0x800) as usize] = val,
+            0xc000..=0xdfff => self.nametable_banks[((addr - 0xc000) / 0x800) as usize] = val,
+            0xe000..=0xe7ff => {
+                self.prg_banks[0] = val & 0b00111111;
+                self.audio.enabled = val & 0b01000000 == 0;
+            }
+            0xe800..=0xefff => self.prg_banks[1] = val & 0b00111111,
+            0xf000..=0xf7ff => self.prg_banks[2] = val & 0b00111111,
+            _ => self.f800 = val,
+        }
+    }
+
+    fn expansion_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4fff => Some(self.audio.ram[self.sound_address()]),
+            0x5000..=0x57ff => Some(self.irq_counter as u8),
+            0x5800..=0x5fff => Some((self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7),
+            _ => None,
+        }
+    }
+
+    fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+        let val = self.expansion_peek(addr);
+        if let 0x4800..=0x4fff = addr {
+            self.increment_sound_address();
+        }
+        val
+    }
+
+    fn expansion_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x4800..=0x4fff => {
+                self.audio.ram[self.sound_address()] = val;
+                self.increment_sound_address();
+            }
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | val as u16;
+                self.irq = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | ((val & 0b01111111) as u16) << 8;
+                self.irq_enabled = val & 0b10000000 != 0;
+                self.irq = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x400, self.chr_banks[(addr / 0x400) as usize] as usize, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x400, self.chr_banks[(addr / 0x400) as usize] as usize, val, addr);
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        let bank = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        (bank < 0xe0).then(|| self.memory.read_chr(0x400, bank as usize, addr))
+    }
+
+    fn nametable_write(&mut self, _val: u8, addr: u16) -> bool {
+        // Nametables in CHR ROM ignore writes.
+        self.nametable_banks[((addr >> 10) & 0b11) as usize] < 0xe0
+    }
+
+    fn nametable_page(&self, addr: u16) -> usize {
+        (self.nametable_banks[((addr >> 10) & 0b11) as usize] & 1) as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7fff {
+                self.irq = true;
+            }
+        }
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+/// Wavetable channels sharing one DAC. Each channel's registers are the
+/// last eight bytes of a 64-byte block at the top of the sound RAM,
+/// channel 7 at $78 and channel 0 at $40; the rest of the RAM holds 4-bit
+/// samples, two to a byte.
+struct Namco163Audio {
+    ram: [u8; 0x80],
+    enabled: bool,
+    /// Channel being updated, counting down from 7.
+    channel: u8,
+    cycles: u8,
+    /// Last sample times volume each channel produced.
+    outputs: [u8; 8],
+}
+
+impl Namco163Audio {
+    fn new() -> Namco163Audio {
+        Namco163Audio {
+            ram: [0; 0x80],
+            enabled: true,
+            channel: 7,
+            cycles: 0,
+            outputs: [0; 8],
+        }
+    }
+
+    /// Number of channels playing, 1-8. The highest numbered ones play.
+    fn channels(&self) -> u8 {
+        ((self.ram[0x7f] >> 4) & 0b111) + 1
+    }
+
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.cycles = 0;
+
+        self.update_channel(self.channel as usize);
+        let lowest = 8 - self.channels();
+        self.channel = if self.channel <= lowest { 7 } else { self.channel - 1 };
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let regs = 0x40 + channel * 8;
+        let frequency = u32::from_le_bytes([self.ram[regs], self.ram[regs + 2], self.ram[regs + 4] & 0b11, 0]);
+        let phase = u32::from_le_bytes([self.ram[regs + 1], self.ram[regs + 3], self.ram[regs + 5], 0]);
+        let length = 256 - (self.ram[regs + 4] & 0b11111100) as u32;
+        let phase = (phase + frequency) % (length << 16);
+        self.ram[regs + 1] = phase as u8;
+        self.ram[regs + 3] = (phase >> 8) as u8;
+        self.ram[regs + 5] = (phase >> 16) as u8;
+
+        let index = ((phase >> 16) + self.ram[regs + 6] as u32) as u8 as usize;
+        let byte = self.ram[index / 2];
+        let sample = if index & 1 == 0 { byte & 0x0f } else { byte >> 4 };
+        self.outputs[channel] = sample * (self.ram[regs + 7] & 0x0f);
+    }
+
+    fn output(&self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+        // The chip plays the channels one after another, so more channels
+        // means each is heard for less of the time.
+        let channels = self.channels();
+        let sum: u32 = self.outputs[(8 - channels) as usize..].iter().map(|&out| out as u32).sum();
+        sum as f32 / (225.0 * channels as f32)
+    }
+}
@@ -0,0 +1,50 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 0: no bank switching. 16KB of PRG ROM repeats at $C000.
+pub struct Nrom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Nrom {
+        Nrom { memory, mirroring }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            0x6000..=0x7fff => self.memory.read_prg_ram(addr),
+            _ => self.memory.read_prg(0x8000, 0, addr),
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        if let 0x6000..=0x7fff = addr {
+            self.memory.write_prg_ram(val, addr);
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, 0, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x2000, 0, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
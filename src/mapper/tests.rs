@@ -0,0 +1,747 @@
+use super::*;
+
+/// Builds mapper `number` over `prg_banks` 16KB banks of PRG ROM, each
+/// filled with its bank number, and `chr_banks` 8KB banks of CHR ROM whose
+/// 1KB pages are filled with their page number. No CHR ROM means CHR-RAM.
+fn mapper(number: u8, prg_banks: u8, chr_banks: u8) -> Box<dyn Mapper> {
+    let mut data = b"NES\x1a".to_vec();
+    data.extend([prg_banks, chr_banks, number << 4 | 0x01, number & 0xf0]);
+    data.resize(16, 0);
+    for bank in 0..prg_banks {
+        data.extend([bank; 0x4000]);
+    }
+    for page in 0..chr_banks * 8 {
+        data.extend([page; 0x400]);
+    }
+    from_rom(Rom::from_bytes(&data).unwrap()).ok().unwrap()
+}
+
+fn prg(mapper: &dyn Mapper, addr: u16) -> u8 {
+    mapper.cpu_peek(addr).unwrap()
+}
+
+#[test]
+fn nrom_mirrors_16kb_of_prg() {
+    let mut nrom = mapper(0, 1, 1);
+    assert_eq!(prg(&*nrom, 0x8000), 0);
+    assert_eq!(prg(&*nrom, 0xffff), 0);
+    assert_eq!(nrom.mirroring(), Mirroring::Vertical);
+
+    nrom.cpu_write(0x42, 0x6000);
+    assert_eq!(prg(&*nrom, 0x6000), 0x42);
+    nrom.cpu_write(0x42, 0x8000);
+    assert_eq!(prg(&*nrom, 0x8000), 0);
+}
+
+#[test]
+fn uxrom_switches_the_low_16kb() {
+    let mut uxrom = mapper(2, 8, 0);
+    assert_eq!(prg(&*uxrom, 0xc000), 7);
+
+    uxrom.cpu_write(3, 0x8000);
+    assert_eq!(prg(&*uxrom, 0x8000), 3);
+    assert_eq!(prg(&*uxrom, 0xbfff), 3);
+    assert_eq!(prg(&*uxrom, 0xc000), 7);
+
+    // Bank numbers wrap around the ROM size.
+    uxrom.cpu_write(9, 0xffff);
+    assert_eq!(prg(&*uxrom, 0x8000), 1);
+
+    uxrom.ppu_write(0x55, 0x1234);
+    assert_eq!(uxrom.ppu_peek(0x1234), 0x55);
+}
+
+#[test]
+fn cnrom_switches_8kb_of_chr() {
+    let mut cnrom = mapper(3, 2, 4);
+    assert_eq!(cnrom.ppu_peek(0x0000), 0);
+
+    cnrom.cpu_write(2, 0x8000);
+    assert_eq!(cnrom.ppu_peek(0x0000), 16);
+    assert_eq!(cnrom.ppu_peek(0x1fff), 23);
+    assert_eq!(prg(&*cnrom, 0x8000), 0);
+    assert_eq!(prg(&*cnrom, 0xc000), 1);
+
+    cnrom.ppu_write(0xff, 0x0000);
+    assert_eq!(cnrom.ppu_peek(0x0000), 16);
+}
+
+#[test]
+fn axrom_switches_32kb_and_one_screen_mirroring() {
+    let mut axrom = mapper(7, 16, 0);
+    assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+    axrom.cpu_write(0x13, 0x8000);
+    assert_eq!(prg(&*axrom, 0x8000), 6);
+    assert_eq!(prg(&*axrom, 0xc000), 7);
+    assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+
+    axrom.cpu_write(0x02, 0x8000);
+    assert_eq!(prg(&*axrom, 0x8000), 4);
+    assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+}
+
+/// Loads an MMC1 register through the serial port, a bit per write, as a
+/// program would with separate STA instructions.
+fn mmc1_write(mmc1: &mut dyn Mapper, val: u8, addr: u16) {
+    for bit in 0..5 {
+        mmc1.cpu_write(val >> bit & 1, addr);
+        for _ in 0..4 {
+            mmc1.cpu_tick();
+        }
+    }
+}
+
+#[test]
+fn mmc1_prg_banking_modes() {
+    let mut mmc1 = mapper(1, 16, 4);
+    assert_eq!(prg(&*mmc1, 0xc000), 15);
+
+    // Switch $8000, fix the last bank at $C000.
+    mmc1_write(&mut *mmc1, 0b01110, 0x8000);
+    mmc1_write(&mut *mmc1, 5, 0xe000);
+    assert_eq!(prg(&*mmc1, 0x8000), 5);
+    assert_eq!(prg(&*mmc1, 0xc000), 15);
+
+    // Fix the first bank at $8000, switch $C000.
+    mmc1_write(&mut *mmc1, 0b01010, 0x8000);
+    assert_eq!(prg(&*mmc1, 0x8000), 0);
+    assert_eq!(prg(&*mmc1, 0xc000), 5);
+
+    // 32KB mode ignores the low bit.
+    mmc1_write(&mut *mmc1, 0b00010, 0x8000);
+    assert_eq!(prg(&*mmc1, 0x8000), 4);
+    assert_eq!(prg(&*mmc1, 0xc000), 5);
+}
+
+#[test]
+fn mmc1_chr_banking_modes() {
+    let mut mmc1 = mapper(1, 2, 4);
+
+    // Two 4KB banks.
+    mmc1_write(&mut *mmc1, 0b11100, 0x8000);
+    mmc1_write(&mut *mmc1, 3, 0xa000);
+    mmc1_write(&mut *mmc1, 5, 0xc000);
+    assert_eq!(mmc1.ppu_peek(0x0000), 12);
+    assert_eq!(mmc1.ppu_peek(0x1000), 20);
+
+    // One 8KB bank, selected by the first register without its low bit.
+    mmc1_write(&mut *mmc1, 0b01100, 0x8000);
+    assert_eq!(mmc1.ppu_peek(0x0000), 8);
+    assert_eq!(mmc1.ppu_peek(0x1000), 12);
+}
+
+#[test]
+fn mmc1_mirroring_and_prg_ram_enable() {
+    let mut mmc1 = mapper(1, 2, 0);
+    for (control, mirroring) in [
+        (0b01100, Mirroring::SingleScreenLower),
+        (0b01101, Mirroring::SingleScreenUpper),
+        (0b01110, Mirroring::Vertical),
+        (0b01111, Mirroring::Horizontal),
+    ] {
+        mmc1_write(&mut *mmc1, control, 0x8000);
+        assert_eq!(mmc1.mirroring(), mirroring);
+    }
+
+    mmc1.cpu_write(0x99, 0x6000);
+    assert_eq!(mmc1.cpu_peek(0x6000), Some(0x99));
+    mmc1_write(&mut *mmc1, 0b10000, 0xe000);
+    assert_eq!(mmc1.cpu_peek(0x6000), None);
+}
+
+#[test]
+fn mmc1_resets_and_ignores_consecutive_writes() {
+    let mut mmc1 = mapper(1, 16, 0);
+    mmc1_write(&mut *mmc1, 0b00010, 0x8000);
+    mmc1_write(&mut *mmc1, 6, 0xe000);
+    assert_eq!(prg(&*mmc1, 0xc000), 7);
+
+    // A reset mid-sequence discards the bits so far and fixes the last bank.
+    mmc1.cpu_write(1, 0xe000);
+    mmc1.cpu_tick();
+    mmc1.cpu_tick();
+    mmc1.cpu_write(0x80, 0x8000);
+    mmc1.cpu_tick();
+    mmc1.cpu_tick();
+    assert_eq!(prg(&*mmc1, 0xc000), 15);
+    assert_eq!(prg(&*mmc1, 0x8000), 6);
+
+    // Like INC $E000 on a value of $FF: the write of $FF resets the shift
+    // register and the write of $00 on the next cycle is dropped.
+    mmc1_write(&mut *mmc1, 0b1111, 0xe000);
+    mmc1.cpu_write(0xff, 0xe000);
+    mmc1.cpu_tick();
+    mmc1.cpu_write(0x00, 0xe000);
+    mmc1.cpu_tick();
+    mmc1.cpu_tick();
+    mmc1_write(&mut *mmc1, 3, 0xe000);
+    assert_eq!(prg(&*mmc1, 0x8000), 3);
+}
+
+#[test]
+fn mmc3_prg_banking_modes() {
+    let mut mmc3 = mapper(4, 8, 0);
+    mmc3.cpu_write(6, 0x8000);
+    mmc3.cpu_write(3, 0x8001);
+    mmc3.cpu_write(7, 0x8000);
+    mmc3.cpu_write(5, 0x8001);
+
+    // 8KB banks: 16KB bank n holds 8KB banks 2n and 2n + 1.
+    assert_eq!(prg(&*mmc3, 0x8000), 1);
+    assert_eq!(prg(&*mmc3, 0xa000), 2);
+    assert_eq!(prg(&*mmc3, 0xc000), 7);
+    assert_eq!(prg(&*mmc3, 0xe000), 7);
+
+    mmc3.cpu_write(0x40, 0x8000);
+    assert_eq!(prg(&*mmc3, 0x8000), 7);
+    assert_eq!(prg(&*mmc3, 0xa000), 2);
+    assert_eq!(prg(&*mmc3, 0xc000), 1);
+}
+
+#[test]
+fn mmc3_chr_banking_and_inversion() {
+    let mut mmc3 = mapper(4, 2, 2);
+    for (reg, bank) in [3, 8, 10, 11, 12, 13].into_iter().enumerate() {
+        mmc3.cpu_write(reg as u8, 0x8000);
+        mmc3.cpu_write(bank, 0x8001);
+    }
+
+    let pages = |mmc3: &dyn Mapper| (0..8).map(|i| mmc3.ppu_peek(i * 0x400)).collect::<Vec<_>>();
+    assert_eq!(pages(&*mmc3), [2, 3, 8, 9, 10, 11, 12, 13]);
+
+    mmc3.cpu_write(0x80, 0x8000);
+    assert_eq!(pages(&*mmc3), [10, 11, 12, 13, 2, 3, 8, 9]);
+}
+
+#[test]
+fn mmc3_mirroring_and_prg_ram_protect() {
+    let mut mmc3 = mapper(4, 2, 0);
+    mmc3.cpu_write(1, 0xa000);
+    assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+    mmc3.cpu_write(0, 0xa000);
+    assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+
+    mmc3.cpu_write(0x80, 0xa001);
+    mmc3.cpu_write(0x11, 0x6000);
+    mmc3.cpu_write(0xc0, 0xa001);
+    mmc3.cpu_write(0x22, 0x6000);
+    assert_eq!(mmc3.cpu_peek(0x6000), Some(0x11));
+
+    mmc3.cpu_write(0x00, 0xa001);
+    assert_eq!(mmc3.cpu_peek(0x6000), None);
+}
+
+/// Runs one scanline's worth of PPU fetches with background patterns at
+/// $0000 and sprite patterns at $1000, for a single A12 rise.
+fn scanline(mmc3: &mut dyn Mapper) {
+    for _ in 0..85 {
+        mmc3.cpu_tick();
+        mmc3.ppu_address(0x2000);
+        mmc3.ppu_address(0x0000);
+    }
+    for _ in 0..28 {
+        mmc3.cpu_tick();
+        mmc3.ppu_address(0x1000);
+    }
+}
+
+#[test]
+fn mmc3_counts_scanlines_and_raises_irq() {
+    let mut mmc3 = mapper(4, 2, 0);
+    mmc3.cpu_write(3, 0xc000);
+    mmc3.cpu_write(0, 0xc001);
+    mmc3.cpu_write(0, 0xe001);
+
+    // The first clock reloads the counter, the next three count it down.
+    for _ in 0..3 {
+        scanline(&mut *mmc3);
+        assert!(!mmc3.irq());
+    }
+    scanline(&mut *mmc3);
+    assert!(mmc3.irq());
+
+    // Writing $E000 acknowledges and disables.
+    mmc3.cpu_write(0, 0xe000);
+    assert!(!mmc3.irq());
+    for _ in 0..8 {
+        scanline(&mut *mmc3);
+    }
+    assert!(!mmc3.irq());
+}
+
+#[test]
+fn mmc3_filters_short_a12_drops() {
+    let mut mmc3 = mapper(4, 2, 0);
+    mmc3.cpu_write(0, 0xc000);
+    mmc3.cpu_write(0, 0xc001);
+    mmc3.cpu_write(0, 0xe001);
+
+    // A12 toggling faster than the filter does not clock the counter.
+    for _ in 0..16 {
+        mmc3.ppu_address(0x1000);
+        mmc3.ppu_address(0x0000);
+    }
+    assert!(!mmc3.irq());
+
+    scanline(&mut *mmc3);
+    assert!(mmc3.irq());
+}
+
+#[test]
+fn mmc2_latches_switch_chr_on_tiles_fd_and_fe() {
+    let mut mmc2 = mapper(9, 8, 4);
+    for (reg, bank) in [0xb000, 0xc000, 0xd000, 0xe000].into_iter().zip(1..) {
+        mmc2.cpu_write(bank, reg);
+    }
+
+    // Both latches power up holding $FE. 4KB bank n starts with page 4n.
+    assert_eq!(mmc2.ppu_peek(0x0000), 8);
+    assert_eq!(mmc2.ppu_peek(0x1000), 16);
+
+    // The fetch that trips the latch still sees the old bank.
+    assert_eq!(mmc2.ppu_read(0x0fd8), 11);
+    assert_eq!(mmc2.ppu_peek(0x0000), 4);
+    mmc2.ppu_read(0x0fe9);
+    assert_eq!(mmc2.ppu_peek(0x0000), 4);
+    mmc2.ppu_read(0x0fe8);
+    assert_eq!(mmc2.ppu_peek(0x0000), 8);
+    mmc2.ppu_read(0x1fdd);
+    assert_eq!(mmc2.ppu_peek(0x1000), 12);
+
+    mmc2.cpu_write(5, 0xa000);
+    assert_eq!(prg(&*mmc2, 0x8000), 2);
+    assert_eq!(prg(&*mmc2, 0xa000), 6);
+    assert_eq!(prg(&*mmc2, 0xe000), 7);
+    assert_eq!(mmc2.cpu_peek(0x6000), None);
+
+    mmc2.cpu_write(1, 0xf000);
+    assert_eq!(mmc2.mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn mmc4_switches_16kb_and_watches_whole_tiles() {
+    let mut mmc4 = mapper(10, 8, 4);
+    mmc4.cpu_write(3, 0xa000);
+    assert_eq!(prg(&*mmc4, 0x8000), 3);
+    assert_eq!(prg(&*mmc4, 0xc000), 7);
+
+    mmc4.cpu_write(1, 0xb000);
+    mmc4.cpu_write(2, 0xc000);
+    mmc4.ppu_read(0x0fdc);
+    assert_eq!(mmc4.ppu_peek(0x0000), 4);
+
+    mmc4.cpu_write(0x42, 0x6000);
+    assert_eq!(prg(&*mmc4, 0x6000), 0x42);
+}
+
+#[test]
+fn mmc5_prg_banking_modes() {
+    let mut mmc5 = mapper(5, 8, 1);
+    // 8KB banks, with the last one at $E000 on power-up.
+    assert_eq!(prg(&*mmc5, 0xe000), 7);
+    mmc5.expansion_write(0x82, 0x5114);
+    mmc5.expansion_write(0x85, 0x5115);
+    mmc5.expansion_write(0x86, 0x5116);
+    assert_eq!(prg(&*mmc5, 0x8000), 1);
+    assert_eq!(prg(&*mmc5, 0xa000), 2);
+    assert_eq!(prg(&*mmc5, 0xc000), 3);
+
+    mmc5.expansion_write(1, 0x5100);
+    assert_eq!(prg(&*mmc5, 0x8000), 2);
+    assert_eq!(prg(&*mmc5, 0xa000), 2);
+    assert_eq!(prg(&*mmc5, 0xc000), 7);
+
+    mmc5.expansion_write(0, 0x5100);
+    mmc5.expansion_write(0x84, 0x5117);
+    let banks: Vec<_> = [0x8000, 0xa000, 0xc000, 0xe000].iter().map(|&addr| prg(&*mmc5, addr)).collect();
+    assert_eq!(banks, [2, 2, 3, 3]);
+}
+
+#[test]
+fn mmc5_maps_protected_prg_ram_into_rom_space() {
+    let mut mmc5 = mapper(5, 8, 1);
+    mmc5.expansion_write(0x00, 0x5114);
+    mmc5.cpu_write(0x55, 0x8000);
+    assert_eq!(prg(&*mmc5, 0x8000), 0);
+
+    mmc5.expansion_write(0b10, 0x5102);
+    mmc5.expansion_write(0b01, 0x5103);
+    mmc5.cpu_write(0x55, 0x8000);
+    assert_eq!(prg(&*mmc5, 0x8000), 0x55);
+    assert_eq!(prg(&*mmc5, 0x6000), 0x55);
+}
+
+#[test]
+fn mmc5_multiplies_and_maps_exram() {
+    let mut mmc5 = mapper(5, 2, 1);
+    mmc5.expansion_write(200, 0x5205);
+    mmc5.expansion_write(100, 0x5206);
+    assert_eq!(mmc5.expansion_peek(0x5205), Some(0x20));
+    assert_eq!(mmc5.expansion_peek(0x5206), Some(0x4e));
+
+    mmc5.expansion_write(2, 0x5104);
+    mmc5.expansion_write(7, 0x5c00);
+    assert_eq!(mmc5.expansion_peek(0x5c00), Some(7));
+
+    // While the PPU may use it, outside rendering it can't be read and
+    // writes store zero.
+    mmc5.expansion_write(0, 0x5104);
+    assert_eq!(mmc5.expansion_peek(0x5c00), None);
+    mmc5.expansion_write(9, 0x5c00);
+    mmc5.expansion_write(2, 0x5104);
+    assert_eq!(mmc5.expansion_peek(0x5c00), Some(0));
+}
+
+#[test]
+fn mmc5_nametable_mapping_and_fill_mode() {
+    let mut mmc5 = mapper(5, 2, 1);
+    mmc5.expansion_write(0b11_10_01_00, 0x5105);
+    mmc5.expansion_write(0x33, 0x5106);
+    mmc5.expansion_write(2, 0x5107);
+
+    assert_eq!(mmc5.nametable_page(0x2000), 0);
+    assert_eq!(mmc5.nametable_page(0x2400), 1);
+    assert_eq!(mmc5.nametable_peek(0x2400), None);
+    assert!(!mmc5.nametable_write(1, 0x2400));
+
+    assert!(mmc5.nametable_write(9, 0x2805));
+    assert_eq!(mmc5.nametable_peek(0x2805), Some(9));
+
+    assert_eq!(mmc5.nametable_peek(0x2c00), Some(0x33));
+    assert_eq!(mmc5.nametable_peek(0x2fc0), Some(0b10101010));
+}
+
+/// Makes the fetches of one rendered scanline, from the third tile's
+/// nametable byte on, ending with the two dummy nametable fetches that let
+/// the MMC5 spot the next scanline.
+fn mmc5_scanline(mmc5: &mut dyn Mapper) {
+    let mut fetch_tile = |tile: u16| {
+        mmc5.nametable_read(0x2000 + tile);
+        mmc5.nametable_read(0x23c0);
+        mmc5.ppu_read(0x0000);
+        mmc5.ppu_read(0x0008);
+    };
+    for tile in 2..34 {
+        fetch_tile(tile);
+    }
+    for _ in 0..8 {
+        mmc5.nametable_read(0x2000);
+        mmc5.nametable_read(0x2000);
+        mmc5.ppu_read(0x1000);
+        mmc5.ppu_read(0x1008);
+    }
+    for tile in 0..2 {
+        mmc5.nametable_read(0x2000 + tile);
+        mmc5.nametable_read(0x23c0);
+        mmc5.ppu_read(0x0000);
+        mmc5.ppu_read(0x0008);
+    }
+    mmc5.nametable_read(0x2002);
+    mmc5.nametable_read(0x2002);
+}
+
+#[test]
+fn mmc5_counts_scanlines_and_raises_irq() {
+    let mut mmc5 = mapper(5, 2, 1);
+    mmc5.expansion_write(3, 0x5203);
+    mmc5.expansion_write(0x80, 0x5204);
+
+    // The end of the pre-render line, then scanlines 0-2.
+    mmc5.nametable_read(0x2002);
+    mmc5.nametable_read(0x2002);
+    for _ in 0..3 {
+        mmc5_scanline(&mut *mmc5);
+        assert!(!mmc5.irq());
+    }
+    assert_eq!(mmc5.expansion_peek(0x5204), Some(0x40));
+
+    mmc5_scanline(&mut *mmc5);
+    assert!(mmc5.irq());
+    assert_eq!(mmc5.expansion_read(0x5204), Some(0xc0));
+    assert!(!mmc5.irq());
+
+    // Fetches stopping for a few cycles means vertical blank.
+    for _ in 0..3 {
+        mmc5.cpu_tick();
+    }
+    assert_eq!(mmc5.expansion_peek(0x5204), Some(0x00));
+}
+
+#[test]
+fn mmc5_separates_chr_for_8x16_sprites() {
+    let mut mmc5 = mapper(5, 2, 2);
+    mmc5.expansion_write(3, 0x5101);
+    for reg in 0..12 {
+        mmc5.expansion_write(reg as u8, 0x5120 + reg);
+    }
+    mmc5.ppu_register_write(0b00100000, 0x2000);
+
+    // Outside rendering the set written last applies.
+    assert_eq!(mmc5.ppu_peek(0x0400), 9);
+
+    for _ in 0..3 {
+        mmc5.nametable_read(0x2002);
+    }
+    assert_eq!(mmc5.ppu_read(0x0400), 9);
+    for _ in 1..64 {
+        mmc5.ppu_read(0x0000);
+    }
+    assert_eq!(mmc5.ppu_read(0x0400), 1);
+}
+
+#[test]
+fn mmc5_extended_attributes() {
+    let mut mmc5 = mapper(5, 2, 4);
+    mmc5.expansion_write(2, 0x5104);
+    mmc5.expansion_write(0b11_000101, 0x5c02);
+    mmc5.expansion_write(1, 0x5104);
+
+    for _ in 0..3 {
+        mmc5.nametable_read(0x2002);
+    }
+    assert_eq!(mmc5.nametable_read(0x23c0), Some(0xff));
+    // 4KB bank 5 starts with page 20.
+    assert_eq!(mmc5.ppu_read(0x0010), 20);
+}
+
+#[test]
+fn vrc4_decodes_both_candidate_select_lines() {
+    let mut vrc4 = mapper(21, 8, 2);
+    vrc4.cpu_write(3, 0x8000);
+    vrc4.cpu_write(5, 0xa000);
+    assert_eq!(prg(&*vrc4, 0x8000), 1);
+    assert_eq!(prg(&*vrc4, 0xa000), 2);
+    assert_eq!(prg(&*vrc4, 0xc000), 7);
+    assert_eq!(prg(&*vrc4, 0xe000), 7);
+
+    // $9002 through A2 on VRC4a boards, A7 on VRC4c.
+    vrc4.cpu_write(0b10, 0x9004);
+    assert_eq!(prg(&*vrc4, 0x8000), 7);
+    assert_eq!(prg(&*vrc4, 0xc000), 1);
+    vrc4.cpu_write(0b00, 0x9080);
+    assert_eq!(prg(&*vrc4, 0x8000), 1);
+
+    // Banks are written a nibble at a time.
+    vrc4.cpu_write(0x05, 0xb000);
+    vrc4.cpu_write(0x01, 0xb002);
+    vrc4.cpu_write(0x07, 0xb004);
+    assert_eq!(vrc4.ppu_peek(0x0000), 0x15 % 16);
+    assert_eq!(vrc4.ppu_peek(0x0400), 7);
+
+    vrc4.cpu_write(3, 0x9000);
+    assert_eq!(vrc4.mirroring(), Mirroring::SingleScreenUpper);
+}
+
+#[test]
+fn vrc4_irq_counts_cpu_cycles() {
+    let mut vrc4 = mapper(21, 2, 1);
+    vrc4.cpu_write(0x0e, 0xf000);
+    vrc4.cpu_write(0x0f, 0xf002);
+    vrc4.cpu_write(0b110, 0xf004);
+
+    vrc4.cpu_tick();
+    assert!(!vrc4.irq());
+    vrc4.cpu_tick();
+    assert!(vrc4.irq());
+
+    vrc4.cpu_write(0, 0xf006);
+    assert!(!vrc4.irq());
+    for _ in 0..512 {
+        vrc4.cpu_tick();
+    }
+    assert!(!vrc4.irq());
+}
+
+#[test]
+fn vrc2a_drops_the_low_chr_bank_bit() {
+    let mut vrc2 = mapper(22, 2, 1);
+    vrc2.cpu_write(4, 0xb000);
+    assert_eq!(vrc2.ppu_peek(0x0000), 2);
+    vrc2.cpu_write(1, 0x9000);
+    assert_eq!(vrc2.mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn vrc6_banking_and_swapped_select_lines() {
+    let mut vrc6 = mapper(24, 8, 2);
+    vrc6.cpu_write(2, 0x8000);
+    vrc6.cpu_write(5, 0xc000);
+    assert_eq!(prg(&*vrc6, 0x8000), 2);
+    assert_eq!(prg(&*vrc6, 0xc000), 2);
+    assert_eq!(prg(&*vrc6, 0xe000), 7);
+
+    vrc6.cpu_write(3, 0xd000);
+    vrc6.cpu_write(12, 0xe003);
+    assert_eq!(vrc6.ppu_peek(0x0000), 3);
+    assert_eq!(vrc6.ppu_peek(0x1c00), 12);
+
+    assert_eq!(vrc6.cpu_peek(0x6000), None);
+    vrc6.cpu_write(0x84, 0xb003);
+    assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+    vrc6.cpu_write(0x42, 0x6000);
+    assert_eq!(prg(&*vrc6, 0x6000), 0x42);
+
+    let mut vrc6b = mapper(26, 8, 2);
+    vrc6b.cpu_write(9, 0xd001);
+    assert_eq!(vrc6b.ppu_peek(0x0800), 9);
+}
+
+#[test]
+fn vrc6_irq_counts_scanlines() {
+    let mut vrc6 = mapper(24, 2, 1);
+    vrc6.cpu_write(0xff, 0xf000);
+    vrc6.cpu_write(0b010, 0xf001);
+
+    // 341 dots at three per CPU cycle.
+    for _ in 0..113 {
+        vrc6.cpu_tick();
+    }
+    assert!(!vrc6.irq());
+    vrc6.cpu_tick();
+    assert!(vrc6.irq());
+}
+
+#[test]
+fn vrc6_pulse_plays_at_constant_volume() {
+    let mut vrc6 = mapper(24, 2, 1);
+    assert_eq!(vrc6.audio_output(), 0.0);
+    vrc6.cpu_write(0x8f, 0x9000);
+    vrc6.cpu_write(0x80, 0x9002);
+    assert_eq!(vrc6.audio_output(), 15.0 / 61.0);
+}
+
+#[test]
+fn fme7_banking_and_prg_ram() {
+    let mut fme7 = mapper(69, 8, 2);
+    let command = |fme7: &mut dyn Mapper, command: u8, val: u8| {
+        fme7.cpu_write(command, 0x8000);
+        fme7.cpu_write(val, 0xa000);
+    };
+
+    command(&mut *fme7, 9, 3);
+    command(&mut *fme7, 7, 11);
+    assert_eq!(prg(&*fme7, 0x8000), 1);
+    assert_eq!(prg(&*fme7, 0xe000), 7);
+    assert_eq!(fme7.ppu_peek(0x1c00), 11);
+
+    command(&mut *fme7, 8, 0x02);
+    assert_eq!(prg(&*fme7, 0x6000), 1);
+    command(&mut *fme7, 8, 0x40);
+    assert_eq!(fme7.cpu_peek(0x6000), None);
+    command(&mut *fme7, 8, 0xc0);
+    fme7.cpu_write(0x42, 0x6000);
+    assert_eq!(prg(&*fme7, 0x6000), 0x42);
+
+    command(&mut *fme7, 0xc, 1);
+    assert_eq!(fme7.mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn fme7_irq_fires_when_the_counter_wraps() {
+    let mut fme7 = mapper(69, 2, 1);
+    for (command, val) in [(0xe, 2), (0xf, 0), (0xd, 0x81)] {
+        fme7.cpu_write(command, 0x8000);
+        fme7.cpu_write(val, 0xa000);
+    }
+
+    fme7.cpu_tick();
+    fme7.cpu_tick();
+    assert!(!fme7.irq());
+    fme7.cpu_tick();
+    assert!(fme7.irq());
+
+    fme7.cpu_write(0xd, 0x8000);
+    fme7.cpu_write(0, 0xa000);
+    assert!(!fme7.irq());
+}
+
+#[test]
+fn sunsoft_5b_plays_a_tone() {
+    let mut fme7 = mapper(69, 2, 1);
+    for (reg, val) in [(8, 0x0f), (7, 0b111110)] {
+        fme7.cpu_write(reg, 0xc000);
+        fme7.cpu_write(val, 0xe000);
+    }
+
+    // With a period of zero the square flips every 16 cycles.
+    assert_eq!(fme7.audio_output(), 0.0);
+    for _ in 0..16 {
+        fme7.cpu_tick();
+    }
+    assert_eq!(fme7.audio_output(), 1.0 / 3.0);
+}
+
+#[test]
+fn namco163_banking_and_nametables() {
+    let mut n163 = mapper(19, 8, 2);
+    n163.cpu_write(3, 0xe000);
+    n163.cpu_write(5, 0xe800);
+    n163.cpu_write(6, 0xf000);
+    let banks: Vec<_> = [0x8000, 0xa000, 0xc000, 0xe000].iter().map(|&addr| prg(&*n163, addr)).collect();
+    assert_eq!(banks, [1, 2, 3, 7]);
+
+    n163.cpu_write(9, 0x8000);
+    assert_eq!(n163.ppu_peek(0x0000), 9);
+
+    n163.cpu_write(4, 0xc000);
+    n163.cpu_write(0xe1, 0xc800);
+    assert_eq!(n163.nametable_peek(0x2000), Some(4));
+    assert_eq!(n163.nametable_peek(0x2400), None);
+    assert_eq!(n163.nametable_page(0x2400), 1);
+}
+
+#[test]
+fn namco163_irq_and_sound_ram() {
+    let mut n163 = mapper(19, 2, 1);
+    n163.expansion_write(0xfe, 0x5000);
+    n163.expansion_write(0xff, 0x5800);
+    assert!(!n163.irq());
+    n163.cpu_tick();
+    assert!(n163.irq());
+    assert_eq!(n163.expansion_peek(0x5800), Some(0xff));
+    n163.expansion_write(0x00, 0x5000);
+    assert!(!n163.irq());
+
+    n163.cpu_write(0x80, 0xf800);
+    n163.expansion_write(1, 0x4800);
+    n163.expansion_write(2, 0x4800);
+    n163.cpu_write(0x80, 0xf800);
+    assert_eq!(n163.expansion_read(0x4800), Some(1));
+    assert_eq!(n163.expansion_read(0x4800), Some(2));
+}
+
+#[test]
+fn namco163_sound_address_wraps_with_auto_increment() {
+    let mut n163 = mapper(19, 2, 1);
+    n163.cpu_write(0x80, 0xf800);
+    for val in 0..130 {
+        n163.expansion_write(val, 0x4800);
+    }
+
+    // The 129th write wraps back to $00 and auto-increment stays on, so the
+    // 130th lands at $01.
+    n163.cpu_write(0x80, 0xf800);
+    assert_eq!(n163.expansion_read(0x4800), Some(128));
+    assert_eq!(n163.expansion_read(0x4800), Some(129));
+    assert_eq!(n163.expansion_read(0x4800), Some(2));
+
+    // Reads auto-increment and wrap the same way.
+    n163.cpu_write(0xff, 0xf800);
+    assert_eq!(n163.expansion_read(0x4800), Some(127));
+    assert_eq!(n163.expansion_read(0x4800), Some(128));
+    assert_eq!(n163.expansion_read(0x4800), Some(129));
+}
+
+#[test]
+fn namco163_write_protects_prg_ram() {
+    let mut n163 = mapper(19, 2, 1);
+    n163.cpu_write(0x11, 0x6000);
+    assert_eq!(prg(&*n163, 0x6000), 0);
+
+    n163.cpu_write(0x41, 0xf800);
+    n163.cpu_write(0x11, 0x6000);
+    n163.cpu_write(0x22, 0x6800);
+    assert_eq!(prg(&*n163, 0x6000), 0);
+    assert_eq!(prg(&*n163, 0x6800), 0x22);
+}
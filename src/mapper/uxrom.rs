@@ -0,0 +1,61 @@
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mapper 2: a switchable 16KB PRG bank at $8000 and the last bank fixed
+/// at $C000, with 8KB of CHR-RAM.
+pub struct Uxrom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+}
+
+impl Uxrom {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Uxrom {
+        Uxrom {
+            memory,
+            mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            0x6000..=0x7fff => self.memory.read_prg_ram(addr),
+            0x8000..=0xbfff => self.memory.read_prg(0x4000, self.prg_bank, addr),
+            _ => {
+                let last = self.memory.prg_banks(0x4000) - 1;
+                self.memory.read_prg(0x4000, last, addr)
+            }
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        match addr {
+            0x6000..=0x7fff => self.memory.write_prg_ram(val, addr),
+            _ => self.prg_bank = val as usize,
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, 0, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x2000, 0, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
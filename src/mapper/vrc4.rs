@@ -0,0 +1,159 @@
+use super::vrc_irq::VrcIrq;
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mappers 21, 22, 23 and 25: Konami's VRC4 and its IRQ-less predecessor,
+/// the VRC2. Two switchable 8KB PRG banks, eight 1KB CHR banks and
+/// software-controlled mirroring.
+///
+/// Boards wire the chip's two register select pins to different CPU
+/// address lines. NES 2.0 submappers say which; for plain iNES headers both
+/// candidate lines are decoded, as no game writes in a way that tells them
+/// apart.
+pub struct Vrc4 {
+    memory: CartridgeMemory,
+    /// CPU address lines on the chip's A0 and A1 pins.
+    a0: u16,
+    a1: u16,
+    vrc2: bool,
+    /// VRC2a boards leave out the lowest CHR bank line.
+    chr_shift: u8,
+    prg_banks: [u8; 2],
+    /// Whether the first PRG register switches $C000 instead of $8000.
+    prg_swapped: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(memory: CartridgeMemory, mapper: u16, submapper: u8) -> Vrc4 {
+        let (a0, a1, vrc2) = match (mapper, submapper) {
+            (21, 1) => (0x02, 0x04, false),
+            (21, 2) => (0x40, 0x80, false),
+            (21, _) => (0x42, 0x84, false),
+            (22, _) => (0x02, 0x01, true),
+            (23, 1) => (0x01, 0x02, false),
+            (23, 2) => (0x04, 0x08, false),
+            (23, 3) => (0x01, 0x02, true),
+            (23, _) => (0x05, 0x0a, false),
+            (25, 1) => (0x02, 0x01, false),
+            (25, 2) => (0x08, 0x04, false),
+            (25, 3) => (0x02, 0x01, true),
+            _ => (0x0a, 0x05, false),
+        };
+        Vrc4 {
+            memory,
+            a0,
+            a1,
+            vrc2,
+            chr_shift: (mapper == 22) as u8,
+            prg_banks: [0; 2],
+            prg_swapped: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    /// `addr` with the select pins moved to bits 0 and 1, as the registers
+    /// are documented.
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0 != 0) as u16;
+        let a1 = (addr & self.a1 != 0) as u16;
+        (addr & 0xf000) | (a1 << 1) | a0
+    }
+
+    fn write_chr_bank(&mut self, val: u8, reg: u16) {
+        // $B000-$E003 hold the low and high halves of two banks each.
+        let index = (((reg >> 12) - 0xb) * 2 + ((reg >> 1) & 1)) as usize;
+        let bank = &mut self.chr_banks[index];
+        if reg & 1 == 0 {
+            *bank = (*bank & 0x1f0) | (val & 0x0f) as u16;
+        } else {
+            let high = if self.vrc2 { 0x0f } else { 0x1f };
+            *bank = (*bank & 0x00f) | ((val & high) as u16) << 4;
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 {
+            return Some(self.memory.read_prg_ram(addr));
+        }
+
+        let second_last = self.memory.prg_banks(0x2000).saturating_sub(2);
+        let bank = match (addr - 0x8000) / 0x2000 {
+            0 if self.prg_swapped => second_last,
+            0 => self.prg_banks[0] as usize,
+            1 => self.prg_banks[1] as usize,
+            2 if self.prg_swapped => self.prg_banks[0] as usize,
+            2 => second_last,
+            _ => second_last + 1,
+        };
+        Some(self.memory.read_prg(0x2000, bank, addr))
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        if addr < 0x8000 {
+            self.memory.write_prg_ram(val, addr);
+            return;
+        }
+
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8fff => self.prg_banks[0] = val & 0b00011111,
+            0x9000 | 0x9001 if self.vrc2 => {
+                self.mirroring = if val & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            0x9000 | 0x9001 => {
+                self.mirroring = match val & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0x9002 if !self.vrc2 => self.prg_swapped = val & 0b10 != 0,
+            0xa000..=0xafff => self.prg_banks[1] = val & 0b00011111,
+            0xb000..=0xefff => self.write_chr_bank(val, reg),
+            _ if self.vrc2 => {}
+            0xf000 => self.irq.write_latch_low(val),
+            0xf001 => self.irq.write_latch_high(val),
+            0xf002 => self.irq.write_control(val),
+            0xf003 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr / 0x400) as usize] >> self.chr_shift;
+        self.memory.read_chr(0x400, bank as usize, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        let bank = self.chr_banks[(addr / 0x400) as usize] >> self.chr_shift;
+        self.memory.write_chr(0x400, bank as usize, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+    }
+}
@@ -0,0 +1,298 @@
+use super::vrc_irq::VrcIrq;
+use super::{CartridgeMemory, Mapper};
+use crate::cartridge::Mirroring;
+
+/// Mappers 24 and 26: Konami's VRC6, with a 16KB and an 8KB switchable PRG
+/// bank, eight 1KB CHR banks, the VRC IRQ counter and three extra sound
+/// channels. Mapper 26 boards swap the A0 and A1 register select lines.
+pub struct Vrc6 {
+    memory: CartridgeMemory,
+    swapped_lines: bool,
+    prg_16k_bank: u8,
+    prg_8k_bank: u8,
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(memory: CartridgeMemory, mapper: u16) -> Vrc6 {
+        Vrc6 {
+            memory,
+            swapped_lines: mapper == 26,
+            prg_16k_bank: 0,
+            prg_8k_bank: 0,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        let (a0, a1) = if self.swapped_lines { ((addr >> 1) & 1, addr & 1) } else { (addr & 1, (addr >> 1) & 1) };
+        (addr & 0xf000) | (a1 << 1) | a0
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.memory.read_prg_ram(addr),
+            0x6000..=0x7fff => return None,
+            0x8000..=0xbfff => self.memory.read_prg(0x4000, self.prg_16k_bank as usize, addr),
+            0xc000..=0xdfff => self.memory.read_prg(0x2000, self.prg_8k_bank as usize, addr),
+            _ => {
+                let last = self.memory.prg_banks(0x2000) - 1;
+                self.memory.read_prg(0x2000, last, addr)
+            }
+        };
+        Some(val)
+    }
+
+    fn cpu_write(&mut self, val: u8, addr: u16) {
+        if addr < 0x8000 {
+            if self.prg_ram_enabled {
+                self.memory.write_prg_ram(val, addr);
+            }
+            return;
+        }
+
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8fff => self.prg_16k_bank = val & 0b00001111,
+            0x9000..=0xb002 => self.audio.write(val, reg),
+            0xb003 => {
+                self.mirroring = match (val >> 2) & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+                self.prg_ram_enabled = val & 0b10000000 != 0;
+            }
+            0xc000..=0xcfff => self.prg_8k_bank = val & 0b00011111,
+            0xd000..=0xefff => self.chr_banks[(((reg >> 12) - 0xd) * 4 + (reg & 0b11)) as usize] = val,
+            0xf000 => self.irq.write_latch(val),
+            0xf001 => self.irq.write_control(val),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.memory.read_chr(0x400, self.chr_banks[(addr / 0x400) as usize] as usize, addr)
+    }
+
+    fn ppu_write(&mut self, val: u8, addr: u16) {
+        self.memory.write_chr(0x400, self.chr_banks[(addr / 0x400) as usize] as usize, val, addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+/// Two pulse channels with eight duty cycles and a sawtooth channel.
+struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halted: bool,
+    /// How far right the frequency control shifts every channel's period.
+    period_shift: u8,
+}
+
+impl Vrc6Audio {
+    fn new() -> Vrc6Audio {
+        Vrc6Audio {
+            pulses: [Vrc6Pulse::new(), Vrc6Pulse::new()],
+            saw: Vrc6Saw::new(),
+            halted: false,
+            period_shift: 0,
+        }
+    }
+
+    /// Writes $9000-$B002, with the select lines already in bits 0 and 1.
+    fn write(&mut self, val: u8, reg: u16) {
+        match reg {
+            0x9003 => {
+                self.halted = val & 0b001 != 0;
+                self.period_shift = if val & 0b100 != 0 {
+                    8
+                } else if val & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000..=0x9002 => self.pulses[0].write(val, reg & 0b11),
+            0xa000..=0xa002 => self.pulses[1].write(val, reg & 0b11),
+            0xb000..=0xb002 => self.saw.write(val, reg & 0b11),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.halted {
+            return;
+        }
+        for pulse in &mut self.pulses {
+            pulse.tick(self.period_shift);
+        }
+        self.saw.tick(self.period_shift);
+    }
+
+    fn output(&self) -> f32 {
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        sum as f32 / 61.0
+    }
+}
+
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    /// Ignores the duty cycle and outputs the volume constantly.
+    constant: bool,
+    period: u16,
+    enabled: bool,
+    divider: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Vrc6Pulse {
+        Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            constant: false,
+            period: 0,
+            enabled: false,
+            divider: 0,
+            step: 0,
+        }
+    }
+
+    fn write(&mut self, val: u8, reg: u16) {
+        match reg {
+            0 => {
+                self.volume = val & 0x0f;
+                self.duty = (val >> 4) & 0b111;
+                self.constant = val & 0b10000000 != 0;
+            }
+            1 => self.period = (self.period & 0xf00) | val as u16,
+            _ => {
+                self.period = (self.period & 0x0ff) | ((val & 0x0f) as u16) << 8;
+                self.enabled = val & 0b10000000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, period_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.divider == 0 {
+            self.divider = self.period >> period_shift;
+            self.step = (self.step + 1) & 0x0f;
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Vrc6Saw {
+    /// Added to the accumulator every other step.
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    divider: u16,
+    /// 0-13: the accumulator resets on the 14th step.
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn new() -> Vrc6Saw {
+        Vrc6Saw {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            divider: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, val: u8, reg: u16) {
+        match reg {
+            0 => self.rate = val & 0b00111111,
+            1 => self.period = (self.period & 0xf00) | val as u16,
+            _ => {
+                self.period = (self.period & 0x0ff) | ((val & 0x0f) as u16) << 8;
+                self.enabled = val & 0b10000000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, period_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.period >> period_shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
@@ -0,0 +1,86 @@
+/// PPU dots per scanline, which the prescaler counts down in steps of three
+/// per CPU cycle.
+const PRESCALER_PERIOD: i16 = 341;
+
+/// The IRQ counter Konami put in the VRC4, VRC6 and VRC7: an 8-bit counter
+/// counting up from a reload value, clocked every CPU cycle or, through a
+/// prescaler, once per scanline's worth of cycles.
+pub(super) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    /// Becomes `enabled` when the IRQ is acknowledged.
+    enable_on_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub(super) fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_on_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub(super) fn write_latch(&mut self, val: u8) {
+        self.latch = val;
+    }
+
+    /// The VRC4 takes the latch four bits at a time.
+    pub(super) fn write_latch_low(&mut self, val: u8) {
+        self.latch = (self.latch & 0xf0) | (val & 0x0f);
+    }
+
+    pub(super) fn write_latch_high(&mut self, val: u8) {
+        self.latch = (self.latch & 0x0f) | (val << 4);
+    }
+
+    pub(super) fn write_control(&mut self, val: u8) {
+        self.enable_on_ack = val & 0b001 != 0;
+        self.enabled = val & 0b010 != 0;
+        self.cycle_mode = val & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub(super) fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_on_ack;
+    }
+
+    pub(super) fn pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Called once per CPU cycle.
+    pub(super) fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += PRESCALER_PERIOD;
+        }
+
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
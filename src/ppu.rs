@@ -0,0 +1,895 @@
+use crate::cartridge::Cartridge;
+
+#[cfg(test)]
+mod tests;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const VBLANK_SCANLINE: u16 = 241;
+const PRERENDER_SCANLINE: u16 = 261;
+
+/// How long a bit of the register data bus holds its value once nothing
+/// drives it, about 600ms.
+const IO_LATCH_DECAY_DOTS: u64 = 36 * 262 * DOTS_PER_SCANLINE as u64;
+
+const CTRL_INCREMENT_32: u8 = 0b00000100;
+const CTRL_SPRITE_TABLE: u8 = 0b00001000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b00010000;
+const CTRL_LARGE_SPRITES: u8 = 0b00100000;
+const CTRL_NMI: u8 = 0b10000000;
+
+const MASK_GREYSCALE: u8 = 0b00000001;
+const MASK_BACKGROUND_LEFT: u8 = 0b00000010;
+const MASK_SPRITES_LEFT: u8 = 0b00000100;
+const MASK_BACKGROUND: u8 = 0b00001000;
+const MASK_SPRITES: u8 = 0b00010000;
+
+const STATUS_SPRITE_OVERFLOW: u8 = 0b00100000;
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b01000000;
+const STATUS_VBLANK: u8 = 0b10000000;
+
+/// Sprite attribute bits.
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b00100000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b01000000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b10000000;
+
+/// The 2C02 picture processing unit.
+///
+/// The PPU runs one dot per `tick`, three per CPU cycle, and fetches
+/// nametables, attributes and patterns through the cartridge as the real
+/// chip does, so mappers watching its address bus see the same accesses.
+/// Each visible dot writes one pixel of the frame buffer as an index into
+/// the console's 64-color palette; see `NTSC_PALETTE`. Color emphasis is
+/// not applied.
+///
+/// The scroll registers follow the hardware's layout: `v` is the current
+/// VRAM address, `t` the temporary one the CPU writes, `x` the fine X
+/// scroll and `w` the first/second write toggle of $2005 and $2006.
+///
+/// The NMI output is a level, `nmi`, that the bus passes on to the CPU;
+/// the CPU runs an NMI on each rising edge. Reading $2002 right as vblank
+/// starts races with it, and suppresses the NMI for that frame.
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    oam: [u8; 256],
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    /// PPUDATA reads below the palette return the previous read's value.
+    read_buffer: u8,
+    /// The data bus between the CPU and the PPU's registers, which reads of
+    /// write-only registers return. Each bit decays to 0 some time after
+    /// it was last driven.
+    io_latch: u8,
+    /// When each bit of `io_latch` was last driven, in `dots`.
+    io_latch_refreshed: [u64; 8],
+    /// Set by a $2002 read just before vblank starts, which keeps the flag
+    /// and NMI from going up that frame.
+    suppress_vblank: bool,
+    /// Set by a $2002 read on the dot vblank starts or the one after. The
+    /// read sees the flag, but NMI stays down for the rest of the frame.
+    suppress_nmi: bool,
+    /// The NMI output. It follows the vblank flag and PPUCTRL once per dot,
+    /// so a $2002 read only lowers it on the next dot, after the CPU has
+    /// seen it.
+    nmi_line: bool,
+    /// The console's 2KB of nametable RAM.
+    vram: [u8; 0x800],
+    palette: [u8; 32],
+    scanline: u16,
+    dot: u16,
+    frame_count: u64,
+    /// Dots run since power-on.
+    dots: u64,
+    frame: Vec<u8>,
+    // Background fetches and the shift registers they feed.
+    tile: u8,
+    tile_attribute: u8,
+    tile_low: u8,
+    tile_high: u8,
+    pattern_low: u16,
+    pattern_high: u16,
+    attribute_low: u16,
+    attribute_high: u16,
+    /// The sprites evaluation found for the next scanline.
+    secondary_oam: [u8; 32],
+    evaluation: SpriteEvaluation,
+    /// OAM indices of the sprites on the next scanline past the first
+    /// eight, when the sprite limit is off.
+    extra_sprites: Vec<u8>,
+    sprite_limit: bool,
+    /// The sprites being drawn on the current scanline.
+    sprites: Vec<Sprite>,
+}
+
+/// A sprite fetched for the scanline, with its pattern row already flipped.
+#[derive(Clone, Copy)]
+struct Sprite {
+    x: u8,
+    attribute: u8,
+    low: u8,
+    high: u8,
+    /// Whether this is sprite 0, the one that sets the sprite-zero hit flag.
+    zero: bool,
+}
+
+impl Sprite {
+    fn new(sprite: [u8; 4], low: u8, high: u8, zero: bool) -> Sprite {
+        let [_, _, attribute, x] = sprite;
+        let flip = |row: u8| if attribute & SPRITE_FLIP_HORIZONTAL != 0 { row.reverse_bits() } else { row };
+        Sprite { x, attribute, low: flip(low), high: flip(high), zero }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    pixel: u8,
+    attribute: u8,
+    zero: bool,
+}
+
+/// Progress of sprite evaluation through OAM.
+#[derive(Clone, Copy, Default)]
+struct SpriteEvaluation {
+    /// The sprite being looked at.
+    n: u8,
+    /// The byte of that sprite.
+    m: u8,
+    /// Sprites copied to secondary OAM.
+    count: u8,
+    /// Whether sprite 0 is among them.
+    sprite_zero: bool,
+    done: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            io_latch_refreshed: [0; 8],
+            suppress_vblank: false,
+            suppress_nmi: false,
+            nmi_line: false,
+            vram: [0; 0x800],
+            palette: [0; 32],
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            dots: 0,
+            frame: vec![0; WIDTH * HEIGHT],
+            tile: 0,
+            tile_attribute: 0,
+            tile_low: 0,
+            tile_high: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            attribute_low: 0,
+            attribute_high: 0,
+            secondary_oam: [0xff; 32],
+            evaluation: SpriteEvaluation::default(),
+            extra_sprites: Vec::new(),
+            sprite_limit: true,
+            sprites: Vec::with_capacity(8),
+        }
+    }
+
+    /// The last complete frame, and the current one up to the dot being
+    /// drawn, as palette indices, row by row.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Number of frames started since power-on.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// 0-239 are visible, 241-260 are vertical blank and 261 is the
+    /// pre-render scanline.
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam
+    }
+
+    /// Whether the PPU is asserting NMI: during vblank, with NMIs enabled
+    /// in PPUCTRL.
+    pub fn nmi(&self) -> bool {
+        self.nmi_line
+    }
+
+    fn update_nmi_line(&mut self) {
+        self.nmi_line = self.status & STATUS_VBLANK != 0 && self.ctrl & CTRL_NMI != 0 && !self.suppress_nmi;
+    }
+
+    /// Whether only the first eight sprites on a scanline are drawn, as on
+    /// hardware. Turning the limit off removes the flicker games use to
+    /// show more; the overflow flag works the same either way.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    /// What OAMDATA reads return. While secondary OAM is being cleared
+    /// the PPU reads $FF from OAM and leaves it on the bus.
+    fn oam_data(&self) -> u8 {
+        let clearing = self.scanline < HEIGHT as u16 && (1..=64).contains(&self.dot);
+        if clearing && self.rendering_enabled() {
+            0xff
+        } else {
+            self.oam[self.oam_addr as usize]
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    /// Handles a CPU read of $2000-$3FFF.
+    pub fn read_register(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
+        self.io_latch = self.decayed_io_latch();
+        match addr & 0x0007 {
+            2 => {
+                // `dot` is the next dot to run, so the flag goes up between
+                // dots 1 and 2.
+                if self.scanline == VBLANK_SCANLINE {
+                    match self.dot {
+                        // Reading a dot before the flag goes up races with
+                        // it: the read misses it and the flag never goes up.
+                        1 => self.suppress_vblank = true,
+                        // Reading as it goes up, or a dot later, sees the
+                        // flag but keeps NMI from going up.
+                        2 | 3 => {
+                            self.suppress_nmi = true;
+                            self.nmi_line = false;
+                        }
+                        _ => {}
+                    }
+                }
+                let status = self.status;
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+                self.drive_io_latch(status, 0xe0);
+            }
+            4 => {
+                let val = self.oam_data();
+                self.drive_io_latch(val, 0xff);
+            }
+            7 => {
+                if self.v & 0x3fff >= 0x3f00 {
+                    // Palette reads are not buffered, but the buffer is
+                    // still filled from the nametable underneath. The top
+                    // two bits are open bus.
+                    self.read_buffer = self.read_memory(self.v - 0x1000, cartridge);
+                    let color = self.read_palette(self.v);
+                    self.drive_io_latch(color, 0x3f);
+                } else {
+                    let val = self.read_buffer;
+                    self.read_buffer = self.read_memory(self.v, cartridge);
+                    self.drive_io_latch(val, 0xff);
+                }
+                self.increment_v(cartridge);
+            }
+            _ => {}
+        }
+        self.io_latch
+    }
+
+    /// Reads a register without side effects.
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        let io_latch = self.decayed_io_latch();
+        match addr & 0x0007 {
+            2 => (self.status & 0xe0) | (io_latch & 0x1f),
+            4 => self.oam_data(),
+            7 if self.v & 0x3fff >= 0x3f00 => (self.read_palette(self.v) & 0x3f) | (io_latch & 0xc0),
+            7 => self.read_buffer,
+            _ => io_latch,
+        }
+    }
+
+    /// Puts the bits of `val` in `mask` on the register data bus.
+    fn drive_io_latch(&mut self, val: u8, mask: u8) {
+        self.io_latch = (self.io_latch & !mask) | (val & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.io_latch_refreshed[bit] = self.dots;
+            }
+        }
+    }
+
+    fn decayed_io_latch(&self) -> u8 {
+        (0..8)
+            .filter(|&bit| self.dots - self.io_latch_refreshed[bit] < IO_LATCH_DECAY_DOTS)
+            .fold(0, |latch, bit| latch | (self.io_latch & (1 << bit)))
+    }
+
+    /// Handles a CPU write to $2000-$3FFF.
+    pub fn write_register(&mut self, val: u8, addr: u16, cartridge: &mut Cartridge) {
+        self.drive_io_latch(val, 0xff);
+        match addr & 0x0007 {
+            0 => {
+                self.ctrl = val;
+                self.t = (self.t & !0x0c00) | ((val & 0b11) as u16) << 10;
+                self.update_nmi_line();
+            }
+            1 => self.mask = val,
+            3 => self.oam_addr = val,
+            4 => {
+                // The attribute byte has no bits 2-4.
+                let val = if self.oam_addr & 0b11 == 2 {
+                    val & 0xe3
+                } else {
+                    val
+                };
+                self.oam[self.oam_addr as usize] = val;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 if !self.w => {
+                self.t = (self.t & !0x001f) | (val >> 3) as u16;
+                self.x = val & 0b111;
+                self.w = true;
+            }
+            5 => {
+                self.t =
+                    (self.t & !0x73e0) | ((val & 0b111) as u16) << 12 | ((val & 0xf8) as u16) << 2;
+                self.w = false;
+            }
+            6 if !self.w => {
+                self.t = (self.t & 0x00ff) | ((val & 0x3f) as u16) << 8;
+                self.w = true;
+            }
+            6 => {
+                self.t = (self.t & 0xff00) | val as u16;
+                self.v = self.t;
+                self.w = false;
+                cartridge.ppu_address(self.v & 0x3fff);
+            }
+            7 => {
+                self.write_memory(val, self.v, cartridge);
+                self.increment_v(cartridge);
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves `v` on after a PPUDATA access.
+    fn increment_v(&mut self, cartridge: &mut Cartridge) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7fff;
+        cartridge.ppu_address(self.v & 0x3fff);
+    }
+
+    /// Reads the PPU address space without side effects.
+    pub fn peek_memory(&self, addr: u16, cartridge: &Cartridge) -> u8 {
+        let addr = addr & 0x3fff;
+        match addr {
+            0..=0x1fff => cartridge.ppu_peek(addr),
+            0x2000..=0x3eff => cartridge
+                .nametable_peek(addr)
+                .unwrap_or_else(|| self.vram[vram_index(addr, cartridge)]),
+            _ => self.read_palette(addr),
+        }
+    }
+
+    fn read_memory(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
+        let addr = addr & 0x3fff;
+        cartridge.ppu_address(addr);
+        match addr {
+            0..=0x1fff => cartridge.ppu_read(addr),
+            0x2000..=0x3eff => self.read_nametable(addr, cartridge),
+            _ => self.read_palette(addr),
+        }
+    }
+
+    fn read_nametable(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
+        cartridge
+            .nametable_read(addr)
+            .unwrap_or_else(|| self.vram[vram_index(addr, cartridge)])
+    }
+
+    fn write_memory(&mut self, val: u8, addr: u16, cartridge: &mut Cartridge) {
+        let addr = addr & 0x3fff;
+        cartridge.ppu_address(addr);
+        match addr {
+            0..=0x1fff => cartridge.ppu_write(val, addr),
+            0x2000..=0x3eff => {
+                if !cartridge.nametable_write(val, addr) {
+                    self.vram[vram_index(addr, cartridge)] = val;
+                }
+            }
+            _ => self.palette[palette_index(addr)] = val & 0x3f,
+        }
+    }
+
+    fn read_palette(&self, addr: u16) -> u8 {
+        let color = self.palette[palette_index(addr)];
+        if self.mask & MASK_GREYSCALE != 0 {
+            color & 0x30
+        } else {
+            color
+        }
+    }
+
+    /// Advances one dot.
+    pub fn tick(&mut self, cartridge: &mut Cartridge) {
+        let visible = self.scanline < HEIGHT as u16;
+        let prerender = self.scanline == PRERENDER_SCANLINE;
+
+        if (visible || prerender) && self.rendering_enabled() {
+            self.render_dot(visible, prerender, cartridge);
+        } else if visible && (1..=WIDTH as u16).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 && !self.suppress_vblank {
+            self.status |= STATUS_VBLANK;
+        } else if prerender && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_SPRITE_OVERFLOW);
+            self.suppress_vblank = false;
+            self.suppress_nmi = false;
+        }
+        self.update_nmi_line();
+
+        self.dots += 1;
+        self.dot += 1;
+        if prerender && self.dot == DOTS_PER_SCANLINE - 1 && self.frame_count & 1 == 1 && self.rendering_enabled() {
+            // Odd frames skip the last dot of the pre-render scanline when
+            // rendering is on, to shift the picture's color artifacts.
+            self.dot = DOTS_PER_SCANLINE;
+        }
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > PRERENDER_SCANLINE {
+                self.scanline = 0;
+                self.frame_count += 1;
+            }
+        }
+    }
+
+    /// One dot of a visible or pre-render scanline with rendering on.
+    fn render_dot(&mut self, visible: bool, prerender: bool, cartridge: &mut Cartridge) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+        }
+        if dot % 8 == 1 && ((9..=257).contains(&dot) || (329..=337).contains(&dot)) {
+            self.load_background();
+        }
+        if visible && (1..=WIDTH as u16).contains(&dot) {
+            self.output_pixel();
+        }
+        if visible {
+            self.evaluate_sprites();
+        }
+
+        match dot {
+            1..=256 | 321..=336 => {
+                self.fetch_background(cartridge);
+                if dot == 256 {
+                    self.increment_y();
+                }
+            }
+            257..=320 => {
+                if dot == 257 {
+                    self.copy_horizontal_scroll();
+                    if prerender {
+                        // Nothing is drawn on the first scanline.
+                        self.evaluation = SpriteEvaluation::default();
+                        self.extra_sprites.clear();
+                    }
+                }
+                self.oam_addr = 0;
+                if prerender && (280..=304).contains(&dot) {
+                    self.copy_vertical_scroll();
+                }
+                self.fetch_sprite(cartridge);
+            }
+            // The unused nametable fetches at the end of the scanline.
+            337 | 339 => {
+                self.read_memory(0x2000 | (self.v & 0x0fff), cartridge);
+            }
+            _ => {}
+        }
+    }
+
+    /// The background fetch for `dot`, each taking two dots, and the coarse
+    /// X increment after the last of a tile.
+    fn fetch_background(&mut self, cartridge: &mut Cartridge) {
+        match self.dot % 8 {
+            1 => self.tile = self.read_memory(0x2000 | (self.v & 0x0fff), cartridge),
+            3 => {
+                let addr =
+                    0x23c0 | (self.v & 0x0c00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+                let attribute = self.read_memory(addr, cartridge);
+                // Each byte covers four 16x16 quadrants.
+                let shift = ((self.v >> 4) & 0b100) | (self.v & 0b10);
+                self.tile_attribute = (attribute >> shift) & 0b11;
+            }
+            5 => self.tile_low = self.read_memory(self.background_pattern_addr(), cartridge),
+            7 => self.tile_high = self.read_memory(self.background_pattern_addr() + 8, cartridge),
+            0 => self.increment_x(),
+            _ => {}
+        }
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+        let fine_y = (self.v >> 12) & 0b111;
+        table | (self.tile as u16) << 4 | fine_y
+    }
+
+    fn load_background(&mut self) {
+        self.pattern_low = (self.pattern_low & 0xff00) | self.tile_low as u16;
+        self.pattern_high = (self.pattern_high & 0xff00) | self.tile_high as u16;
+        let spread = |bit: u8| {
+            if self.tile_attribute & bit != 0 {
+                0x00ff
+            } else {
+                0
+            }
+        };
+        self.attribute_low = (self.attribute_low & 0xff00) | spread(0b01);
+        self.attribute_high = (self.attribute_high & 0xff00) | spread(0b10);
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.attribute_low <<= 1;
+        self.attribute_high <<= 1;
+    }
+
+    fn increment_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            // Wrap into the horizontally adjacent nametable.
+            self.v = (self.v & !0x001f) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let coarse_y = (self.v >> 5) & 0x1f;
+        let coarse_y = match coarse_y {
+            // Row 29 is the last of a nametable; wrap into the vertically
+            // adjacent one.
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            // Rows 30 and 31 hold the attributes, and wrap without
+            // switching nametables.
+            31 => 0,
+            _ => coarse_y + 1,
+        };
+        self.v = (self.v & !0x03e0) | coarse_y << 5;
+    }
+
+    fn copy_horizontal_scroll(&mut self) {
+        self.v = (self.v & !0x041f) | (self.t & 0x041f);
+    }
+
+    fn copy_vertical_scroll(&mut self) {
+        self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_LARGE_SPRITES != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn sprite_in_range(&self, y: u8) -> bool {
+        self.scanline.wrapping_sub(y as u16) < self.sprite_height()
+    }
+
+    /// Sprite evaluation for the next scanline, run on visible scanlines:
+    /// dots 1-64 clear secondary OAM, dots 65-256 copy the first eight
+    /// sprites in range into it, one step every two dots.
+    fn evaluate_sprites(&mut self) {
+        match self.dot {
+            1..=64 if self.dot & 1 == 1 => self.secondary_oam[(self.dot / 2) as usize] = 0xff,
+            65 => self.evaluation = SpriteEvaluation::default(),
+            66..=256 if self.dot & 1 == 0 => self.evaluate_sprite(),
+            257 => {
+                self.extra_sprites.clear();
+                if !self.sprite_limit {
+                    let in_range = (0..64u8).filter(|&n| self.sprite_in_range(self.oam[n as usize * 4]));
+                    let extra_sprites = in_range.skip(8).collect();
+                    self.extra_sprites = extra_sprites;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn evaluate_sprite(&mut self) {
+        let eval = self.evaluation;
+        if eval.done {
+            return;
+        }
+
+        let val = self.oam[eval.n as usize * 4 + eval.m as usize];
+        if eval.count < 8 {
+            self.secondary_oam[eval.count as usize * 4 + eval.m as usize] = val;
+            if eval.m == 0 && !self.sprite_in_range(val) {
+                self.next_sprite();
+            } else if eval.m == 3 {
+                self.evaluation.sprite_zero |= eval.n == 0;
+                self.evaluation.count += 1;
+                self.evaluation.m = 0;
+                self.next_sprite();
+            } else {
+                self.evaluation.m += 1;
+            }
+        } else if self.sprite_in_range(val) {
+            self.status |= STATUS_SPRITE_OVERFLOW;
+            self.evaluation.done = true;
+        } else {
+            // The hardware bug behind false overflows and missed ones: with
+            // eight sprites found, the byte index moves on along with the
+            // sprite index, so tile numbers, attributes and X coordinates
+            // get compared as if they were Y coordinates.
+            self.evaluation.m = (eval.m + 1) & 0b11;
+            self.next_sprite();
+        }
+    }
+
+    fn next_sprite(&mut self) {
+        self.evaluation.n += 1;
+        if self.evaluation.n == 64 {
+            self.evaluation.done = true;
+        }
+    }
+
+    /// The fetches of dots 257-320: two unused nametable reads and a
+    /// pattern row for each of the eight slots of secondary OAM. Empty
+    /// slots fetch tile $FF.
+    fn fetch_sprite(&mut self, cartridge: &mut Cartridge) {
+        let slot = ((self.dot - 257) / 8) as usize;
+        match (self.dot - 257) % 8 {
+            0 => {
+                if slot == 0 {
+                    self.sprites.clear();
+                }
+                self.read_memory(0x2000 | (self.v & 0x0fff), cartridge);
+            }
+            2 => {
+                self.read_memory(0x2000 | (self.v & 0x0fff), cartridge);
+            }
+            4 => {
+                let mut sprite = [0xff; 4];
+                if slot < self.evaluation.count as usize {
+                    sprite.copy_from_slice(&self.secondary_oam[slot * 4..][..4]);
+                }
+                let addr = self.sprite_pattern_addr(sprite);
+                let low = self.read_memory(addr, cartridge);
+                let high = self.read_memory(addr + 8, cartridge);
+                if slot < self.evaluation.count as usize {
+                    let zero = slot == 0 && self.evaluation.sprite_zero;
+                    self.sprites.push(Sprite::new(sprite, low, high, zero));
+                }
+
+                // Sprites past the limit are fetched without touching the
+                // bus, so the cartridge sees what it would on hardware.
+                if slot == 7 {
+                    for i in 0..self.extra_sprites.len() {
+                        let mut sprite = [0; 4];
+                        sprite.copy_from_slice(&self.oam[self.extra_sprites[i] as usize * 4..][..4]);
+                        let addr = self.sprite_pattern_addr(sprite);
+                        let low = cartridge.ppu_peek(addr);
+                        let high = cartridge.ppu_peek(addr + 8);
+                        self.sprites.push(Sprite::new(sprite, low, high, false));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The pattern row of `sprite`, four bytes as in OAM, on the next
+    /// scanline.
+    fn sprite_pattern_addr(&self, sprite: [u8; 4]) -> u16 {
+        let [y, tile, attribute, _] = sprite;
+        let height = self.sprite_height();
+        let mut row = self.scanline.wrapping_sub(y as u16) & (height - 1);
+        if attribute & SPRITE_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+
+        let tile = tile as u16;
+        if height == 16 {
+            // 8x16 sprites take their table from bit 0 of the tile number.
+            let table = (tile & 1) << 12;
+            let tile = (tile & !1) + (row >= 8) as u16;
+            table | tile << 4 | (row & 0b111)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+            table | tile << 4 | row
+        }
+    }
+
+    fn output_pixel(&mut self) {
+        let x = self.dot - 1;
+        let color = if self.rendering_enabled() {
+            let (background, palette) = self.background_pixel(x);
+            let sprite = self.sprite_pixel(x);
+            if let Some(sprite) = &sprite {
+                // The hit needs both pixels opaque, whatever the priority,
+                // and never happens at X=255.
+                if sprite.zero && background != 0 && x != 255 {
+                    self.status |= STATUS_SPRITE_ZERO_HIT;
+                }
+            }
+            let palette_addr = match sprite {
+                // The first opaque sprite wins even when it is behind the
+                // background and a later one is in front.
+                Some(sprite) if background == 0 || sprite.attribute & SPRITE_BEHIND_BACKGROUND == 0 => {
+                    0x10 | (sprite.attribute & 0b11) << 2 | sprite.pixel
+                }
+                _ if background == 0 => 0,
+                _ => palette << 2 | background,
+            };
+            self.read_palette(0x3f00 | palette_addr as u16)
+        } else if self.v & 0x3f00 == 0x3f00 {
+            // With rendering off and `v` in the palette, the color it
+            // points at is shown instead of the backdrop.
+            self.read_palette(self.v)
+        } else {
+            self.read_palette(0x3f00)
+        };
+        self.frame[self.scanline as usize * WIDTH + x as usize] = color;
+    }
+
+    /// The background pixel at `x` and its palette.
+    fn background_pixel(&self, x: u16) -> (u8, u8) {
+        if self.mask & MASK_BACKGROUND == 0 || (x < 8 && self.mask & MASK_BACKGROUND_LEFT == 0) {
+            return (0, 0);
+        }
+        let bit = 15 - self.x;
+        let pixel = ((self.pattern_high >> bit) & 1) << 1 | ((self.pattern_low >> bit) & 1);
+        let palette = ((self.attribute_high >> bit) & 1) << 1 | ((self.attribute_low >> bit) & 1);
+        (pixel as u8, palette as u8)
+    }
+
+    /// The first opaque sprite pixel at `x`.
+    fn sprite_pixel(&self, x: u16) -> Option<SpritePixel> {
+        if self.mask & MASK_SPRITES == 0 || (x < 8 && self.mask & MASK_SPRITES_LEFT == 0) {
+            return None;
+        }
+        self.sprites.iter().find_map(|sprite| {
+            let offset = x.checked_sub(sprite.x as u16).filter(|&offset| offset < 8)?;
+            let bit = 7 - offset;
+            let pixel = ((sprite.high >> bit) & 1) << 1 | ((sprite.low >> bit) & 1);
+            (pixel != 0).then_some(SpritePixel { pixel, attribute: sprite.attribute, zero: sprite.zero })
+        })
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new()
+    }
+}
+
+/// Where nametable `addr` lives in the console's 2KB of nametable RAM.
+fn vram_index(addr: u16, cartridge: &Cartridge) -> usize {
+    (cartridge.nametable_page(addr) & 1) * 0x400 + (addr & 0x03ff) as usize
+}
+
+/// $3F10, $3F14, $3F18 and $3F1C are the same bytes as $3F00, $3F04, $3F08
+/// and $3F0C.
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1f) as usize;
+    if index & 0x13 == 0x10 {
+        index & !0x10
+    } else {
+        index
+    }
+}
+
+/// RGB colors for the PPU's palette indices on an NTSC television.
+pub const NTSC_PALETTE: [[u8; 3]; 64] = [
+    [0x66, 0x66, 0x66],
+    [0x00, 0x2a, 0x88],
+    [0x14, 0x12, 0xa7],
+    [0x3b, 0x00, 0xa4],
+    [0x5c, 0x00, 0x7e],
+    [0x6e, 0x00, 0x40],
+    [0x6c, 0x06, 0x00],
+    [0x56, 0x1d, 0x00],
+    [0x33, 0x35, 0x00],
+    [0x0b, 0x48, 0x00],
+    [0x00, 0x52, 0x00],
+    [0x00, 0x4f, 0x08],
+    [0x00, 0x40, 0x4d],
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+    [0xad, 0xad, 0xad],
+    [0x15, 0x5f, 0xd9],
+    [0x42, 0x40, 0xff],
+    [0x75, 0x27, 0xfe],
+    [0xa0, 0x1a, 0xcc],
+    [0xb7, 0x1e, 0x7b],
+    [0xb5, 0x31, 0x20],
+    [0x99, 0x4e, 0x00],
+    [0x6b, 0x6d, 0x00],
+    [0x38, 0x87, 0x00],
+    [0x0c, 0x93, 0x00],
+    [0x00, 0x8f, 0x32],
+    [0x00, 0x7c, 0x8d],
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+    [0xff, 0xfe, 0xff],
+    [0x64, 0xb0, 0xff],
+    [0x92, 0x90, 0xff],
+    [0xc6, 0x76, 0xff],
+    [0xf3, 0x6a, 0xff],
+    [0xfe, 0x6e, 0xcc],
+    [0xfe, 0x81, 0x70],
+    [0xea, 0x9e, 0x22],
+    [0xbc, 0xbe, 0x00],
+    [0x88, 0xd8, 0x00],
+    [0x5c, 0xe4, 0x30],
+    [0x45, 0xe0, 0x82],
+    [0x48, 0xcd, 0xde],
+    [0x4f, 0x4f, 0x4f],
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+    [0xff, 0xfe, 0xff],
+    [0xc0, 0xdf, 0xff],
+    [0xd3, 0xd2, 0xff],
+    [0xe8, 0xc8, 0xff],
+    [0xfb, 0xc2, 0xff],
+    [0xfe, 0xc4, 0xea],
+    [0xfe, 0xcc, 0xc5],
+    [0xf7, 0xd8, 0xa5],
+    [0xe4, 0xe5, 0x94],
+    [0xcf, 0xef, 0x96],
+    [0xbd, 0xf4, 0xab],
+    [0xb3, 0xf3, 0xcc],
+    [0xb5, 0xeb, 0xf2],
+    [0xb8, 0xb8, 0xb8],
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+];
@@ -0,0 +1,528 @@
+use super::*;
+use crate::cartridge::Rom;
+
+/// A cartridge for mapper `mapper` with 16KB of PRG ROM, CHR-RAM and
+/// the mirroring bits of `flags6`.
+fn cartridge_with(mapper: u8, flags6: u8) -> Cartridge {
+    let mut image = vec![0; 16 + 0x4000];
+    image[..4].copy_from_slice(b"NES\x1a");
+    image[4] = 1;
+    image[6] = (mapper << 4) | flags6;
+    Cartridge::from_rom(Rom::from_bytes(&image).unwrap()).unwrap()
+}
+
+/// An NROM cartridge with CHR-RAM and vertical mirroring.
+fn cartridge() -> Cartridge {
+    cartridge_with(0, 0b00000001)
+}
+
+/// Points PPUADDR at `addr` and writes `data` through PPUDATA.
+fn write_memory(ppu: &mut Ppu, cartridge: &mut Cartridge, addr: u16, data: &[u8]) {
+    ppu.write_register((addr >> 8) as u8, 0x2006, cartridge);
+    ppu.write_register(addr as u8, 0x2006, cartridge);
+    for &val in data {
+        ppu.write_register(val, 0x2007, cartridge);
+    }
+}
+
+fn run_frame(ppu: &mut Ppu, cartridge: &mut Cartridge) {
+    let frame = ppu.frame_count();
+    while ppu.frame_count() == frame {
+        ppu.tick(cartridge);
+    }
+}
+
+#[test]
+fn scroll_writes_fill_t_and_fine_x() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    ppu.write_register(0b00000010, 0x2000, &mut cartridge);
+    ppu.write_register(0b01111101, 0x2005, &mut cartridge);
+    assert_eq!((ppu.t, ppu.x, ppu.w), (0x080f, 0b101, true));
+    ppu.write_register(0b01011110, 0x2005, &mut cartridge);
+    assert_eq!((ppu.t, ppu.w), (0x696f, false));
+    // Scrolling leaves `v` alone until rendering copies it.
+    assert_eq!(ppu.v, 0);
+}
+
+#[test]
+fn second_ppuaddr_write_copies_t_into_v() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    ppu.write_register(0xff, 0x2006, &mut cartridge);
+    assert_eq!((ppu.t, ppu.v), (0x3f00, 0));
+    ppu.write_register(0x42, 0x2006, &mut cartridge);
+    assert_eq!((ppu.t, ppu.v, ppu.w), (0x3f42, 0x3f42, false));
+
+    // Reading PPUSTATUS resets the toggle.
+    ppu.write_register(0x21, 0x2006, &mut cartridge);
+    ppu.read_register(0x2002, &mut cartridge);
+    ppu.write_register(0x23, 0x2006, &mut cartridge);
+    assert_eq!(ppu.t & 0x3f00, 0x2300);
+}
+
+#[test]
+fn ppudata_reads_are_buffered_below_the_palette() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    write_memory(&mut ppu, &mut cartridge, 0x2400, &[0x11, 0x22]);
+    write_memory(&mut ppu, &mut cartridge, 0x3f00, &[0x0f, 0x30]);
+
+    // Vertical mirroring puts $2C00 on the same page as $2400.
+    write_memory(&mut ppu, &mut cartridge, 0x2c00, &[]);
+    assert_eq!(ppu.read_register(0x2007, &mut cartridge), 0);
+    assert_eq!(ppu.read_register(0x2007, &mut cartridge), 0x11);
+    assert_eq!(ppu.read_register(0x2007, &mut cartridge), 0x22);
+
+    write_memory(&mut ppu, &mut cartridge, 0x3f01, &[]);
+    assert_eq!(ppu.read_register(0x2007, &mut cartridge), 0x30);
+}
+
+#[test]
+fn ppudata_increments_by_32_when_asked() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    ppu.write_register(CTRL_INCREMENT_32, 0x2000, &mut cartridge);
+    write_memory(&mut ppu, &mut cartridge, 0x2000, &[1, 2]);
+    assert_eq!(ppu.v, 0x2040);
+    assert_eq!(ppu.peek_memory(0x2020, &cartridge), 2);
+}
+
+#[test]
+fn sprite_backdrops_mirror_the_background_ones() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    write_memory(
+        &mut ppu,
+        &mut cartridge,
+        0x3f10,
+        &[0x01, 0x02, 0x03, 0x04, 0x05],
+    );
+    assert_eq!(ppu.peek_memory(0x3f00, &cartridge), 0x01);
+    assert_eq!(ppu.peek_memory(0x3f04, &cartridge), 0x05);
+    assert_eq!(ppu.peek_memory(0x3f11, &cartridge), 0x02);
+    assert_eq!(ppu.peek_memory(0x3f01, &cartridge), 0);
+    // The palette repeats every 32 bytes up to $3FFF.
+    assert_eq!(ppu.peek_memory(0x3fe0, &cartridge), 0x01);
+}
+
+#[test]
+fn vblank_starts_at_scanline_241_and_ends_on_status_reads() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    while (ppu.scanline(), ppu.dot()) != (VBLANK_SCANLINE, 1) {
+        ppu.tick(&mut cartridge);
+    }
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+    ppu.tick(&mut cartridge);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+
+    assert_ne!(ppu.read_register(0x2002, &mut cartridge) & STATUS_VBLANK, 0);
+    assert_eq!(ppu.read_register(0x2002, &mut cartridge) & STATUS_VBLANK, 0);
+}
+
+#[test]
+fn vblank_ends_on_the_prerender_scanline() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    while (ppu.scanline(), ppu.dot()) != (PRERENDER_SCANLINE, 2) {
+        ppu.tick(&mut cartridge);
+    }
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+}
+
+#[test]
+fn renders_background_tiles_and_sprites() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    // Tile 1 is solid color 1 and tile 2 solid color 2.
+    write_memory(&mut ppu, &mut cartridge, 0x0010, &[0xff; 8]);
+    write_memory(&mut ppu, &mut cartridge, 0x0028, &[0xff; 8]);
+    write_memory(&mut ppu, &mut cartridge, 0x2000, &[1]);
+    write_memory(&mut ppu, &mut cartridge, 0x3f00, &[0x0f, 0x16]);
+    write_memory(&mut ppu, &mut cartridge, 0x3f12, &[0x2a]);
+    for val in [9, 2, 0, 20] {
+        ppu.write_register(val, 0x2004, &mut cartridge);
+    }
+    ppu.write_register(0, 0x2000, &mut cartridge);
+    ppu.write_register(0, 0x2005, &mut cartridge);
+    ppu.write_register(0, 0x2005, &mut cartridge);
+    ppu.write_register(
+        MASK_BACKGROUND | MASK_SPRITES | MASK_BACKGROUND_LEFT | MASK_SPRITES_LEFT,
+        0x2001,
+        &mut cartridge,
+    );
+
+    // The first frame starts without a pre-render scanline to set up `v`.
+    run_frame(&mut ppu, &mut cartridge);
+    run_frame(&mut ppu, &mut cartridge);
+
+    let frame = ppu.frame();
+    assert_eq!(
+        frame[..9],
+        [0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x0f]
+    );
+    assert_eq!(frame[8 * WIDTH], 0x0f);
+    // Sprites show up a scanline below their Y coordinate.
+    assert_eq!(frame[9 * WIDTH + 20], 0x0f);
+    assert_eq!(
+        frame[10 * WIDTH + 19..][..10],
+        [0x0f, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x0f]
+    );
+    assert_eq!(frame[17 * WIDTH + 20], 0x2a);
+    assert_eq!(frame[18 * WIDTH + 20], 0x0f);
+}
+
+#[test]
+fn left_column_masks_hide_the_first_eight_pixels() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    write_memory(&mut ppu, &mut cartridge, 0x0010, &[0xff; 8]);
+    write_memory(&mut ppu, &mut cartridge, 0x2000, &[1, 1]);
+    write_memory(&mut ppu, &mut cartridge, 0x3f00, &[0x0f, 0x16]);
+    ppu.write_register(0, 0x2000, &mut cartridge);
+    ppu.write_register(0, 0x2005, &mut cartridge);
+    ppu.write_register(0, 0x2005, &mut cartridge);
+    ppu.write_register(MASK_BACKGROUND, 0x2001, &mut cartridge);
+
+    run_frame(&mut ppu, &mut cartridge);
+    run_frame(&mut ppu, &mut cartridge);
+
+    assert_eq!(ppu.frame()[7], 0x0f);
+    assert_eq!(ppu.frame()[8], 0x16);
+}
+
+fn run_to(ppu: &mut Ppu, cartridge: &mut Cartridge, scanline: u16, dot: u16) {
+    while (ppu.scanline(), ppu.dot()) != (scanline, dot) {
+        ppu.tick(cartridge);
+    }
+}
+
+/// Sets up solid tiles 1 and 2, a screen of tile 1 from column `first_column`
+/// on, and sprites from `oam`, then turns rendering on.
+fn sprite_scene(first_column: usize, oam: &[[u8; 4]]) -> (Ppu, Cartridge) {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    write_memory(&mut ppu, &mut cartridge, 0x0010, &[0xff; 8]);
+    write_memory(&mut ppu, &mut cartridge, 0x0028, &[0xff; 8]);
+    let mut nametable = [1; 960];
+    for row in nametable.chunks_mut(32) {
+        row[..first_column].fill(0);
+    }
+    write_memory(&mut ppu, &mut cartridge, 0x2000, &nametable);
+    write_memory(&mut ppu, &mut cartridge, 0x3f00, &[0x0f, 0x16]);
+    write_memory(&mut ppu, &mut cartridge, 0x3f11, &[0x21, 0x2a]);
+    // Park every sprite below the screen, then place the ones asked for.
+    ppu.write_register(0, 0x2003, &mut cartridge);
+    for _ in 0..256 {
+        ppu.write_register(0xff, 0x2004, &mut cartridge);
+    }
+    ppu.write_register(0, 0x2003, &mut cartridge);
+    for sprite in oam {
+        for &val in sprite {
+            ppu.write_register(val, 0x2004, &mut cartridge);
+        }
+    }
+    ppu.write_register(0, 0x2000, &mut cartridge);
+    ppu.write_register(0, 0x2005, &mut cartridge);
+    ppu.write_register(0, 0x2005, &mut cartridge);
+    ppu.write_register(0b00011110, 0x2001, &mut cartridge);
+    run_frame(&mut ppu, &mut cartridge);
+    (ppu, cartridge)
+}
+
+#[test]
+fn sprite_zero_hit_needs_an_opaque_background_pixel() {
+    // Sprite 0 starts over transparent background at X=4 and reaches tile 1
+    // at X=8.
+    let (mut ppu, mut cartridge) = sprite_scene(1, &[[19, 2, 0, 4]]);
+    run_to(&mut ppu, &mut cartridge, 20, 8);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    run_to(&mut ppu, &mut cartridge, 20, 12);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+
+    // It stays set until the pre-render scanline.
+    run_to(&mut ppu, &mut cartridge, PRERENDER_SCANLINE, 1);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    ppu.tick(&mut cartridge);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+}
+
+#[test]
+fn sprite_zero_hit_ignores_other_sprites_and_priority() {
+    // Sprite 1 over the background does nothing; sprite 0 hits from behind.
+    let (mut ppu, mut cartridge) = sprite_scene(0, &[[100, 2, SPRITE_BEHIND_BACKGROUND, 50], [19, 2, 0, 50]]);
+    run_to(&mut ppu, &mut cartridge, 100, 0);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    run_to(&mut ppu, &mut cartridge, 102, 0);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    // The background drew over it.
+    assert_eq!(ppu.frame()[101 * WIDTH + 50], 0x16);
+}
+
+#[test]
+fn lower_oam_indices_win_overlapping_pixels() {
+    // Sprite 0 is behind the background and sprite 1 in front, but sprite
+    // 0's pixel decides, so the background shows.
+    let (mut ppu, mut cartridge) = sprite_scene(0, &[[29, 2, SPRITE_BEHIND_BACKGROUND, 40], [29, 1, 0, 40]]);
+    run_frame(&mut ppu, &mut cartridge);
+    assert_eq!(ppu.frame()[30 * WIDTH + 40], 0x16);
+
+    let (mut ppu, mut cartridge) = sprite_scene(0, &[[29, 2, 0, 40], [29, 1, 0, 40]]);
+    run_frame(&mut ppu, &mut cartridge);
+    assert_eq!(ppu.frame()[30 * WIDTH + 40], 0x2a);
+}
+
+#[test]
+fn ninth_sprite_on_a_scanline_sets_overflow_and_is_not_drawn() {
+    let sprites: Vec<_> = (0..9).map(|i| [49, 2, 0, i * 10]).collect();
+    let (mut ppu, mut cartridge) = sprite_scene(32, &sprites[..8]);
+    run_frame(&mut ppu, &mut cartridge);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_OVERFLOW, 0);
+
+    let (mut ppu, mut cartridge) = sprite_scene(32, &sprites);
+    run_to(&mut ppu, &mut cartridge, 49, 0);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_OVERFLOW, 0);
+    run_to(&mut ppu, &mut cartridge, 50, 0);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_OVERFLOW, 0);
+    run_to(&mut ppu, &mut cartridge, 51, 0);
+    assert_eq!(ppu.frame()[50 * WIDTH + 70], 0x2a);
+    assert_eq!(ppu.frame()[50 * WIDTH + 80], 0x0f);
+}
+
+#[test]
+fn sprite_limit_can_be_turned_off() {
+    let sprites: Vec<_> = (0..10).map(|i| [49, 2, 0, i * 10]).collect();
+    let (mut ppu, mut cartridge) = sprite_scene(32, &sprites);
+    ppu.set_sprite_limit(false);
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 0);
+    assert_eq!(ppu.frame()[50 * WIDTH + 80], 0x2a);
+    assert_eq!(ppu.frame()[50 * WIDTH + 90], 0x2a);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_OVERFLOW, 0);
+}
+
+#[test]
+fn sprites_past_the_limit_on_the_last_scanline_do_not_reach_the_next_frame() {
+    let sprites: Vec<_> = (0..10).map(|i| [238, 2, 0, i * 10]).collect();
+    let (mut ppu, mut cartridge) = sprite_scene(32, &sprites);
+    ppu.set_sprite_limit(false);
+    run_frame(&mut ppu, &mut cartridge);
+    run_to(&mut ppu, &mut cartridge, 1, 0);
+    assert_eq!(ppu.frame()[239 * WIDTH + 90], 0x2a);
+    assert!(ppu.frame()[..WIDTH].iter().all(|&color| color == 0x0f));
+}
+
+#[test]
+fn overflow_checks_the_wrong_bytes_after_eight_sprites() {
+    // Sprite 8 is out of range, so the PPU moves to sprite 9 but reads its
+    // tile number as Y, and 49 is in range.
+    let mut sprites: Vec<_> = (0..8).map(|i| [49, 2, 0, i * 10]).collect();
+    sprites.push([200, 0, 0, 0]);
+    sprites.push([200, 49, 0, 0]);
+    let (mut ppu, mut cartridge) = sprite_scene(32, &sprites);
+    run_to(&mut ppu, &mut cartridge, 50, 0);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_OVERFLOW, 0);
+
+    // A real ninth sprite whose Y the same misreading skips over goes
+    // unnoticed.
+    let mut sprites: Vec<_> = (0..8).map(|i| [49, 2, 0, i * 10]).collect();
+    sprites.push([200, 0, 0, 0]);
+    sprites.push([49, 200, 0, 0]);
+    let (mut ppu, mut cartridge) = sprite_scene(32, &sprites);
+    run_to(&mut ppu, &mut cartridge, 50, 0);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_OVERFLOW, 0);
+}
+
+#[test]
+fn oamdata_reads_ff_while_secondary_oam_is_cleared() {
+    let (mut ppu, mut cartridge) = sprite_scene(0, &[[7, 7, 3, 7]]);
+    run_to(&mut ppu, &mut cartridge, 10, 30);
+    assert_eq!(ppu.peek_register(0x2004), 0xff);
+    run_to(&mut ppu, &mut cartridge, 10, 65);
+    assert_eq!(ppu.peek_register(0x2004), 7);
+}
+
+/// Writes 1-4 to $2000, $2400, $2800 and $2C00 in turn, then reads them
+/// back: tables sharing a page show the last value written to it.
+fn nametable_contents(ppu: &mut Ppu, cartridge: &mut Cartridge) -> [u8; 4] {
+    for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2c00].into_iter().enumerate() {
+        write_memory(ppu, cartridge, addr, &[i as u8 + 1]);
+    }
+    [0x2000, 0x2400, 0x2800, 0x2c00].map(|addr| ppu.peek_memory(addr, cartridge))
+}
+
+#[test]
+fn header_picks_the_nametable_mirroring() {
+    let mut ppu = Ppu::new();
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge_with(0, 0b0000)), [2, 2, 4, 4]);
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge_with(0, 0b0001)), [3, 4, 3, 4]);
+}
+
+#[test]
+fn four_screen_boards_have_a_page_per_nametable() {
+    let mut ppu = Ppu::new();
+    let mut cartridge = cartridge_with(0, 0b1000);
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge), [1, 2, 3, 4]);
+    // The mirror at $3000-$3EFF reaches the board's VRAM too.
+    assert_eq!(ppu.peek_memory(0x3c00, &cartridge), 4);
+    write_memory(&mut ppu, &mut cartridge, 0x3800, &[0x55]);
+    assert_eq!(ppu.peek_memory(0x2800, &cartridge), 0x55);
+}
+
+fn set_mmc1_control(cartridge: &mut Cartridge, val: u8) {
+    for i in 0..5 {
+        // MMC1 ignores writes on consecutive cycles.
+        cartridge.cpu_tick();
+        cartridge.cpu_tick();
+        cartridge.cpu_write((val >> i) & 1, 0x8000);
+    }
+}
+
+#[test]
+fn mmc1_switches_mirroring_at_runtime() {
+    let mut ppu = Ppu::new();
+    let mut cartridge = cartridge_with(1, 0);
+    set_mmc1_control(&mut cartridge, 0b01110);
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge), [3, 4, 3, 4]);
+    set_mmc1_control(&mut cartridge, 0b01111);
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge), [2, 2, 4, 4]);
+
+    // The two single-screen modes show different pages.
+    set_mmc1_control(&mut cartridge, 0b01100);
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge), [4, 4, 4, 4]);
+    set_mmc1_control(&mut cartridge, 0b01101);
+    write_memory(&mut ppu, &mut cartridge, 0x2000, &[9]);
+    assert_eq!(ppu.peek_memory(0x2c00, &cartridge), 9);
+    set_mmc1_control(&mut cartridge, 0b01100);
+    assert_eq!(ppu.peek_memory(0x2400, &cartridge), 4);
+}
+
+#[test]
+fn axrom_picks_the_single_screen_page_at_runtime() {
+    let mut ppu = Ppu::new();
+    let mut cartridge = cartridge_with(7, 0);
+    cartridge.cpu_write(0b00000000, 0x8000);
+    assert_eq!(nametable_contents(&mut ppu, &mut cartridge), [4, 4, 4, 4]);
+    cartridge.cpu_write(0b00010000, 0x8000);
+    assert_eq!(ppu.peek_memory(0x2400, &cartridge), 0);
+    write_memory(&mut ppu, &mut cartridge, 0x2c00, &[5]);
+    cartridge.cpu_write(0b00000000, 0x8000);
+    assert_eq!(ppu.peek_memory(0x2000, &cartridge), 4);
+}
+
+fn dots_in_frame(ppu: &mut Ppu, cartridge: &mut Cartridge) -> u64 {
+    let start = ppu.dots;
+    run_frame(ppu, cartridge);
+    ppu.dots - start
+}
+
+#[test]
+fn odd_frames_skip_a_dot_when_rendering() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    assert_eq!(dots_in_frame(&mut ppu, &mut cartridge), 341 * 262);
+    assert_eq!(dots_in_frame(&mut ppu, &mut cartridge), 341 * 262);
+
+    ppu.write_register(MASK_BACKGROUND, 0x2001, &mut cartridge);
+    assert_eq!(dots_in_frame(&mut ppu, &mut cartridge), 341 * 262);
+    assert_eq!(dots_in_frame(&mut ppu, &mut cartridge), 341 * 262 - 1);
+}
+
+#[test]
+fn nmi_follows_vblank_and_ppuctrl() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 2);
+    assert!(ppu.nmi());
+
+    // Turning NMIs off and on again during vblank raises another.
+    ppu.write_register(0, 0x2000, &mut cartridge);
+    assert!(!ppu.nmi());
+    ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+    assert!(ppu.nmi());
+
+    // Reading the flag lowers it from the next dot on.
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 10);
+    ppu.read_register(0x2002, &mut cartridge);
+    assert!(ppu.nmi());
+    ppu.tick(&mut cartridge);
+    assert!(!ppu.nmi());
+    ppu.write_register(0, 0x2000, &mut cartridge);
+    ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+    assert!(!ppu.nmi());
+}
+
+#[test]
+fn reading_status_just_before_vblank_suppresses_it() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 1);
+    assert_eq!(ppu.read_register(0x2002, &mut cartridge) & STATUS_VBLANK, 0);
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE + 1, 0);
+    assert_eq!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+    assert!(!ppu.nmi());
+
+    // Only for that frame.
+    run_frame(&mut ppu, &mut cartridge);
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 2);
+    assert_ne!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+    assert!(ppu.nmi());
+}
+
+#[test]
+fn reading_status_as_vblank_starts_suppresses_nmi() {
+    let mut cartridge = cartridge();
+    // The flag goes up between dots 1 and 2.
+    for dot in [2, 3] {
+        let mut ppu = Ppu::new();
+        ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+        run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, dot);
+        assert_ne!(ppu.read_register(0x2002, &mut cartridge) & STATUS_VBLANK, 0, "dot {dot}");
+        assert!(!ppu.nmi(), "dot {dot}");
+
+        // Not even toggling NMIs in PPUCTRL brings it back that frame.
+        run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE + 1, 0);
+        ppu.write_register(0, 0x2000, &mut cartridge);
+        ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+        assert!(!ppu.nmi(), "dot {dot}");
+
+        run_frame(&mut ppu, &mut cartridge);
+        run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 2);
+        assert!(ppu.nmi(), "dot {dot}");
+    }
+
+    // A dot later the read is too late: NMI is already up and only drops
+    // on the next dot.
+    let mut ppu = Ppu::new();
+    ppu.write_register(CTRL_NMI, 0x2000, &mut cartridge);
+    run_to(&mut ppu, &mut cartridge, VBLANK_SCANLINE, 4);
+    assert_ne!(ppu.read_register(0x2002, &mut cartridge) & STATUS_VBLANK, 0);
+    assert!(ppu.nmi());
+    ppu.tick(&mut cartridge);
+    assert!(!ppu.nmi());
+}
+
+#[test]
+fn io_latch_decays_unless_driven() {
+    let mut cartridge = cartridge();
+    let mut ppu = Ppu::new();
+    ppu.write_register(0xff, 0x2000, &mut cartridge);
+    assert_eq!(ppu.read_register(0x2001, &mut cartridge), 0xff);
+
+    for _ in 0..IO_LATCH_DECAY_DOTS / 2 {
+        ppu.tick(&mut cartridge);
+    }
+    // Reading PPUSTATUS drives only its top three bits.
+    let status = ppu.read_register(0x2002, &mut cartridge) & 0xe0;
+    for _ in 0..IO_LATCH_DECAY_DOTS / 2 {
+        ppu.tick(&mut cartridge);
+    }
+    assert_eq!(ppu.read_register(0x2005, &mut cartridge), status);
+
+    for _ in 0..IO_LATCH_DECAY_DOTS {
+        ppu.tick(&mut cartridge);
+    }
+    assert_eq!(ppu.read_register(0x2005, &mut cartridge), 0);
+}
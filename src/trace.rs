@@ -0,0 +1,222 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::{AddressingMode, Cpu, OPCODES};
+
+fn peek_word<B: Bus + ?Sized>(cpu: &Cpu<B>, addr: u16) -> u16 {
+    cpu.peek(addr) as u16 | (cpu.peek(addr.wrapping_add(1)) as u16) << 8
+}
+
+/// Reads a pointer the way the 6502 does: the high byte never leaves the
+/// page of the low byte.
+fn peek_word_in_page<B: Bus + ?Sized>(cpu: &Cpu<B>, addr: u16) -> u16 {
+    let hi_addr = (addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff);
+    cpu.peek(addr) as u16 | (cpu.peek(hi_addr) as u16) << 8
+}
+
+/// Disassembles the instruction at `pc` in Nintendulator's notation,
+/// including the memory contents the instruction is about to touch.
+pub fn disassemble<B: Bus + ?Sized>(cpu: &Cpu<B>, pc: u16) -> String {
+    let opcode = &OPCODES[cpu.peek(pc) as usize];
+    let (name, mode) = (opcode.mnemonic, opcode.mode);
+    let b1 = cpu.peek(pc.wrapping_add(1));
+    let w1 = peek_word(cpu, pc.wrapping_add(1));
+
+    match mode {
+        AddressingMode::Implied => name.to_string(),
+        AddressingMode::Accumulator => format!("{name} A"),
+        AddressingMode::Immediate => format!("{name} #${b1:02X}"),
+        AddressingMode::ZeroPage => format!("{name} ${b1:02X} = {:02X}", cpu.peek(b1 as u16)),
+        AddressingMode::ZeroPageX => {
+            let addr = b1.wrapping_add(cpu.x());
+            format!("{name} ${b1:02X},X @ {addr:02X} = {:02X}", cpu.peek(addr as u16))
+        }
+        AddressingMode::ZeroPageY => {
+            let addr = b1.wrapping_add(cpu.y());
+            format!("{name} ${b1:02X},Y @ {addr:02X} = {:02X}", cpu.peek(addr as u16))
+        }
+        AddressingMode::Absolute if name == "JMP" || name == "JSR" => format!("{name} ${w1:04X}"),
+        AddressingMode::Absolute => format!("{name} ${w1:04X} = {:02X}", cpu.peek(w1)),
+        AddressingMode::AbsoluteX => {
+            let addr = w1.wrapping_add(cpu.x() as u16);
+            format!("{name} ${w1:04X},X @ {addr:04X} = {:02X}", cpu.peek(addr))
+        }
+        AddressingMode::AbsoluteY => {
+            let addr = w1.wrapping_add(cpu.y() as u16);
+            format!("{name} ${w1:04X},Y @ {addr:04X} = {:02X}", cpu.peek(addr))
+        }
+        AddressingMode::Indirect => {
+            format!("{name} (${w1:04X}) = {:04X}", peek_word_in_page(cpu, w1))
+        }
+        AddressingMode::IndirectX => {
+            let ptr = b1.wrapping_add(cpu.x());
+            let addr = peek_word_in_page(cpu, ptr as u16);
+            format!("{name} (${b1:02X},X) @ {ptr:02X} = {addr:04X} = {:02X}", cpu.peek(addr))
+        }
+        AddressingMode::IndirectY => {
+            let base = peek_word_in_page(cpu, b1 as u16);
+            let addr = base.wrapping_add(cpu.y() as u16);
+            format!("{name} (${b1:02X}),Y = {base:04X} @ {addr:04X} = {:02X}", cpu.peek(addr))
+        }
+        AddressingMode::Relative => {
+            let target = pc.wrapping_add(2).wrapping_add(b1 as i8 as u16);
+            format!("{name} ${target:04X}")
+        }
+    }
+}
+
+/// Scanline and dot a PPU would have reached after `cycles` CPU cycles,
+/// running three dots per CPU cycle from power-up, for buses without one.
+fn ppu_position(cycles: u64) -> (u16, u16) {
+    let dots = cycles * 3;
+    (((dots / 341) % 262) as u16, (dots % 341) as u16)
+}
+
+/// One line of a nestest.log style trace, describing the CPU state right
+/// before an instruction executes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceLine {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub official: bool,
+    pub disassembly: String,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    /// PPU scanline and dot.
+    pub ppu: (u16, u16),
+    pub cycles: u64,
+}
+
+impl TraceLine {
+    /// Captures the state of `cpu` before it executes its next instruction.
+    pub fn capture<B: Bus + ?Sized>(cpu: &Cpu<B>) -> TraceLine {
+        let pc = cpu.pc();
+        let opcode = &OPCODES[cpu.peek(pc) as usize];
+        let bytes = (0..opcode.len)
+            .map(|i| cpu.peek(pc.wrapping_add(i)))
+            .collect();
+
+        TraceLine {
+            pc,
+            bytes,
+            official: opcode.official,
+            disassembly: disassemble(cpu, pc),
+            a: cpu.a(),
+            x: cpu.x(),
+            y: cpu.y(),
+            p: cpu.p(),
+            sp: cpu.sp(),
+            ppu: cpu.bus().ppu_position().unwrap_or_else(|| ppu_position(cpu.cycles())),
+            cycles: cpu.cycles(),
+        }
+    }
+
+    /// Parses a line of Nintendulator's nestest.log.
+    pub fn parse(line: &str) -> Option<TraceLine> {
+        let line = line.trim_end();
+        if line.len() < 73 || !line.is_char_boundary(48) {
+            return None;
+        }
+
+        let hex8 = |field: &str| u8::from_str_radix(field, 16).ok();
+        let register = |name: &str| {
+            let start = line.find(name)? + name.len();
+            hex8(line.get(start..start + 2)?)
+        };
+
+        let pc = u16::from_str_radix(line.get(0..4)?, 16).ok()?;
+        let bytes = line
+            .get(6..14)?
+            .split_whitespace()
+            .map(hex8)
+            .collect::<Option<Vec<u8>>>()?;
+
+        let ppu_start = line.find("PPU:")? + 4;
+        let cyc_start = line.find("CYC:")?;
+        let (scanline, dot) = line.get(ppu_start..cyc_start)?.split_once(',')?;
+
+        Some(TraceLine {
+            pc,
+            bytes,
+            official: line.get(15..16)? != "*",
+            disassembly: line.get(16..48)?.trim_end().to_string(),
+            a: register("A:")?,
+            x: register("X:")?,
+            y: register("Y:")?,
+            p: register("P:")?,
+            sp: register("SP:")?,
+            ppu: (scanline.trim().parse().ok()?, dot.trim().parse().ok()?),
+            cycles: line.get(cyc_start + 4..)?.parse().ok()?,
+        })
+    }
+
+    /// Lists every field that differs from `expected`, one per line.
+    pub fn diff(&self, expected: &TraceLine) -> Vec<String> {
+        let mut diffs = Vec::new();
+        let mut check = |name: &str, actual: String, expected: String| {
+            if actual != expected {
+                diffs.push(format!("{name:>5}: expected {expected}, got {actual}"));
+            }
+        };
+
+        check("PC", format!("{:04X}", self.pc), format!("{:04X}", expected.pc));
+        check("bytes", hex_bytes(&self.bytes), hex_bytes(&expected.bytes));
+        check("A", format!("{:02X}", self.a), format!("{:02X}", expected.a));
+        check("X", format!("{:02X}", self.x), format!("{:02X}", expected.x));
+        check("Y", format!("{:02X}", self.y), format!("{:02X}", expected.y));
+        check("P", format_p(self.p), format_p(expected.p));
+        check("SP", format!("{:02X}", self.sp), format!("{:02X}", expected.sp));
+        check("PPU", format!("{:?}", self.ppu), format!("{:?}", expected.ppu));
+        check("CYC", self.cycles.to_string(), expected.cycles.to_string());
+
+        diffs
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats the status register along with its flags, e.g. `24 (nv-bdIzc)`.
+fn format_p(p: u8) -> String {
+    let flags: String = "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| {
+            if p & (0x80 >> i) != 0 {
+                flag
+            } else {
+                flag.to_ascii_lowercase()
+            }
+        })
+        .collect();
+    format!("{p:02X} ({flags})")
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            self.pc,
+            hex_bytes(&self.bytes),
+            if self.official { ' ' } else { '*' },
+            self.disassembly,
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.sp,
+            self.ppu.0,
+            self.ppu.1,
+            self.cycles,
+        )
+    }
+}
@@ -0,0 +1,62 @@
+use nes_emulator::cartridge::Cartridge;
+use nes_emulator::cpu::Cpu;
+
+/// Emulated time a ROM gets to report a result, about ten seconds.
+const CYCLE_BUDGET: u64 = 18_000_000;
+
+/// Reads the status blargg's test ROMs keep in PRG-RAM: $6000 holds $80
+/// while the test runs and the result code once it is done, $6001-$6003
+/// hold a signature marking the status as valid and $6004 starts a
+/// NUL-terminated message.
+fn blargg_status(cpu: &Cpu) -> Option<(u8, String)> {
+    let signature = [cpu.peek(0x6001), cpu.peek(0x6002), cpu.peek(0x6003)];
+    let status = cpu.peek(0x6000);
+    if signature != [0xde, 0xb0, 0x61] || status >= 0x80 {
+        return None;
+    }
+
+    let message = (0x6004..0x7000)
+        .map(|addr| cpu.peek(addr))
+        .take_while(|&b| b != 0)
+        .map(|b| b as char)
+        .collect();
+    Some((status, message))
+}
+
+/// The text on the first nametable, for the older test ROMs that only
+/// report on screen. They print with tiles numbered as ASCII.
+fn screen_text(cpu: &Cpu) -> String {
+    let bus = cpu.bus();
+    (0..30 * 32)
+        .map(|i| bus.ppu().peek_memory(0x2000 + i, bus.cartridge()))
+        .collect::<Vec<_>>()
+        .chunks(32)
+        .map(|row| row.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { ' ' }).collect::<String>())
+        .map(|row| row.trim_end().to_string())
+        .filter(|row| !row.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs one of blargg's test ROMs and panics with its message unless it
+/// reports success.
+pub fn run_blargg_test(path: &str) {
+    let cartridge = Cartridge::load(path).unwrap_or_else(|e| panic!("{path}: {e}"));
+    let mut cpu = Cpu::new(cartridge);
+
+    let mut elapsed = 0;
+    while elapsed < CYCLE_BUDGET {
+        elapsed += cpu.run_for_cycles(10_000).unwrap_or_else(|e| panic!("{path}: {e}"));
+        if let Some((status, message)) = blargg_status(&cpu) {
+            assert_eq!(status, 0, "{path} failed:\n{message}");
+            return;
+        }
+
+        let screen = screen_text(&cpu);
+        if screen.contains("Passed") {
+            return;
+        }
+        assert!(!screen.contains("Failed"), "{path} failed:\n{screen}");
+    }
+    panic!("{path} did not finish within {CYCLE_BUDGET} cycles");
+}
@@ -0,0 +1,13 @@
+mod common;
+
+/// cpu_dummy_reads.nes checks the dummy reads of indexed addressing and
+/// read-modify-write instructions by watching their side effects on the PPU
+/// status register.
+///
+/// The ROM is checked in at the repo root. It predates the $6000 status
+/// protocol and only prints its result, so the harness reads it off the
+/// screen.
+#[test]
+fn cpu_dummy_reads() {
+    common::run_blargg_test("cpu_dummy_reads.nes");
+}
@@ -0,0 +1,44 @@
+use std::fs;
+
+use nes_emulator::cartridge::Cartridge;
+use nes_emulator::cpu::Cpu;
+use nes_emulator::trace::TraceLine;
+
+/// Runs nestest.nes in automation mode (PC = $C000) and compares every
+/// executed instruction against nestest-log.txt, reporting the first line
+/// that differs. Also checks that every cycle of every instruction shows up
+/// as a bus access.
+///
+/// Both files are checked in at the repo root, which is where cargo runs
+/// integration tests from, so this runs with a plain `cargo test`.
+#[test]
+fn nestest_matches_golden_log() {
+    let log = fs::read_to_string("nestest-log.txt").expect("nestest-log.txt should be in the repo root");
+
+    let mut cpu = Cpu::builder(Cartridge::load("nestest.nes").unwrap()).start_pc(0xc000).build();
+
+    for (i, line) in log.lines().enumerate() {
+        let expected = TraceLine::parse(line)
+            .unwrap_or_else(|| panic!("nestest-log.txt:{}: malformed line {line:?}", i + 1));
+        let actual = TraceLine::capture(&cpu);
+
+        let diffs = actual.diff(&expected);
+        if !diffs.is_empty() {
+            panic!(
+                "nestest-log.txt:{} differs\n expected: {}\n   actual: {}\n{}",
+                i + 1,
+                line,
+                actual,
+                diffs.join("\n")
+            );
+        }
+
+        let step = cpu.step().unwrap_or_else(|e| panic!("nestest-log.txt:{}: {e}", i + 1));
+        assert_eq!(
+            step.accesses.len() as u64,
+            step.cycles,
+            "nestest-log.txt:{}: every cycle should access the bus",
+            i + 1
+        );
+    }
+}